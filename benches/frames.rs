@@ -0,0 +1,53 @@
+// Emulated frames per second of a headless run.
+// The ROM defaults to nestest and can be changed with NESPERA_BENCH_ROM.
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use nespera::cartridge::Cartridge;
+use nespera::console::Console;
+
+const DEFAULT_ROM: &str = "tests/resources/cpu/nestest.nes";
+const WARMUP_FRAMES: u32 = 60;
+const FRAMES: u32 = 600;
+const SAMPLES: usize = 5;
+
+// NTSC CPU clock.
+const CYCLES: u64 = 1_789_773;
+
+fn console(rom: &str) -> Console {
+    let cartridge = Cartridge::from_file(rom).unwrap();
+    let mut console = Console::new(cartridge);
+    console.run_frames(WARMUP_FRAMES);
+    console
+}
+
+// Best of a few samples, so a busy machine doesn't hide an improvement.
+fn measure(rom: &str, run: impl Fn(&mut Console)) -> Duration {
+    (0..SAMPLES)
+        .map(|_| {
+            let mut console = console(rom);
+            let start = Instant::now();
+            run(&mut console);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn per_second(amount: f64, elapsed: Duration) -> f64 {
+    amount / (elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9)
+}
+
+fn main() {
+    let rom = env::var("NESPERA_BENCH_ROM").unwrap_or_else(|_| DEFAULT_ROM.to_string());
+    println!("{}", rom);
+
+    let elapsed = measure(&rom, |console| console.run_frames(FRAMES));
+    println!("run_frames: {} frames in {:?}, {:.1} emulated fps",
+             FRAMES, elapsed, per_second(f64::from(FRAMES), elapsed));
+
+    let elapsed = measure(&rom, |console| console.run_cycles(CYCLES));
+    println!("run_cycles: {} cycles in {:?}, {:.2} emulated seconds per second",
+             CYCLES, elapsed, per_second(1.0, elapsed));
+}
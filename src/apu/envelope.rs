@@ -0,0 +1,115 @@
+use crate::utils::bits;
+
+// Volume generator shared by the pulse and noise channels.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    // Restart the decay when the length register is written.
+    pub start: bool,
+
+    // Decay restarts after reaching zero. Shares the bit with the length counter halt flag.
+    pub looping: bool,
+
+    // Output the volume directly instead of the decay level.
+    pub constant: bool,
+
+    // Constant volume or the period of the divider.
+    pub volume: u8,
+
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self { Default::default() }
+
+    // Write to $4000, $4004 or $400C.
+    pub fn write(&mut self, data: u8) {
+        self.looping = bits::is_set(data, 5);
+        self.constant = bits::is_set(data, 4);
+        self.volume = data & 0b0000_1111;
+    }
+
+    // Clocked on quarter frames.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 0x0f;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1
+            } else if self.looping {
+                self.decay = 0x0f
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    // Volume from 0 to 15.
+    pub fn output(&self) -> u8 {
+        if self.constant { self.volume } else { self.decay }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(data: u8) -> Envelope {
+        let mut envelope = Envelope::new();
+        envelope.write(data);
+        envelope.start = true;
+        envelope.clock();
+        envelope
+    }
+
+    #[test]
+    fn decays_from_15() {
+        // Divider period 0 decreases the level on every clock.
+        let mut envelope = started(0b0000_0000);
+        assert_eq!(envelope.output(), 15);
+
+        for level in (0..15).rev() {
+            envelope.clock();
+            assert_eq!(envelope.output(), level);
+        }
+    }
+
+    #[test]
+    fn one_shot_stays_silent() {
+        let mut envelope = started(0b0000_0000);
+        for _ in 0..20 { envelope.clock() }
+        assert_eq!(envelope.output(), 0);
+    }
+
+    #[test]
+    fn loop_restarts() {
+        let mut envelope = started(0b0010_0000);
+        for _ in 0..15 { envelope.clock() }
+        assert_eq!(envelope.output(), 0);
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+
+    #[test]
+    fn divider_period() {
+        // Period 2 takes three clocks for each step.
+        let mut envelope = started(0b0000_0010);
+        for _ in 0..2 { envelope.clock() }
+        assert_eq!(envelope.output(), 15);
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+    }
+
+    #[test]
+    fn constant_volume() {
+        let mut envelope = started(0b0001_0111);
+        for _ in 0..20 { envelope.clock() }
+        assert_eq!(envelope.output(), 7);
+    }
+}
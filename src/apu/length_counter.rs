@@ -0,0 +1,89 @@
+// Number of half frames a note lasts, indexed by the top five bits of the length register.
+const LENGTH_TABLE: [u8; 0x20] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Silences a channel after some time unless halted.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+    pub counter: u8,
+
+    // Freezes the counter. Shares the bit with the envelope loop flag.
+    pub halt: bool,
+
+    // Set by $4015. A disabled counter is kept at zero.
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self { Default::default() }
+
+    // Load from the top five bits of the length register.
+    pub fn load(&mut self, data: u8) {
+        if self.enabled { self.counter = LENGTH_TABLE[(data >> 3) as usize] }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled { self.counter = 0 }
+    }
+
+    // Clocked on half frames.
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 { self.counter -= 1 }
+    }
+
+    pub fn is_active(&self) -> bool { self.counter > 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(halt: bool) -> LengthCounter {
+        let mut length = LengthCounter::new();
+        length.set_enabled(true);
+        length.halt = halt;
+
+        // Index 1 is the longest note.
+        length.load(0b0000_1000);
+        length
+    }
+
+    #[test]
+    fn counts_down() {
+        let mut length = loaded(false);
+        assert_eq!(length.counter, 254);
+
+        for _ in 0..253 { length.clock() }
+        assert!(length.is_active());
+
+        length.clock();
+        length.clock();
+        assert_eq!(length.counter, 0);
+        assert!(!length.is_active());
+    }
+
+    #[test]
+    fn halt_freezes() {
+        let mut length = loaded(true);
+        for _ in 0..300 { length.clock() }
+        assert_eq!(length.counter, 254);
+
+        length.halt = false;
+        length.clock();
+        assert_eq!(length.counter, 253);
+    }
+
+    #[test]
+    fn disabled_ignores_load() {
+        let mut length = loaded(false);
+        length.set_enabled(false);
+        assert_eq!(length.counter, 0);
+
+        length.load(0b0000_1000);
+        assert_eq!(length.counter, 0);
+    }
+}
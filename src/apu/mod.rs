@@ -0,0 +1,498 @@
+use std::fmt;
+
+use pretty_hex::PrettyHex;
+
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::apu::sweep::Sweep;
+use crate::ppu::Region;
+use crate::utils::bits;
+
+pub mod envelope;
+pub mod length_counter;
+pub mod sweep;
+
+pub const APU_CAPACITY: usize = 0x0018;
+const STATUS: u16 = 0x0015;
+const FRAME_COUNTER: u16 = 0x0017;
+
+// CPU cycles in which each step of the frame sequencer happens.
+const NTSC_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+// Pulse waves.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    pub sweep: Sweep,
+
+    // Period of the wave. 11 bits.
+    pub timer: u16,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Self { sweep: Sweep::new(ones_complement), ..Default::default() }
+    }
+
+    // Pulse 1 is index 0 and negates with one's complement. Pulse 2 uses two's complement.
+    pub fn channel(index: usize) -> Self { Self::new(index == 0) }
+
+    pub fn output(&self) -> u8 {
+        if self.length.is_active() && !self.sweep.is_muting(self.timer) { self.envelope.output() } else { 0 }
+    }
+}
+
+// Triangle wave. Has no volume control.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Triangle {
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+    pub fn output(&self) -> u8 { if self.length.is_active() { 0x0f } else { 0 } }
+}
+
+// Noise generator.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+}
+
+impl Noise {
+    pub fn output(&self) -> u8 { if self.length.is_active() { self.envelope.output() } else { 0 } }
+}
+
+pub struct Apu {
+    // Last values written to each register.
+    registers: [u8; APU_CAPACITY],
+
+    // Channels
+    pub pulse: [Pulse; 2],
+    pub triangle: Triangle,
+    pub noise: Noise,
+
+    // Frame sequencer
+    pub region: Region,
+    five_step: bool,
+    irq_inhibit: bool,
+    cycle: u32,
+
+    // Interrupt flags reported by $4015. There is no DMC channel yet, so its flag is only ever set by hand.
+    pub frame_irq: bool,
+    pub dmc_irq: bool,
+
+    // Address of a DMC sample byte waiting to be fetched. The CPU halts to read it into the buffer.
+    // Also only requested by hand until there is a DMC channel.
+    pub dmc_dma: Option<u16>,
+    pub dmc_sample: Option<u8>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; APU_CAPACITY],
+            pulse: [Pulse::channel(0), Pulse::channel(1)],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            region: Region::Ntsc,
+            five_step: false,
+            irq_inhibit: false,
+            cycle: 0,
+            frame_irq: false,
+            dmc_irq: false,
+            dmc_dma: None,
+            dmc_sample: None,
+        }
+    }
+
+    // Reset button. Silences every channel.
+    pub fn soft_reset(&mut self) {
+        self.write(STATUS, 0);
+        self.cycle = 0;
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {
+        if addr == STATUS { return self.peek_status(); }
+        unsafe { *self.registers.get_unchecked(addr as usize % APU_CAPACITY) }
+    }
+
+    // Read a register. Only the status has side effects.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if addr == STATUS { return self.read_status(); }
+        self.peek(addr)
+    }
+
+    // Channels with a running length counter in the low bits, then the frame and DMC interrupts.
+    pub fn peek_status(&self) -> u8 {
+        let channels = [self.pulse[0].length, self.pulse[1].length, self.triangle.length, self.noise.length];
+        let status = channels.iter().enumerate()
+            .filter(|(_, length)| length.is_active())
+            .fold(0, |status, (index, _)| bits::set(status, index as u8));
+
+        let status = if self.frame_irq { bits::set(status, 6) } else { status };
+        if self.dmc_irq { bits::set(status, 7) } else { status }
+    }
+
+    // Reading the status acknowledges the frame interrupt but not the DMC one.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    // Write to a register. Address is relative to $4000.
+    pub fn write(&mut self, addr: u16, data: u8) {
+        unsafe { *self.registers.get_unchecked_mut(addr as usize % APU_CAPACITY) = data }
+
+        match addr {
+            0x00 | 0x04 => {
+                let pulse = &mut self.pulse[(addr / 4) as usize];
+                pulse.length.halt = bits::is_set(data, 5);
+                pulse.envelope.write(data);
+            }
+            0x01 | 0x05 => self.pulse[(addr / 4) as usize].sweep.write(data),
+            0x02 | 0x06 => {
+                let pulse = &mut self.pulse[(addr / 4) as usize];
+                pulse.timer = (pulse.timer & 0x0700) | u16::from(data);
+            }
+            0x03 | 0x07 => {
+                let pulse = &mut self.pulse[(addr / 4) as usize];
+                pulse.timer = (pulse.timer & 0x00ff) | (u16::from(data & 0b0000_0111) << 8);
+                pulse.length.load(data);
+                pulse.envelope.start = true;
+            }
+
+            0x08 => self.triangle.length.halt = bits::is_set(data, 7),
+            0x0b => self.triangle.length.load(data),
+
+            0x0c => {
+                self.noise.length.halt = bits::is_set(data, 5);
+                self.noise.envelope.write(data);
+            }
+            0x0f => {
+                self.noise.length.load(data);
+                self.noise.envelope.start = true;
+            }
+
+            STATUS => {
+                self.pulse[0].length.set_enabled(bits::is_set(data, 0));
+                self.pulse[1].length.set_enabled(bits::is_set(data, 1));
+                self.triangle.length.set_enabled(bits::is_set(data, 2));
+                self.noise.length.set_enabled(bits::is_set(data, 3));
+            }
+
+            FRAME_COUNTER => {
+                self.five_step = bits::is_set(data, 7);
+                self.irq_inhibit = bits::is_set(data, 6);
+                if self.irq_inhibit { self.frame_irq = false }
+                self.cycle = 0;
+
+                // Five step mode clocks everything immediately.
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    // Clocked on quarter frames by the frame sequencer.
+    pub fn clock_quarter_frame(&mut self) {
+        self.pulse[0].envelope.clock();
+        self.pulse[1].envelope.clock();
+        self.noise.envelope.clock();
+    }
+
+    // Clocked on half frames by the frame sequencer.
+    pub fn clock_half_frame(&mut self) {
+        for pulse in self.pulse.iter_mut() {
+            pulse.length.clock();
+            pulse.timer = pulse.sweep.clock(pulse.timer);
+        }
+
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    // Run one CPU cycle of the frame sequencer.
+    pub fn step(&mut self) {
+        let steps = match self.region {
+            Region::Ntsc => &NTSC_STEPS,
+            Region::Pal => &PAL_STEPS,
+        };
+
+        self.cycle += 1;
+
+        let last = if self.five_step { steps[4] } else { steps[3] };
+        if self.cycle == steps[0] || self.cycle == steps[1] || self.cycle == steps[2] || self.cycle == last {
+            self.clock_quarter_frame()
+        }
+        if self.cycle == steps[1] || self.cycle == last { self.clock_half_frame() }
+
+        // Only the four step sequence raises the interrupt.
+        if self.cycle == last && !self.five_step && !self.irq_inhibit { self.frame_irq = true }
+        if self.cycle >= last { self.cycle = 0 }
+    }
+
+    // Mix every channel into a sample between zero and one.
+    pub fn sample(&self) -> f32 {
+        let pulse = f32::from(self.pulse[0].output() + self.pulse[1].output());
+        let pulse = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+        let tnd = f32::from(self.triangle.output()) / 8227.0 + f32::from(self.noise.output()) / 12241.0;
+        let tnd = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+        pulse + tnd
+    }
+}
+
+impl fmt::Debug for Apu {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "APU | {:?}", (&self.registers[..]).hex_dump())
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "serde")]
+    use crate::utils::state;
+
+    use super::*;
+
+    fn playing(halt: bool) -> Apu {
+        let mut apu = Apu::new();
+        apu.write(STATUS, 0b0000_1111);
+        apu.write(0x00, if halt { 0b0010_1111 } else { 0b0000_1111 });
+        apu.write(0x08, if halt { 0b1000_0000 } else { 0 });
+        apu.write(0x0c, if halt { 0b0010_1111 } else { 0b0000_1111 });
+
+        // Shortest note: two half frames.
+        for &addr in &[0x03, 0x0b, 0x0f] { apu.write(addr, 0b0001_1000) }
+        apu
+    }
+
+    fn active(apu: &Apu) -> (bool, bool, bool) {
+        (apu.pulse[0].length.is_active(), apu.triangle.length.is_active(), apu.noise.length.is_active())
+    }
+
+    #[test]
+    fn halt_sustains() {
+        let mut apu = playing(true);
+        for _ in 0..10 { apu.clock_half_frame() }
+        assert_eq!(active(&apu), (true, true, true));
+        assert!(apu.sample() > 0.0);
+    }
+
+    #[test]
+    fn no_halt_decays() {
+        let mut apu = playing(false);
+        apu.clock_half_frame();
+        assert_eq!(active(&apu), (true, true, true));
+
+        apu.clock_half_frame();
+        assert_eq!(active(&apu), (false, false, false));
+        assert_eq!(apu.sample(), 0.0);
+    }
+
+    #[test]
+    fn toggle_halt() {
+        let mut apu = playing(true);
+        apu.clock_half_frame();
+        assert_eq!(apu.pulse[0].length.counter, 2);
+
+        apu.write(0x00, 0b0000_1111);
+        apu.clock_half_frame();
+        assert_eq!(apu.pulse[0].length.counter, 1);
+    }
+
+    #[test]
+    fn disabled_channel_is_silent() {
+        let mut apu = playing(true);
+        apu.write(STATUS, 0b0000_0000);
+        assert_eq!(active(&apu), (false, false, false));
+    }
+
+    fn swept(data: u8) -> Apu {
+        let mut apu = Apu::new();
+        apu.write(STATUS, 0b0000_0011);
+        for &(addr, data) in &[(0x01, data), (0x02, 0x00), (0x03, 0b0000_1001), (0x05, data), (0x06, 0x00), (0x07, 0b0000_1001)] {
+            apu.write(addr, data);
+        }
+        apu
+    }
+
+    #[test]
+    fn sweep_negate_per_pulse() {
+        // Enabled, period 0, negate, shift 2.
+        let mut apu = swept(0b1000_1010);
+        assert_eq!((apu.pulse[0].timer, apu.pulse[1].timer), (0x0100, 0x0100));
+
+        apu.clock_half_frame();
+        assert_eq!((apu.pulse[0].timer, apu.pulse[1].timer), (0x00bf, 0x00c0));
+    }
+
+    #[test]
+    fn sweep_negate_targets_differ_by_one() {
+        for shift in 1..8 {
+            for &timer in &[0x0008, 0x0100, 0x0234, 0x07ff] {
+                let data = 0b1000_1000 | shift;
+                let targets = [0, 1].iter().map(|&index| {
+                    let mut pulse = Pulse::channel(index);
+                    pulse.sweep.write(data);
+                    pulse.sweep.target(timer)
+                }).collect::<Vec<_>>();
+
+                assert_eq!(targets[0] + 1, targets[1], "shift: {}, timer: {:03x}", shift, timer);
+            }
+        }
+    }
+
+    #[test]
+    fn sweep_overflow_mutes() {
+        // Disabled, shift 0. Target doubles the period.
+        let mut apu = swept(0b0000_0000);
+        apu.write(0x00, 0b0001_1111);
+        apu.write(0x03, 0b0000_1011);
+        assert_eq!(apu.pulse[0].timer, 0x0300);
+        assert_eq!(apu.pulse[0].output(), 0x0f);
+
+        apu.write(0x03, 0b0000_1100);
+        assert_eq!(apu.pulse[0].output(), 0);
+    }
+
+    #[test]
+    fn envelope_starts_on_length_write() {
+        let mut apu = playing(false);
+        assert_eq!(apu.pulse[0].output(), 0);
+
+        // Timer is out of the muting range.
+        apu.write(0x02, 0xff);
+        apu.clock_quarter_frame();
+        assert_eq!(apu.pulse[0].output(), 15);
+        assert_eq!(apu.noise.output(), 15);
+
+        for _ in 0..16 { apu.clock_quarter_frame() }
+        assert_eq!(apu.noise.output(), 14);
+    }
+
+    #[test]
+    fn frame_sequencer_half_frames() {
+        let mut apu = playing(false);
+        for _ in 0..NTSC_STEPS[1] - 1 { apu.step() }
+        assert_eq!(apu.pulse[0].length.counter, 2);
+
+        apu.step();
+        assert_eq!(apu.pulse[0].length.counter, 1);
+
+        for _ in NTSC_STEPS[1]..NTSC_STEPS[3] { apu.step() }
+        assert_eq!(apu.pulse[0].length.counter, 0);
+    }
+
+    mod status {
+        use super::*;
+
+        #[test]
+        fn length_counters() {
+            let mut apu = Apu::new();
+            apu.write(STATUS, 0b0000_1111);
+            assert_eq!(apu.read(STATUS), 0);
+
+            // Play a note on the second pulse.
+            apu.write(0x04, 0b0000_1111);
+            apu.write(0x07, 0b0001_1000);
+            assert_eq!(apu.read(STATUS), 0b0000_0010);
+
+            // Two half frames later the note is over.
+            apu.clock_half_frame();
+            assert_eq!(apu.read(STATUS), 0b0000_0010);
+            apu.clock_half_frame();
+            assert_eq!(apu.read(STATUS), 0);
+        }
+
+        #[test]
+        fn every_channel() {
+            let apu = playing(false);
+            assert_eq!(apu.peek(STATUS), 0b0000_1101);
+        }
+
+        #[test]
+        fn expires_with_the_sequencer() {
+            let mut apu = playing(false);
+            apu.write(FRAME_COUNTER, 0b0100_0000);
+            assert_eq!(apu.read(STATUS) & 0b0000_0001, 0b0000_0001);
+
+            for _ in 0..NTSC_STEPS[3] { apu.step() }
+            assert_eq!(apu.read(STATUS), 0);
+        }
+
+        #[test]
+        fn frame_irq_cleared_by_read() {
+            let mut apu = Apu::new();
+            for _ in 0..NTSC_STEPS[3] { apu.step() }
+
+            assert_eq!(apu.peek(STATUS), 0b0100_0000);
+            assert_eq!(apu.read(STATUS), 0b0100_0000);
+            assert_eq!(apu.read(STATUS), 0);
+        }
+
+        #[test]
+        fn frame_irq_inhibited() {
+            let mut apu = Apu::new();
+            for _ in 0..NTSC_STEPS[3] { apu.step() }
+            apu.write(FRAME_COUNTER, 0b0100_0000);
+            assert_eq!(apu.read(STATUS), 0);
+
+            for _ in 0..NTSC_STEPS[3] { apu.step() }
+            assert_eq!(apu.read(STATUS), 0);
+
+            // Five step mode never raises it.
+            apu.write(FRAME_COUNTER, 0b1000_0000);
+            for _ in 0..NTSC_STEPS[4] { apu.step() }
+            assert_eq!(apu.read(STATUS), 0);
+        }
+
+        #[test]
+        fn dmc_irq_kept() {
+            let mut apu = Apu::new();
+            apu.dmc_irq = true;
+            apu.frame_irq = true;
+
+            assert_eq!(apu.read(STATUS), 0b1100_0000);
+            assert_eq!(apu.read(STATUS), 0b1000_0000);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn channels_state() {
+        let mut apu = playing(true);
+        apu.write(0x01, 0b1010_1010);
+        apu.clock_quarter_frame();
+
+        let saved = state::save(&apu.pulse[0]);
+        let expected = apu.pulse[0];
+
+        apu.clock_half_frame();
+        apu.write(0x00, 0);
+        state::load(&mut apu.pulse[0], &saved).unwrap();
+        assert_eq!(state::save(&apu.pulse[0]), state::save(&expected));
+        assert_eq!(apu.pulse[0].output(), expected.output());
+
+        let mut noise = Noise::default();
+        state::load(&mut noise, &state::save(&apu.noise)).unwrap();
+        assert_eq!(noise.length.counter, apu.noise.length.counter);
+        assert_eq!(noise.output(), apu.noise.output());
+    }
+}
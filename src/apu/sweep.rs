@@ -0,0 +1,124 @@
+use crate::utils::bits;
+
+// Periodically changes the period of a pulse channel.
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sweep {
+    pub enabled: bool,
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+
+    // Pulse 1 negates with one's complement, so it subtracts one more than pulse 2.
+    ones_complement: bool,
+
+    divider: u8,
+    reload: bool,
+}
+
+impl Sweep {
+    pub fn new(ones_complement: bool) -> Self {
+        Self { ones_complement, ..Default::default() }
+    }
+
+    // Write to $4001 or $4005.
+    pub fn write(&mut self, data: u8) {
+        self.enabled = bits::is_set(data, 7);
+        self.period = (data & 0b0111_0000) >> 4;
+        self.negate = bits::is_set(data, 3);
+        self.shift = data & 0b0000_0111;
+        self.reload = true;
+    }
+
+    // Period the channel would change to.
+    pub fn target(&self, timer: u16) -> u16 {
+        let change = timer >> self.shift;
+
+        if self.negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            timer.saturating_sub(change)
+        } else {
+            timer + change
+        }
+    }
+
+    // The channel is silenced even if the sweep is disabled.
+    pub fn is_muting(&self, timer: u16) -> bool {
+        timer < 8 || self.target(timer) > 0x07ff
+    }
+
+    // Clocked on half frames. Returns the new period of the channel.
+    pub fn clock(&mut self, timer: u16) -> u16 {
+        let mut res = timer;
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.is_muting(timer) {
+            res = self.target(timer);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(ones_complement: bool, data: u8) -> Sweep {
+        let mut res = Sweep::new(ones_complement);
+        res.write(data);
+        res
+    }
+
+    #[test]
+    fn negate_differs_between_pulses() {
+        // Enabled, period 0, negate, shift 1.
+        let first = sweep(true, 0b1000_1001);
+        let second = sweep(false, 0b1000_1001);
+
+        assert_eq!(first.target(0x0100), 0x007f);
+        assert_eq!(second.target(0x0100), 0x0080);
+    }
+
+    #[test]
+    fn add() {
+        let sweep = sweep(true, 0b1000_0010);
+        assert_eq!(sweep.target(0x0100), 0x0140);
+    }
+
+    #[test]
+    fn mute_on_overflow() {
+        let sweep = sweep(false, 0b0000_0000);
+        assert!(sweep.is_muting(0x0400));
+        assert!(!sweep.is_muting(0x03ff));
+    }
+
+    #[test]
+    fn mute_on_low_period() {
+        let sweep = sweep(false, 0b1000_1001);
+        assert!(sweep.is_muting(0x0007));
+        assert!(!sweep.is_muting(0x0008));
+    }
+
+    #[test]
+    fn clock_with_divider() {
+        // Enabled, period 1, shift 1.
+        let mut sweep = sweep(false, 0b1001_0001);
+
+        // Reload first, then the divider counts down to zero before updating again.
+        assert_eq!(sweep.clock(0x0100), 0x0180);
+        assert_eq!(sweep.clock(0x0180), 0x0180);
+        assert_eq!(sweep.clock(0x0180), 0x0240);
+    }
+
+    #[test]
+    fn muted_period_is_kept() {
+        let mut sweep = sweep(false, 0b1000_0001);
+        assert_eq!(sweep.clock(0x0700), 0x0700);
+    }
+}
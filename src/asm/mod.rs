@@ -47,3 +47,62 @@ pub fn sta(operand: Operand) -> Vec<u8> {
         _ => vec![]
     }
 }
+
+// Wraps a program into an NROM image with 32kb of PRG ROM, so $8000 is the start of the ROM.
+// The program is placed at the reset vector, which must be in $8000-$ffff. Other vectors point to it as well.
+pub fn build_test_rom(program: &[u8], reset_vector: u16) -> Vec<u8> {
+    const HEADER: &[u8] = b"NES\x1a\x02\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+    const PRG_ROM_SIZE: usize = 0x8000;
+    const CHR_ROM_SIZE: usize = 0x2000;
+
+    assert!(reset_vector >= 0x8000, "Reset vector outside of PRG ROM: 0x{:04x}", reset_vector);
+    let start = (reset_vector - 0x8000) as usize;
+    let end = start + program.len();
+    assert!(end <= PRG_ROM_SIZE - 6, "Program overlaps the vectors: 0x{:04x}", end);
+
+    let mut prg_rom = vec![0; PRG_ROM_SIZE];
+    prg_rom[start..end].copy_from_slice(program);
+
+    // NMI, reset and IRQ.
+    for vector in prg_rom[PRG_ROM_SIZE - 6..].chunks_mut(2) {
+        vector.copy_from_slice(&[bits::low(reset_vector), bits::high(reset_vector)]);
+    }
+
+    let mut rom = HEADER.to_vec();
+    rom.extend(prg_rom);
+    rom.extend(vec![0; CHR_ROM_SIZE]);
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::bus::Bus;
+    use crate::cartridge::Cartridge;
+    use crate::cpu::Cpu;
+
+    use super::*;
+
+    #[test]
+    fn test_rom_runs() {
+        let program = [lda(Operand::Immediate(0x42)), sta(Operand::Absolute(0x0200)), kil()].concat();
+        let rom = build_test_rom(&program, 0xc000);
+
+        let cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.header().mapper, 0);
+
+        let bus = Rc::new(RefCell::new(Bus::with_cartridge(cartridge)));
+        let mut cpu = Cpu::new(bus.clone());
+        cpu.reset();
+        assert_eq!(cpu.reg.get_pc(), 0xc000);
+
+        cpu.step_instruction();
+        assert_eq!(cpu.reg.get_a(), 0x42);
+        assert_eq!(cpu.reg.get_pc(), 0xc002);
+
+        cpu.step_instruction();
+        assert_eq!(bus.borrow().peek_cpu(0x0200), 0x42);
+    }
+}
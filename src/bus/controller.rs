@@ -0,0 +1,252 @@
+use std::ops::BitOr;
+
+// Buttons of a standard controller. Bits are in the order they are read.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub const A: Buttons = Buttons(0b0000_0001);
+    pub const B: Buttons = Buttons(0b0000_0010);
+    pub const SELECT: Buttons = Buttons(0b0000_0100);
+    pub const START: Buttons = Buttons(0b0000_1000);
+    pub const UP: Buttons = Buttons(0b0001_0000);
+    pub const DOWN: Buttons = Buttons(0b0010_0000);
+    pub const LEFT: Buttons = Buttons(0b0100_0000);
+    pub const RIGHT: Buttons = Buttons(0b1000_0000);
+
+    pub fn empty() -> Self { Buttons(0) }
+    pub fn from_bits(bits: u8) -> Self { Buttons(bits) }
+    pub fn bits(self) -> u8 { self.0 }
+
+    pub fn contains(self, other: Buttons) -> bool { self.0 & other.0 == other.0 }
+    pub fn insert(&mut self, other: Buttons) { self.0 |= other.0 }
+    pub fn remove(&mut self, other: Buttons) { self.0 &= !other.0 }
+}
+
+impl BitOr for Buttons {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self { Buttons(self.0 | other.0) }
+}
+
+// Autofire. Held turbo buttons toggle every that many frames. Zero disables it.
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct TurboConfig {
+    pub a_rate: u32,
+    pub b_rate: u32,
+}
+
+// Standard controller. Buttons are loaded into a shift register while strobe is high.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Controller {
+    pub buttons: Buttons,
+
+    // Turbo A and B. Other buttons are ignored.
+    pub turbo_buttons: Buttons,
+    pub turbo: TurboConfig,
+    turbo_pressed: Buttons,
+    turbo_frame: u32,
+
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self { Default::default() }
+
+    // Buttons seen by the console, including the ones pressed by autofire.
+    pub fn pressed(&self) -> Buttons { self.buttons | self.turbo_pressed }
+
+    // Called once per frame to advance autofire.
+    pub fn next_frame(&mut self) {
+        let frame = self.turbo_frame;
+        let phase = |rate: u32| rate > 0 && (frame / rate).is_multiple_of(2);
+
+        self.turbo_pressed = Buttons::empty();
+        if self.turbo_buttons.contains(Buttons::A) && phase(self.turbo.a_rate) { self.turbo_pressed.insert(Buttons::A) }
+        if self.turbo_buttons.contains(Buttons::B) && phase(self.turbo.b_rate) { self.turbo_pressed.insert(Buttons::B) }
+
+        // Autofire starts pressed whenever a turbo button is held again.
+        self.turbo_frame = if self.turbo_buttons.bits() & 0b11 == 0 { 0 } else { self.turbo_frame + 1 };
+    }
+
+    // Write to $4016. Only the strobe bit matters.
+    pub fn write(&mut self, data: u8) {
+        self.strobe = (data & 1) != 0;
+        if self.strobe { self.shift = self.pressed().bits() }
+    }
+
+    // Next bit to be read. Should have no side effects.
+    pub fn peek(&self) -> u8 {
+        if self.strobe { self.pressed().bits() & 1 } else { self.shift & 1 }
+    }
+
+    // Read the next button. After all eight buttons are read it returns 1.
+    pub fn read(&mut self) -> u8 {
+        let res = self.peek();
+        if !self.strobe { self.shift = (self.shift >> 1) | 0b1000_0000 }
+        res
+    }
+}
+
+// The photodiode keeps reporting light for a while after the beam passes.
+const ZAPPER_LIGHT_DOTS: u32 = 20 * 341;
+
+// Light gun. Senses light when the beam draws a bright color where it's aimed.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Zapper {
+    pub x: usize,
+    pub y: usize,
+    pub trigger: bool,
+    light: u32,
+}
+
+impl Zapper {
+    pub fn new(x: usize, y: usize) -> Self { Self { x, y, trigger: false, light: 0 } }
+
+    // Bright colors are the ones on the two lighter rows of the palette, excluding greys and blacks.
+    pub fn is_bright(color: u8) -> bool { (color & 0x30) >= 0x20 && (color & 0x0f) <= 0x0c }
+
+    // Called by the PPU on every dot with the position of the beam and the color on the screen there.
+    pub fn sense(&mut self, x: usize, y: usize, color: u8) {
+        self.light = self.light.saturating_sub(1);
+        if (x, y) == (self.x, self.y) && Self::is_bright(color) { self.light = ZAPPER_LIGHT_DOTS }
+    }
+
+    pub fn is_light_detected(&self) -> bool { self.light > 0 }
+
+    // Read from $4017. Light sense is active low.
+    pub fn read(&self) -> u8 {
+        let light = if self.is_light_detected() { 0 } else { 0b0000_1000 };
+        let trigger = if self.trigger { 0b0001_0000 } else { 0 };
+        light | trigger
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller) -> Vec<u8> {
+        (0..10).map(|_| controller.read()).collect()
+    }
+
+    #[test]
+    fn buttons() {
+        let mut buttons = Buttons::A | Buttons::START;
+        assert!(buttons.contains(Buttons::A));
+        assert!(!buttons.contains(Buttons::B));
+
+        buttons.insert(Buttons::LEFT);
+        buttons.remove(Buttons::A);
+        assert_eq!(buttons.bits(), 0b0100_1000);
+    }
+
+    #[test]
+    fn serial_read() {
+        let mut controller = Controller::new();
+        controller.buttons = Buttons::A | Buttons::START | Buttons::RIGHT;
+        controller.write(1);
+        controller.write(0);
+
+        assert_eq!(read_all(&mut controller), vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn strobe_high_reads_a() {
+        let mut controller = Controller::new();
+        controller.buttons = Buttons::A;
+        controller.write(1);
+
+        assert_eq!(read_all(&mut controller), vec![1; 10]);
+    }
+
+    mod turbo {
+        use super::*;
+
+        // Value of A and B seen on each frame.
+        fn frames(controller: &mut Controller, count: usize) -> Vec<(u8, u8)> {
+            (0..count).map(|_| {
+                controller.next_frame();
+                controller.write(1);
+                controller.write(0);
+                (controller.read(), controller.read())
+            }).collect()
+        }
+
+        #[test]
+        fn alternates_at_rate() {
+            let mut controller = Controller::new();
+            controller.turbo = TurboConfig { a_rate: 2, b_rate: 1 };
+            controller.turbo_buttons = Buttons::A | Buttons::B;
+
+            assert_eq!(frames(&mut controller, 6), vec![(1, 1), (1, 0), (0, 1), (0, 0), (1, 1), (1, 0)]);
+        }
+
+        #[test]
+        fn disabled_rate() {
+            let mut controller = Controller::new();
+            controller.turbo = TurboConfig { a_rate: 0, b_rate: 3 };
+            controller.turbo_buttons = Buttons::A;
+
+            assert_eq!(frames(&mut controller, 3), vec![(0, 0); 3]);
+        }
+
+        #[test]
+        fn regular_buttons_win() {
+            let mut controller = Controller::new();
+            controller.turbo = TurboConfig { a_rate: 1, b_rate: 1 };
+            controller.turbo_buttons = Buttons::A;
+            controller.buttons = Buttons::A;
+
+            assert_eq!(frames(&mut controller, 3), vec![(1, 0); 3]);
+        }
+
+        #[test]
+        fn restarts_pressed() {
+            let mut controller = Controller::new();
+            controller.turbo = TurboConfig { a_rate: 1, b_rate: 1 };
+            controller.turbo_buttons = Buttons::A;
+            assert_eq!(frames(&mut controller, 2), vec![(1, 0), (0, 0)]);
+
+            controller.turbo_buttons = Buttons::empty();
+            assert_eq!(frames(&mut controller, 1), vec![(0, 0)]);
+
+            controller.turbo_buttons = Buttons::A;
+            assert_eq!(frames(&mut controller, 1), vec![(1, 0)]);
+        }
+    }
+
+    #[test]
+    fn zapper_light() {
+        let mut zapper = Zapper::new(10, 20);
+        zapper.sense(9, 20, 0x30);
+        assert_eq!(zapper.read(), 0b0000_1000);
+
+        zapper.sense(10, 20, 0x30);
+        assert_eq!(zapper.read(), 0b0000_0000);
+
+        for _ in 0..ZAPPER_LIGHT_DOTS { zapper.sense(0, 0, 0x30) }
+        assert_eq!(zapper.read(), 0b0000_1000);
+    }
+
+    #[test]
+    fn zapper_dark() {
+        let mut zapper = Zapper::new(10, 20);
+        zapper.trigger = true;
+        zapper.sense(10, 20, 0x0f);
+        assert_eq!(zapper.read(), 0b0001_1000);
+    }
+
+    #[test]
+    fn buttons_latched_on_strobe() {
+        let mut controller = Controller::new();
+        controller.buttons = Buttons::B;
+        controller.write(1);
+        controller.write(0);
+
+        // Pressing after latching isn't seen until the next strobe.
+        controller.buttons = Buttons::A;
+        assert_eq!(controller.read(), 0);
+        assert_eq!(controller.read(), 1);
+    }
+}
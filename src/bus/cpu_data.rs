@@ -5,6 +5,25 @@ use pretty_hex::*;
 // Memory capacity
 pub const RAM_CAPACITY: usize = 0x0800;
 
+// How the RAM is initialized when the console is turned on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RamInit {
+    Zeroed,
+    Filled(u8),
+
+    // Pseudo random values. The same seed always generates the same RAM.
+    Random(u64),
+}
+
+// SplitMix64. Good enough for RAM entropy and needs no state besides the seed.
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut res = *state;
+    res = (res ^ (res >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    res = (res ^ (res >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    res ^ (res >> 31)
+}
+
 pub struct CpuData {
     ram: [u8; RAM_CAPACITY],
 }
@@ -12,6 +31,24 @@ pub struct CpuData {
 impl CpuData {
     pub fn new() -> Self { Self { ram: [0; RAM_CAPACITY] } }
 
+    pub fn with_init(init: RamInit) -> Self {
+        let mut res = Self::new();
+
+        match init {
+            RamInit::Zeroed => {}
+            RamInit::Filled(data) => res.ram.iter_mut().for_each(|byte| *byte = data),
+            RamInit::Random(seed) => {
+                let mut state = seed;
+                for chunk in res.ram.chunks_mut(8) {
+                    let random = split_mix(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(&random[..chunk.len()]);
+                }
+            }
+        }
+
+        res
+    }
+
     pub fn with_ram(ram: &[u8]) -> Self {
         let mut res = Self::new();
 
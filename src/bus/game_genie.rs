@@ -0,0 +1,105 @@
+// Game Genie cheat codes.
+// The Game Genie sits between the cartridge and the console and replaces bytes read from PRG ROM.
+
+// Letters in order of the nybble they encode.
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum GgError {
+    // Codes have six or eight letters.
+    Length(usize),
+
+    // Not one of the sixteen letters.
+    Letter(char),
+}
+
+// A decoded code. Six letter codes always patch. Eight letter ones only when the ROM has the compare byte.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct GameGenie {
+    pub addr: u16,
+    pub data: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    pub fn decode(code: &str) -> Result<Self, GgError> {
+        let n = code.chars()
+            .map(|letter| {
+                LETTERS.find(letter.to_ascii_uppercase())
+                    .map(|index| index as u8)
+                    .ok_or(GgError::Letter(letter))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if n.len() != 6 && n.len() != 8 { return Err(GgError::Length(n.len())); }
+
+        // The bits of the address and values are scrambled across the letters.
+        let addr = 0x8000
+            | (u16::from(n[3] & 7) << 12)
+            | (u16::from(n[5] & 7) << 8)
+            | (u16::from(n[4] & 8) << 8)
+            | (u16::from(n[2] & 7) << 4)
+            | (u16::from(n[1] & 8) << 4)
+            | u16::from(n[4] & 7)
+            | u16::from(n[3] & 8);
+
+        let data = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+        let res = if n.len() == 6 {
+            Self { addr, data: data | (n[5] & 8), compare: None }
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            Self { addr, data: data | (n[7] & 8), compare: Some(compare) }
+        };
+
+        Ok(res)
+    }
+
+    // Byte seen by the CPU when reading the ROM at an address.
+    pub fn patch(&self, addr: u16, rom: u8) -> u8 {
+        if addr != self.addr { return rom; }
+        match self.compare {
+            Some(compare) if compare != rom => rom,
+            _ => self.data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn six_letters() {
+        // Infinite lives on Super Mario Bros.
+        assert_eq!(GameGenie::decode("SXIOPO"), Ok(GameGenie { addr: 0x91d9, data: 0xad, compare: None }));
+    }
+
+    #[test]
+    fn eight_letters() {
+        assert_eq!(GameGenie::decode("ZEXPYGLA"), Ok(GameGenie { addr: 0x94a7, data: 0x02, compare: Some(0x03) }));
+    }
+
+    #[test]
+    fn lower_case() {
+        assert_eq!(GameGenie::decode("sxiopo"), GameGenie::decode("SXIOPO"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(GameGenie::decode("SXIOP"), Err(GgError::Length(5)));
+        assert_eq!(GameGenie::decode("SXIOPOA"), Err(GgError::Length(7)));
+        assert_eq!(GameGenie::decode("SXIOPB"), Err(GgError::Letter('B')));
+    }
+
+    #[test]
+    fn patch() {
+        let code = GameGenie { addr: 0x91d9, data: 0xad, compare: None };
+        assert_eq!(code.patch(0x91d9, 0x12), 0xad);
+        assert_eq!(code.patch(0x91da, 0x12), 0x12);
+
+        let code = GameGenie { addr: 0x94a7, data: 0x02, compare: Some(0x03) };
+        assert_eq!(code.patch(0x94a7, 0x03), 0x02);
+        assert_eq!(code.patch(0x94a7, 0x04), 0x04);
+    }
+}
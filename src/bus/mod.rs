@@ -2,17 +2,24 @@
 
 use std::fmt;
 
-use pretty_hex::PrettyHex;
-
+use crate::apu::Apu;
+use crate::bus::controller::Controller;
+use crate::bus::controller::Zapper;
 use crate::bus::cpu_data::CpuData;
+use crate::bus::cpu_data::RamInit;
+use crate::bus::game_genie::GameGenie;
 use crate::bus::ppu_data::PpuData;
 use crate::cartridge::Cartridge;
+use crate::cartridge::CartridgeLike;
 use crate::cartridge::location::Location;
 
+pub mod controller;
 pub mod cpu_data;
+pub mod game_genie;
 pub mod ppu_data;
 
-const APU_CAPACITY: usize = 0x0018;
+// Longest string read by read_cpu_string. As big as PRG RAM, where test ROMs write their results.
+pub const MAX_STRING_LENGTH: usize = 0x2000;
 
 // General communication between all parts of the NES
 pub struct Bus {
@@ -24,14 +31,24 @@ pub struct Bus {
     // Data
     pub cpu: CpuData,
     pub ppu: PpuData,
-    pub apu: [u8; APU_CAPACITY],
+    pub apu: Apu,
+    pub controllers: [Controller; 2],
+
+    // Replaces the second controller when connected.
+    pub zapper: Option<Zapper>,
 
     // Cartridge
-    pub cartridge: Cartridge,
+    pub cartridge: Box<dyn CartridgeLike>,
+
+    // Cheats patching reads from PRG ROM.
+    pub game_genie: Vec<GameGenie>,
+
+    // Last value on the CPU data bus. Shows up on bits not driven by the device being read.
+    pub open_bus: u8,
 }
 
 impl Bus {
-    fn create(cpu: CpuData, cartridge: Cartridge) -> Self {
+    fn create(cpu: CpuData, cartridge: Box<dyn CartridgeLike>) -> Self {
         Self {
             reset: true,
             nmi: false,
@@ -40,22 +57,39 @@ impl Bus {
             cpu,
             ppu: PpuData::new(),
 
-            apu: [0; APU_CAPACITY],
+            apu: Apu::new(),
+            controllers: [Controller::new(); 2],
+            zapper: None,
 
             cartridge,
+            game_genie: Vec::new(),
+            open_bus: 0,
         }
     }
 
     pub fn new() -> Self {
-        Self::create(CpuData::new(), Cartridge::empty())
+        Self::create(CpuData::new(), Box::new(Cartridge::empty()))
     }
 
     pub fn with_mem(mem: &[u8]) -> Self {
-        Self::create(CpuData::with_ram(mem), Cartridge::empty())
+        Self::create(CpuData::with_ram(mem), Box::new(Cartridge::empty()))
+    }
+
+    pub fn with_cartridge(cartridge: impl CartridgeLike + 'static) -> Self {
+        Self::create(CpuData::new(), Box::new(cartridge))
+    }
+
+    pub fn with_power_state(cartridge: impl CartridgeLike + 'static, ram_init: RamInit) -> Self {
+        Self::create(CpuData::with_init(ram_init), Box::new(cartridge))
     }
 
-    pub fn with_cartridge(cartridge: Cartridge) -> Self {
-        Self::create(CpuData::new(), cartridge)
+    // Reset button. Keeps the contents of every RAM.
+    pub fn soft_reset(&mut self) {
+        self.nmi = false;
+        self.irq = false;
+
+        self.ppu.soft_reset();
+        self.apu.soft_reset();
     }
 
     // Vblank has started
@@ -74,10 +108,6 @@ impl Bus {
         data
     }
 
-    pub fn get_ppu_and_cartridge(&mut self) -> (&mut PpuData, &Cartridge) {
-        (&mut self.ppu, &self.cartridge)
-    }
-
     // Peek a value from this location. Should have no side effects.
     fn peek(&self, location: Location) -> u8 {
         match location {
@@ -87,13 +117,13 @@ impl Bus {
             }
 
             Location::Apu(addr) => {
-                let data = unsafe { *self.apu.get_unchecked(addr as usize % self.apu.len()) };
+                let data = self.apu.peek(addr);
                 Self::trace_addr_read("APU", addr, data)
             }
 
             Location::CpuRam(addr) => Self::trace_addr_read("CPU RAM", addr, self.cpu.read_ram(addr)),
 
-            Location::PpuData => Self::trace_read("Peek: PPUDATA", self.ppu.peek_data()),
+            Location::PpuData => Self::trace_read("Peek: PPUDATA", self.peek_ppu(self.ppu.data_addr())),
             Location::PpuStatus => Self::trace_read("Peek: PPUSTATUS", self.ppu.peek_status()),
             Location::OamData => Self::trace_read("Peek: OAMDATA", self.ppu.peek_oam_data()),
 
@@ -107,18 +137,48 @@ impl Bus {
                 self.ppu.latch
             }
 
+            Location::Controller(port) => {
+                let data = match self.zapper {
+                    Some(zapper) if port == 1 => zapper.read() | (self.open_bus & 0b1110_0000),
+                    _ => self.controllers[port as usize % 2].peek() | (self.open_bus & 0b1111_1110),
+                };
+                Self::trace_addr_read("Peek: Controller", u16::from(port), data)
+            }
+
+            Location::Vram(addr) => Self::trace_addr_read("VRAM", addr, self.ppu.peek_vram(addr)),
+            Location::Palette(addr) => Self::trace_addr_read("Palette", addr, self.ppu.peek_palette(addr)),
+
             Location::PrgRam(addr) => Self::trace_addr_read("PRG RAM", addr, self.cartridge.read_prg_ram(addr)),
             Location::PrgRom(addr) => Self::trace_addr_read("PRG ROM", addr, self.cartridge.read_prg_rom(addr)),
             Location::ChrRom(addr) => Self::trace_addr_read("CHR ROM", addr, self.cartridge.read_chr_rom(addr)),
+            Location::CartridgeVram(addr) => Self::trace_addr_read("Cartridge VRAM", addr, self.cartridge.read_vram(addr)),
+
+            Location::CartridgeRegister(addr) => {
+                error!("Attempted to read from write only cartridge register. Defaulting to zero. 0x{:04x}", addr);
+                0
+            }
         }
     }
 
     // Read a value from this location.
     fn read(&mut self, location: Location) -> u8 {
         match location {
-            Location::PpuData => Self::trace_read("PPUDATA", self.ppu.read_data()),
+            Location::PpuData => {
+                let addr = self.ppu.data_addr();
+                let data = self.peek_ppu(addr);
+                let underneath = if self.ppu.is_palette() { self.peek_ppu(addr.wrapping_sub(0x1000)) } else { data };
+                Self::trace_read("PPUDATA", self.ppu.read_data(data, underneath))
+            }
             Location::PpuStatus => Self::trace_read("PPUSTATUS", self.ppu.read_status()),
             Location::OamData => Self::trace_read("OAMDATA", self.ppu.read_oam_data()),
+            Location::Apu(addr) => Self::trace_addr_read("APU", addr, self.apu.read(addr)),
+            Location::Controller(port) => {
+                let data = match self.zapper {
+                    Some(zapper) if port == 1 => zapper.read() | (self.open_bus & 0b1110_0000),
+                    _ => self.controllers[port as usize % 2].read() | (self.open_bus & 0b1111_1110),
+                };
+                Self::trace_addr_read("Controller", u16::from(port), data)
+            }
             _ => self.peek(location),
         }
     }
@@ -137,8 +197,7 @@ impl Bus {
             Location::Nowhere(addr) => error!("Attempted to write to nowhere in CPU: 0x{:04x}, 0x{:02x}.", addr, data),
 
             Location::Apu(addr) => {
-                let len = self.apu.len();
-                unsafe { *self.apu.get_unchecked_mut(addr as usize % len) = data }
+                self.apu.write(addr, data);
                 Self::trace_addr_write("APU", addr, data)
             }
 
@@ -183,6 +242,7 @@ impl Bus {
             }
 
             Location::PpuData => {
+                self.write_ppu(self.ppu.data_addr(), data);
                 self.ppu.write_data(data);
                 Self::trace_write("PPUDATA", data);
             }
@@ -192,11 +252,36 @@ impl Bus {
                 Self::trace_write("OAMDMA", data);
             }
 
+            Location::Controller(_) => {
+                for controller in self.controllers.iter_mut() { controller.write(data) }
+                Self::trace_write("Controller strobe", data);
+            }
+
+            Location::Vram(addr) => {
+                Self::trace_addr_write("VRAM", addr, data);
+                self.ppu.poke_vram(addr, data)
+            }
+
+            Location::Palette(addr) => {
+                Self::trace_addr_write("Palette", addr, data);
+                self.ppu.poke_palette(addr, data)
+            }
+
             Location::PrgRam(addr) => {
                 Self::trace_addr_write("PRG RAM", addr, data);
                 self.cartridge.write_prg_ram(addr, data)
             }
 
+            Location::CartridgeVram(addr) => {
+                Self::trace_addr_write("Cartridge VRAM", addr, data);
+                self.cartridge.write_vram(addr, data)
+            }
+
+            Location::CartridgeRegister(addr) => {
+                Self::trace_addr_write("Cartridge register", addr, data);
+                self.cartridge.write_register(addr, data)
+            }
+
             Location::PrgRom(addr) | Location::ChrRom(addr) => {
                 error!("Attempted to write to read only memory in cartridge. {:04x?}, {:#02x}", location, data)
             }
@@ -206,31 +291,54 @@ impl Bus {
     // Peek at an address on the CPU. Performs no side effects.
     pub fn peek_cpu(&self, addr: u16) -> u8 {
         let location = self.cartridge.cpu_read_location(addr);
-        self.peek(location)
+        let data = self.peek(location);
+        self.patch_prg_rom(addr, location, data)
     }
 
     // Read an address on the CPU
     pub fn read_cpu(&mut self, addr: u16) -> u8 {
         let location = self.cartridge.cpu_read_location(addr);
-        self.read(location)
+        let data = self.read(location);
+        self.open_bus = self.patch_prg_rom(addr, location, data);
+        self.open_bus
+    }
+
+    // Apply the Game Genie codes to a byte read by the CPU.
+    fn patch_prg_rom(&self, addr: u16, location: Location, data: u8) -> u8 {
+        match location {
+            Location::PrgRom(_) => self.game_genie.iter().fold(data, |data, code| code.patch(addr, data)),
+            _ => data,
+        }
     }
 
     // Read the address as a zero terminated string. Used mostly for testing.
+    // Wraps around the address space and stops at MAX_STRING_LENGTH if no terminator is found.
     pub fn read_cpu_string(&mut self, addr: u16) -> String {
         let mut res = String::new();
-        let mut addr = addr;
-        loop {
-            let ch = self.read_cpu(addr);
+        for offset in 0..MAX_STRING_LENGTH {
+            let ch = self.read_cpu(addr.wrapping_add(offset as u16));
             if ch == 0 { break; }
             res.push(ch as char);
-            addr += 1;
         }
         res
     }
 
+    // Peek at an address on the PPU. Performs no side effects.
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        let location = self.cartridge.ppu_read_location(addr);
+        self.peek(location)
+    }
+
+    // Write into an address on the PPU
+    pub fn write_ppu(&mut self, addr: u16, data: u8) {
+        let location = self.cartridge.ppu_write_location(addr);
+        self.write(location, data)
+    }
+
     // Write into an address on the CPU
     pub fn write_cpu(&mut self, addr: u16, data: u8) {
         let location = self.cartridge.cpu_write_location(addr);
+        self.open_bus = data;
         self.write(location, data)
     }
 }
@@ -240,7 +348,7 @@ impl fmt::Debug for Bus {
         writeln!(formatter, "{:?}\n", self.cpu)?;
         writeln!(formatter, "{:?}\n", self.ppu)?;
         writeln!(formatter, "{:?}\n", self.cartridge)?;
-        write!(formatter, "APU | {:?}", (&self.apu[..]).hex_dump())
+        write!(formatter, "{:?}", self.apu)
     }
 }
 
@@ -253,3 +361,312 @@ impl fmt::Display for Bus {
 impl Default for Bus {
     fn default() -> Self { Self::new() }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_status_keeps_vblank() {
+        let mut bus = Bus::new();
+        bus.ppu.vblank_set();
+
+        assert_eq!(bus.peek_cpu(0x2002) & 0x80, 0x80);
+        assert!(bus.ppu.is_vblank());
+
+        assert_eq!(bus.read_cpu(0x2002) & 0x80, 0x80);
+        assert!(!bus.ppu.is_vblank());
+    }
+
+    #[test]
+    fn peek_status_keeps_write_toggle() {
+        let mut bus = Bus::new();
+        bus.write_cpu(0x2006, 0x21);
+
+        bus.peek_cpu(0x200a);
+        assert!(bus.ppu.w);
+
+        bus.read_cpu(0x200a);
+        assert!(!bus.ppu.w);
+    }
+
+    #[test]
+    fn peek_data_keeps_address() {
+        let mut bus = Bus::new();
+        bus.write_ppu(0x2001, 0x42);
+        bus.write_cpu(0x2006, 0x20);
+        bus.write_cpu(0x2006, 0x01);
+
+        assert_eq!(bus.peek_cpu(0x2007), 0x42);
+        assert_eq!(bus.ppu.v, 0x2001);
+        assert_eq!(bus.ppu.ram_buffer, 0x00);
+
+        bus.read_cpu(0x2007);
+        assert_eq!(bus.ppu.v, 0x2002);
+        assert_eq!(bus.ppu.ram_buffer, 0x42);
+    }
+
+    #[test]
+    fn peek_oam_data_keeps_address() {
+        let mut bus = Bus::new();
+        bus.write_cpu(0x2003, 0x10);
+        bus.write_cpu(0x2004, 0x33);
+        bus.write_cpu(0x2003, 0x10);
+
+        assert_eq!(bus.peek_cpu(0x2004), 0x33);
+        assert_eq!(bus.ppu.oam_addr, 0x10);
+    }
+
+    #[test]
+    fn peek_write_only_is_open_bus() {
+        let mut bus = Bus::new();
+        bus.write_cpu(0x2000, 0x5a);
+
+        for &addr in &[0x2000, 0x2001, 0x2003, 0x2005, 0x2006, 0x4014] {
+            assert_eq!(bus.peek_cpu(addr), 0x5a, "0x{:04x}", addr);
+        }
+    }
+
+    #[test]
+    fn palette_read_buffers_nametable() {
+        let mut bus = Bus::new();
+        bus.write_ppu(0x2f05, 0x42);
+        bus.write_ppu(0x3f05, 0x15);
+
+        bus.write_cpu(0x2006, 0x3f);
+        bus.write_cpu(0x2006, 0x05);
+        assert_eq!(bus.read_cpu(0x2007) & 0x3f, 0x15);
+        assert_eq!(bus.ppu.ram_buffer, 0x42);
+
+        // The buffer is returned by the next read outside the palette.
+        bus.write_cpu(0x2006, 0x20);
+        bus.write_cpu(0x2006, 0x00);
+        assert_eq!(bus.read_cpu(0x2007), 0x42);
+    }
+
+    #[test]
+    fn apu_status() {
+        let mut bus = Bus::new();
+        bus.write_cpu(0x4015, 0b0000_0001);
+        bus.write_cpu(0x4003, 0b0000_1000);
+        assert_eq!(bus.read_cpu(0x4015), 0b0000_0001);
+
+        bus.apu.clock_half_frame();
+        assert_eq!(bus.read_cpu(0x4015), 0b0000_0001);
+
+        bus.apu.frame_irq = true;
+        assert_eq!(bus.peek_cpu(0x4015), 0b0100_0001);
+        assert_eq!(bus.read_cpu(0x4015), 0b0100_0001);
+        assert_eq!(bus.read_cpu(0x4015), 0b0000_0001);
+    }
+
+    #[test]
+    fn controller_open_bus() {
+        let mut bus = Bus::with_mem(&[0xad, 0x16, 0x40]);
+        bus.controllers[0].buttons = controller::Buttons::A;
+        bus.write_cpu(0x4016, 1);
+        bus.write_cpu(0x4016, 0);
+
+        // LDA $4016. The last byte on the bus is the high byte of the address.
+        bus.read_cpu(0x0000);
+        bus.read_cpu(0x0001);
+        bus.read_cpu(0x0002);
+        assert_eq!(bus.peek_cpu(0x4016), 0x41);
+        assert_eq!(bus.read_cpu(0x4016), 0x41);
+
+        // The read itself is now on the bus.
+        assert_eq!(bus.read_cpu(0x4016), 0x40);
+        assert_eq!(bus.read_cpu(0x4017), 0x40);
+    }
+
+    #[test]
+    fn zapper_open_bus() {
+        let mut bus = Bus::new();
+        bus.zapper = Some(Zapper::new(0, 0));
+        bus.write_cpu(0x0000, 0x40);
+        assert_eq!(bus.read_cpu(0x4017), 0x48);
+    }
+
+    mod read_cpu_string {
+        use super::*;
+
+        // PRG ROM and RAM filled with 'A'.
+        fn bus() -> Bus {
+            let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+            rom.resize(0x10, 0);
+            rom.resize(0x10 + 0x4000, b'A');
+            rom.resize(0x10 + 0x4000 + 0x2000, 0);
+            Bus::with_power_state(Cartridge::new(&rom).unwrap(), RamInit::Filled(b'A'))
+        }
+
+        #[test]
+        fn terminated() {
+            let mut bus = bus();
+            bus.write_cpu(0x0003, 0);
+            assert_eq!(bus.read_cpu_string(0x0000), "AAA");
+        }
+
+        #[test]
+        fn wraps_without_terminator() {
+            // 16 bytes of ROM before $FFFF wraps, then RAM and its mirrors until the bound.
+            let res = bus().read_cpu_string(0xfff0);
+            assert_eq!(res.len(), MAX_STRING_LENGTH);
+            assert!(res.chars().all(|ch| ch == 'A'));
+        }
+
+        #[test]
+        fn bound_counts_reads() {
+            // Bytes above $7f take two bytes in UTF-8 but are still a single read.
+            let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+            rom.resize(0x10 + 0x4000 + 0x2000, 0);
+            let mut bus = Bus::with_power_state(Cartridge::new(&rom).unwrap(), RamInit::Filled(0xe9));
+
+            let res = bus.read_cpu_string(0x0000);
+            assert_eq!(res.chars().count(), MAX_STRING_LENGTH);
+            assert!(res.chars().all(|ch| ch == '\u{e9}'));
+        }
+
+        #[test]
+        fn stops_at_terminator_after_wrapping() {
+            let mut bus = bus();
+            bus.write_cpu(0x0002, 0);
+            assert_eq!(bus.read_cpu_string(0xfffe), "AAAA");
+        }
+    }
+
+    #[test]
+    fn game_genie() {
+        let mut bus = Bus::new();
+        bus.write_cpu(0x0000, 0x12);
+        bus.game_genie.push(GameGenie { addr: 0x91d9, data: 0xad, compare: None });
+        bus.game_genie.push(GameGenie { addr: 0x94a7, data: 0x02, compare: Some(0x03) });
+        bus.game_genie.push(GameGenie { addr: 0x94a8, data: 0x02, compare: Some(0x00) });
+
+        assert_eq!(bus.read_cpu(0x91d9), 0xad);
+        assert_eq!(bus.peek_cpu(0x91d9), 0xad);
+        assert_eq!(bus.read_cpu(0x91da), 0x00);
+
+        // The ROM is zeroed, so only the code comparing with zero applies.
+        assert_eq!(bus.read_cpu(0x94a7), 0x00);
+        assert_eq!(bus.read_cpu(0x94a8), 0x02);
+
+        // Only PRG ROM is patched.
+        assert_eq!(bus.read_cpu(0x0000), 0x12);
+    }
+
+    mod ram_mirror {
+        use super::*;
+
+        const MIRRORS: [u16; 4] = [0x0000, 0x0800, 0x1000, 0x1800];
+
+        #[test]
+        fn write_base_read_mirrors() {
+            let mut bus = Bus::new();
+            bus.write_cpu(0x0000, 0x12);
+            bus.write_cpu(0x07ff, 0x34);
+            bus.write_cpu(0x0123, 0x56);
+
+            for &base in &MIRRORS {
+                assert_eq!(bus.read_cpu(base), 0x12, "0x{:04x}", base);
+                assert_eq!(bus.read_cpu(base + 0x07ff), 0x34, "0x{:04x}", base);
+                assert_eq!(bus.read_cpu(base + 0x0123), 0x56, "0x{:04x}", base);
+            }
+        }
+
+        #[test]
+        fn write_mirror_read_all() {
+            for &mirror in &MIRRORS {
+                let mut bus = Bus::new();
+                bus.write_cpu(mirror + 0x0042, 0x99);
+
+                for &base in &MIRRORS {
+                    assert_eq!(bus.read_cpu(base + 0x0042), 0x99, "0x{:04x} -> 0x{:04x}", mirror, base);
+                }
+            }
+        }
+
+        #[test]
+        fn neighbours_untouched() {
+            let mut bus = Bus::new();
+            bus.write_cpu(0x1800, 0xff);
+
+            assert_eq!(bus.read_cpu(0x0001), 0x00);
+            assert_eq!(bus.read_cpu(0x07ff), 0x00);
+            assert_eq!(bus.read_cpu(0x1fff), 0x00);
+        }
+    }
+
+    mod latch {
+        use crate::bus::ppu_data::LATCH_DECAY_FRAMES;
+
+        use super::*;
+
+        #[test]
+        fn any_write_fills_it() {
+            let mut bus = Bus::new();
+            for &(addr, data) in &[(0x2000, 0x11), (0x2001, 0x22), (0x2003, 0x33), (0x2004, 0x44),
+                (0x2005, 0x55), (0x2006, 0x66), (0x2007, 0x77)] {
+                bus.write_cpu(addr, data);
+                assert_eq!(bus.read_cpu(0x2000), data, "0x{:04x}", addr);
+                assert_eq!(bus.read_cpu(0x2006), data, "0x{:04x}", addr);
+            }
+        }
+
+        #[test]
+        fn oam_dma_doesnt_fill_it() {
+            let mut bus = Bus::new();
+            bus.write_cpu(0x2001, 0x5a);
+            bus.write_cpu(0x4014, 0x02);
+            assert_eq!(bus.read_cpu(0x2005), 0x5a);
+        }
+
+        #[test]
+        fn status_reads_fill_it() {
+            let mut bus = Bus::new();
+            bus.ppu.vblank_set();
+            bus.write_cpu(0x2001, 0x1f);
+
+            assert_eq!(bus.read_cpu(0x2002), 0x9f);
+            assert_eq!(bus.read_cpu(0x2000), 0x9f);
+
+            // Vblank is gone, but the latch keeps the bits last read.
+            assert_eq!(bus.read_cpu(0x2002), 0x1f);
+            assert_eq!(bus.read_cpu(0x2003), 0x1f);
+        }
+
+        #[test]
+        fn oam_data_reads_fill_it() {
+            let mut bus = Bus::new();
+            bus.write_cpu(0x2003, 0x10);
+            bus.write_cpu(0x2004, 0x42);
+            bus.write_cpu(0x2003, 0x10);
+
+            assert_eq!(bus.read_cpu(0x2004), 0x42);
+            assert_eq!(bus.read_cpu(0x2005), 0x42);
+        }
+
+        #[test]
+        fn palette_reads_keep_high_bits() {
+            let mut bus = Bus::new();
+            bus.write_ppu(0x3f01, 0x2a);
+            bus.write_cpu(0x2006, 0x3f);
+            bus.write_cpu(0x2006, 0xc1);
+
+            // Latch now has 0xc1 from the write.
+            assert_eq!(bus.read_cpu(0x2007), 0xea);
+            assert_eq!(bus.read_cpu(0x2001), 0xea);
+        }
+
+        #[test]
+        fn decays() {
+            let mut bus = Bus::new();
+            bus.write_cpu(0x2000, 0x5a);
+
+            for _ in 0..LATCH_DECAY_FRAMES - 1 { bus.ppu.age_latch() }
+            assert_eq!(bus.read_cpu(0x2001), 0x5a);
+
+            bus.ppu.age_latch();
+            assert_eq!(bus.read_cpu(0x2001), 0x00);
+        }
+    }
+}
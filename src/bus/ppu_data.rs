@@ -4,18 +4,24 @@ use std::slice::Chunks;
 use pretty_hex::PrettyHex;
 
 use crate::utils::bits;
-use crate::cartridge::PpuMirror;
+use crate::utils::mem;
 
-const NAMETABLE_BASE: usize = 0x2000;
+const NAMETABLE_BASE: u16 = 0x2000;
 
 // PPU capacity. Ends with the palette.
 pub const PALETTE_CAPACITY: usize = 0x0020;
 pub const PALETTE_START_POS: usize = 0x3f00;
 const PPU_CAPACITY: usize = 0x4000;
-const RAM_CAPACITY: usize = 0x4000;
+
+// The console has RAM for two nametables. Cartridges decide how they are mirrored.
+pub const VRAM_CAPACITY: usize = 0x0800;
 const OAM_CAPACITY: usize = 0x0100;
 
-#[derive(Debug, Copy, Clone)]
+// Frames the PPU bus holds its value. About 600ms.
+pub const LATCH_DECAY_FRAMES: u32 = 36;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SpriteSize { S8, S16 }
 
 // T and V are composed this way during rendering:
@@ -87,6 +93,8 @@ impl VRamAddr {
 }
 
 // Information about the PPU registers decoded from writing to them
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PpuData {
     // PPUCTRL
     pub base_nametable_addr: usize,
@@ -108,6 +116,7 @@ pub struct PpuData {
 
     // PPUSTATUS
     pub status: u8,
+    pub status_read: bool,
 
     // PPUADDR
     pub addr: u8,
@@ -120,8 +129,16 @@ pub struct PpuData {
     pub oam_addr: u8,
     pub oam_source: u16,
 
+    // Set by the PPU while it fetches data for a scanline. OAM can't be written in this state.
+    pub rendering: bool,
+
+    // Set after power or reset. Writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are ignored in this state.
+    pub warming_up: bool,
+
     // Internal PPU bus. Any read or write to its registers should fill it.
+    // Nothing holds the charge, so it fades after a while without being driven.
     pub latch: u8,
+    latch_age: u32,
 
     // Internal registers
     // Current VRAM address. 15 bits.
@@ -138,7 +155,10 @@ pub struct PpuData {
     pub w: bool,
 
     // RAM
-    ram: [u8; RAM_CAPACITY],
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::state::bytes"))]
+    vram: [u8; VRAM_CAPACITY],
+    palette: [u8; PALETTE_CAPACITY],
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::state::bytes"))]
     oam: [u8; OAM_CAPACITY],
     pub ram_buffer: u8,
 }
@@ -147,6 +167,7 @@ impl PpuData {
     pub fn new() -> Self {
         let mut res = Self {
             latch: 0,
+            latch_age: 0,
 
             // PPUCTRL
             base_nametable_addr: 0,
@@ -167,6 +188,7 @@ impl PpuData {
             emphasize_blue: false,
 
             status: 0,
+            status_read: false,
             addr: 0,
             data: 0,
 
@@ -174,12 +196,16 @@ impl PpuData {
             oam_addr: 0,
             oam_source: 0,
 
+            rendering: false,
+            warming_up: false,
+
             v: 0,
             t: 0,
             x: 0,
             w: false,
 
-            ram: [0; RAM_CAPACITY],
+            vram: [0; VRAM_CAPACITY],
+            palette: [0; PALETTE_CAPACITY],
             oam: [0; OAM_CAPACITY],
             ram_buffer: 0,
         };
@@ -189,29 +215,75 @@ impl PpuData {
         res.write_mask(0);
         res.write_scroll(0);
 
+        // Setting the scroll flips the write toggle.
+        res.w = false;
+
         res
     }
 
+    // Reset button. Registers are cleared but RAM and OAM are kept.
+    pub fn soft_reset(&mut self) {
+        self.write_control(0);
+        self.write_mask(0);
+        self.x = 0;
+        self.w = false;
+        self.ram_buffer = 0;
+    }
+
     // Direct RAM and OAM access.
-    pub unsafe fn peek_ram(&self, addr: usize) -> u8 {
-        debug_assert!(addr < self.ram.len(), "PPU RAM out of bounds: {}", addr);
-        *self.ram.get_unchecked(addr)
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        mem::read(&self.vram, addr as usize)
+    }
+
+    // $3f10, $3f14, $3f18 and $3f1c are mirrors of $3f00, $3f04, $3f08 and $3f0c.
+    fn palette_index(addr: u16) -> usize {
+        let addr = addr as usize % PALETTE_CAPACITY;
+        if addr & 0x13 == 0x10 { addr & 0x0f } else { addr }
+    }
+
+    pub fn peek_palette(&self, addr: u16) -> u8 {
+        mem::read(&self.palette, Self::palette_index(addr))
+    }
+
+    // The 32 bytes of palette RAM at $3f00-$3f1f, read the way the PPU reads them.
+    pub fn palette_ram(&self) -> [u8; PALETTE_CAPACITY] {
+        let mut res = [0; PALETTE_CAPACITY];
+        for (addr, data) in res.iter_mut().enumerate() { *data = self.peek_palette(addr as u16) }
+        res
+    }
+
+    // Replace the whole palette RAM. Mirror slots are skipped, so the entries they mirror win.
+    pub fn set_palette_ram(&mut self, palette: [u8; PALETTE_CAPACITY]) {
+        for (addr, &data) in palette.iter().enumerate() {
+            if Self::palette_index(addr as u16) == addr { self.poke_palette(addr as u16, data) }
+        }
     }
 
     pub unsafe fn peek_oam(&self, addr: usize) -> u8 {
         debug_assert!(addr < self.oam.len(), "PPU OAM out of bounds: {}", addr);
+        if cfg!(feature = "safe_mem") { return self.oam.get(addr).copied().unwrap_or(0); }
         *self.oam.get_unchecked(addr)
     }
 
     pub fn oam_chunks(&self, size: usize) -> Chunks<u8> { self.oam.chunks(size) }
 
-    unsafe fn poke_ram(&mut self, addr: usize, data: u8) {
-        debug_assert!(addr < self.ram.len(), "PPU RAM out of bounds: {}", addr);
-        *self.ram.get_unchecked_mut(addr) = data
+    // Copy of the whole OAM. Used by debuggers.
+    pub fn oam_snapshot(&self) -> [u8; OAM_CAPACITY] { self.oam }
+
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        mem::write(&mut self.vram, addr as usize, data)
+    }
+
+    pub fn poke_palette(&mut self, addr: u16, data: u8) {
+        mem::write(&mut self.palette, Self::palette_index(addr), data)
     }
 
     unsafe fn poke_oam(&mut self, addr: usize, data: u8) {
         debug_assert!(addr < self.oam.len(), "PPU OAM out of bounds: {}", addr);
+        if cfg!(feature = "safe_mem") {
+            if let Some(dest) = self.oam.get_mut(addr) { *dest = data }
+            return;
+        }
         *self.oam.get_unchecked_mut(addr) = data
     }
 
@@ -247,21 +319,33 @@ impl PpuData {
         self.v = v.as_u16();
     }
 
-    pub fn fetch_nametable(&self, mirror: PpuMirror) -> u8 {
-        let mut v = VRamAddr::new(self.v);
-        v.fine_y = 0;
+    pub fn sprite_height(&self) -> u16 {
+        match self.sprite_size {
+            SpriteSize::S8 => 8,
+            SpriteSize::S16 => 16,
+        }
+    }
 
-        if mirror == PpuMirror::Vertical {
-            v.vertical_nametable = false
-        } else {
-            v.horizontal_nametable = false
+    // Address of the low plane of a sprite row. The high plane is 8 bytes after it.
+    // 8x16 sprites ignore PPUCTRL and take the pattern table from bit 0 of the tile.
+    pub fn sprite_pattern_addr(&self, tile: u8, row: u16) -> u16 {
+        let tile = u16::from(tile);
+        match self.sprite_size {
+            SpriteSize::S8 => self.sprite_pattern_table + 0x10 * tile + (row & 7),
+            SpriteSize::S16 => {
+                let table = 0x1000 * (tile & 1);
+                let tile = (tile & 0xfe) + ((row >> 3) & 1);
+                table + 0x10 * tile + (row & 7)
+            }
         }
+    }
 
-        let addr = NAMETABLE_BASE | (v.as_u16() as usize);
-        unsafe { self.peek_ram(addr) }
+    // Address of the tile being fetched. The cartridge decides where it's mirrored to.
+    pub fn nametable_addr(&self) -> u16 {
+        NAMETABLE_BASE | (self.v & 0x0fff)
     }
 
-    pub fn fetch_attribute(&self) -> u16 {
+    pub fn attribute_addr(&self) -> u16 {
         // Address of attribute is composed like so:
         // NN 1111 YYY XXX
         // || |||| ||| +++-- high 3 bits of coarse X (x/4)
@@ -269,28 +353,26 @@ impl PpuData {
         // || ++++---------- attribute offset (960 bytes)
         // ++--------------- nametable select
         let v = VRamAddr::new(self.v);
-        let addr = NAMETABLE_BASE
-            | ((v.vertical_nametable as usize) << 12)
-            | ((v.horizontal_nametable as usize) << 11)
+        NAMETABLE_BASE
+            | ((v.vertical_nametable as u16) << 11)
+            | ((v.horizontal_nametable as u16) << 10)
             | 0b00_1111_000_000
-            | ((v.coarse_y as usize >> 2) << 3)
-            | (v.coarse_x as usize >> 2)
-            ;
-        unsafe { self.peek_ram(addr) as u16 }
+            | ((v.coarse_y >> 2) << 3)
+            | (v.coarse_x >> 2)
     }
 
-    // Peek PPUDATA
-    pub fn peek_data(&self) -> u8 { unsafe { self.peek_ram(self.get_addr()) } }
-
-    // Read PPUDATA
-    pub fn read_data(&mut self) -> u8 {
-        let data = self.peek_data();
+    // Address PPUDATA is pointing to.
+    pub fn data_addr(&self) -> u16 { self.get_addr() as u16 }
 
+    // Read PPUDATA. The bus fetches the data at the current address.
+    // Palette reads also fetch the nametable byte underneath the palette, which goes into the buffer.
+    pub fn read_data(&mut self, data: u8, underneath: u8) -> u8 {
         // Palette data is read immediately.
         // Everything else is read into a buffer and the previous contents of the buffer is returned.
         self.latch = if self.is_palette() {
-            self.ram_buffer = data;
-            data
+            // Palette entries have six bits. The rest is open bus.
+            self.ram_buffer = underneath;
+            bits::copy(self.latch, data, 0b0011_1111)
         } else {
             let res = self.ram_buffer;
             self.ram_buffer = data;
@@ -299,13 +381,16 @@ impl PpuData {
 
         self.inc_ram_addr();
 
+        self.latch_age = 0;
         self.latch
     }
 
     // Peek PPUSTATUS
     pub fn peek_status(&self) -> u8 {
-        // PPU status has some unused bits, so fill in from the latch.
-        bits::copy(self.status, self.latch, 0b0001_1111)
+        // Only vblank, sprite 0 hit and sprite overflow are driven. The unused bits come from the latch.
+        let flags = [(7, self.is_vblank()), (6, self.is_sprite_zero_hit()), (5, self.is_sprite_overflow())];
+        let status = flags.iter().filter(|(_, set)| *set).fold(0, |res, &(bit, _)| bits::set(res, bit));
+        bits::copy(status, self.latch, 0b0001_1111)
     }
 
     // Read PPUSTATUS
@@ -313,7 +398,11 @@ impl PpuData {
         // Reading the status resets the write toggle.
         self.w = false;
 
+        // The PPU checks this to emulate the race with vblank being set.
+        self.status_read = true;
+
         self.latch = self.peek_status();
+        self.latch_age = 0;
 
         // Vertical blank is cleared after reading status
         self.vblank_clear();
@@ -326,24 +415,34 @@ impl PpuData {
 
     // Read OAMDATA
     pub fn read_oam_data(&mut self) -> u8 {
+        // Reading doesn't increment the address.
         self.latch = self.peek_oam_data();
-        self.oam_addr = self.oam_addr.wrapping_add(1);
+        self.latch_age = 0;
         self.latch
     }
 
     // Common routine for write operations
-    pub fn write(&mut self, data: u8) { self.latch = data }
+    pub fn write(&mut self, data: u8) {
+        self.latch = data;
+        self.latch_age = 0;
+    }
+
+    // Called once per frame.
+    pub fn age_latch(&mut self) {
+        self.latch_age += 1;
+        if self.latch_age >= LATCH_DECAY_FRAMES { self.latch = 0 }
+    }
 
     // Write PPUCTRL
     pub fn write_control(&mut self, data: u8) {
         self.write(data);
+        if self.warming_up { return; }
 
         self.base_nametable_addr = match data & 0b0000_0011 {
             0 => 0x2000,
             1 => 0x2400,
             2 => 0x2800,
-            3 => 0x2c00,
-            x => unimplemented!(),
+            _ => 0x2c00,
         };
 
         let mut t = VRamAddr::new(self.t);
@@ -358,9 +457,38 @@ impl PpuData {
         self.generate_nmi_at_vblank = bits::is_set(data, 7);
     }
 
+    // PPUCTRL as last written. Bit 6 is unused and always clear.
+    pub fn control(&self) -> u8 {
+        let nametable = ((self.base_nametable_addr - 0x2000) / 0x0400) as u8;
+        let flags = [
+            (2, self.ram_increment == 32),
+            (3, self.sprite_pattern_table == 0x1000),
+            (4, self.background_pattern_table == 0x1000),
+            (5, self.sprite_size == SpriteSize::S16),
+            (7, self.generate_nmi_at_vblank),
+        ];
+        flags.iter().filter(|(_, set)| *set).fold(nametable, |res, &(bit, _)| bits::set(res, bit))
+    }
+
+    // PPUMASK as last written.
+    pub fn mask(&self) -> u8 {
+        let flags = [
+            self.greyscale,
+            self.show_background_in_lef,
+            self.show_sprites_in_leftmost,
+            self.show_background,
+            self.show_sprites,
+            self.emphasize_red,
+            self.emphasize_green,
+            self.emphasize_blue,
+        ];
+        flags.iter().enumerate().filter(|(_, &set)| set).fold(0, |res, (bit, _)| bits::set(res, bit as u8))
+    }
+
     // Write PPUMASK
     pub fn write_mask(&mut self, data: u8) {
         self.write(data);
+        if self.warming_up { return; }
 
         self.greyscale = bits::is_set(data, 0);
         self.show_background_in_lef = bits::is_set(data, 1);
@@ -375,10 +503,11 @@ impl PpuData {
     // Write PPUSCROLL
     pub fn write_scroll(&mut self, data: u8) {
         self.write(data);
+        if self.warming_up { return; }
         let data = u16::from(data);
 
-        let fine = data & 0b000000_0111;
-        let coarse = data & 0b1111_1000 >> 3;
+        let fine = data & 0b0000_0111;
+        let coarse = (data & 0b1111_1000) >> 3;
 
         let mut t = VRamAddr::new(self.t);
 
@@ -397,25 +526,22 @@ impl PpuData {
     // Write PPUADDR
     pub fn write_addr(&mut self, data: u8) {
         self.write(data);
+        if self.warming_up { return; }
 
         if self.w {
             self.t = bits::set_low(self.t, data);
             self.v = self.t;
         } else {
-            // Address has 14 bits at most.
-            self.t = bits::set_high(self.t, data) & 0b0011_1111_1111_1111;
+            // Only the lower six bits are used. Bit 14 is cleared, so the address has 14 bits at most.
+            self.t = bits::set_high(self.t, data & 0b0011_1111);
         }
 
         self.w = !self.w;
     }
 
-    // Write PPUDATA
+    // Write PPUDATA. The bus stores the data at the current address.
     pub fn write_data(&mut self, data: u8) {
         self.write(data);
-
-        let addr = self.get_addr();
-        unsafe { self.poke_ram(addr, data) }
-
         self.inc_ram_addr()
     }
 
@@ -429,20 +555,27 @@ impl PpuData {
     pub fn write_oam_data(&mut self, data: u8) {
         self.write(data);
 
+        // While rendering the write is ignored, but the address is bumped to the next sprite.
+        if self.rendering {
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
+
+        // Bits 2 to 4 of the attribute byte don't exist.
+        let data = if self.oam_addr % 4 == 2 { data & 0b1110_0011 } else { data };
+
         unsafe { self.poke_oam(self.oam_addr as usize, data) }
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
-    // Write OAMDMA
+    // Write OAMDMA. Not a PPU register, so the latch isn't touched.
     pub fn write_oam_dma(&mut self, data: u8) {
-        self.write(data);
-
         self.oam_transfer = true;
         self.oam_source = u16::from(data) << 8;
     }
 
     // Check if an address refers to the palette region of memory
-    fn is_palette(&self) -> bool { self.v as usize >= PALETTE_START_POS }
+    pub fn is_palette(&self) -> bool { self.v as usize >= PALETTE_START_POS }
 
     // Increment the RAM address as specified by PPUCTRL
     fn inc_ram_addr(&mut self) {
@@ -471,11 +604,46 @@ impl PpuData {
         trace!("vblank clear");
         self.status = bits::clear(self.status, 7)
     }
+
+    // Sprite 0 hit flags
+    pub fn sprite_zero_hit_set(&mut self) {
+        trace!("sprite 0 hit set");
+        self.status = bits::set(self.status, 6)
+    }
+
+    pub fn sprite_zero_hit_clear(&mut self) {
+        trace!("sprite 0 hit clear");
+        self.status = bits::clear(self.status, 6)
+    }
+
+    // Sprite overflow flags
+    pub fn sprite_overflow_set(&mut self) {
+        trace!("sprite overflow set");
+        self.status = bits::set(self.status, 5)
+    }
+
+    pub fn sprite_overflow_clear(&mut self) {
+        trace!("sprite overflow clear");
+        self.status = bits::clear(self.status, 5)
+    }
+
+    // Status flags getters
+    pub fn is_vblank(&self) -> bool { bits::is_set(self.status, 7) }
+    pub fn is_sprite_zero_hit(&self) -> bool { bits::is_set(self.status, 6) }
+    pub fn is_sprite_overflow(&self) -> bool { bits::is_set(self.status, 5) }
+
+    // All status flags are cleared at once on the pre-render scanline.
+    pub fn status_clear(&mut self) {
+        self.vblank_clear();
+        self.sprite_zero_hit_clear();
+        self.sprite_overflow_clear();
+    }
 }
 
 impl fmt::Debug for PpuData {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(formatter, "PPU | {:?}\n", (&self.ram[..]).hex_dump())?;
+        writeln!(formatter, "VRAM | {:?}\n", (&self.vram[..]).hex_dump())?;
+        writeln!(formatter, "Palette | {:?}\n", (&self.palette[..]).hex_dump())?;
         write!(formatter, "OAM | {:?}", (&self.oam[..]).hex_dump())
     }
 }
@@ -483,3 +651,339 @@ impl fmt::Debug for PpuData {
 impl Default for PpuData {
     fn default() -> Self { Self::new() }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::Bus;
+
+    use super::*;
+
+    #[test]
+    fn registers_encode() {
+        let mut ppu = PpuData::new();
+        for &data in &[0x00, 0x01, 0x02, 0x03, 0x04, 0x08, 0x10, 0x20, 0x80, 0xbf] {
+            ppu.write_control(data);
+            assert_eq!(ppu.control(), data);
+        }
+        for data in 0..=0xff {
+            ppu.write_mask(data);
+            assert_eq!(ppu.mask(), data);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod state {
+        use crate::utils::state;
+
+        use super::*;
+
+        #[test]
+        fn round_trip() {
+            let mut ppu = PpuData::new();
+            ppu.write_control(0b1010_1101);
+            ppu.write_mask(0b0001_1110);
+            ppu.write_scroll(0x7d);
+            ppu.write_oam_addr(0x20);
+            ppu.write_oam_data(0x99);
+            ppu.poke_vram(0x0123, 0x45);
+            ppu.poke_palette(0x05, 0x2a);
+            ppu.write_addr(0x23);
+            ppu.vblank_set();
+            ppu.ram_buffer = 0x66;
+
+            let saved = state::save(&ppu);
+
+            // Mutate everything.
+            ppu.write_control(0);
+            ppu.write_mask(0);
+            ppu.write_addr(0x01);
+            ppu.write_oam_data(0x11);
+            ppu.poke_vram(0x0123, 0);
+            ppu.poke_palette(0x05, 0);
+            ppu.vblank_clear();
+            ppu.ram_buffer = 0;
+            assert_ne!(state::save(&ppu), saved);
+
+            state::load(&mut ppu, &saved).unwrap();
+            assert_eq!(state::save(&ppu), saved);
+
+            assert_eq!(ppu.base_nametable_addr, 0x2400);
+            assert_eq!(ppu.sprite_size, SpriteSize::S16);
+            assert!(ppu.generate_nmi_at_vblank);
+            assert!(ppu.show_sprites && ppu.show_background);
+            assert!(ppu.is_vblank());
+            assert!(!ppu.w);
+            assert_eq!(ppu.x, 0x7d & 0b111);
+            assert_eq!(ppu.oam_addr, 0x21);
+            assert_eq!(ppu.oam_snapshot()[0x20], 0x99);
+            assert_eq!(ppu.peek_vram(0x0123), 0x45);
+            assert_eq!(ppu.peek_palette(0x05), 0x2a);
+            assert_eq!(ppu.ram_buffer, 0x66);
+        }
+
+        #[test]
+        fn truncated() {
+            let ppu = PpuData::new();
+            let saved = state::save(&ppu);
+
+            let mut other = PpuData::new();
+            other.write_control(0x80);
+            assert!(state::load(&mut other, &saved[..saved.len() - 10]).is_err());
+            assert!(other.generate_nmi_at_vblank);
+        }
+    }
+
+    mod palette_ram {
+        use super::*;
+
+        #[test]
+        fn round_trip() {
+            let mut ppu = PpuData::new();
+            let mut palette = [0; PALETTE_CAPACITY];
+            for (i, color) in palette.iter_mut().enumerate() { *color = i as u8 }
+            for &addr in &[0x00, 0x04, 0x08, 0x0c] { palette[addr + 0x10] = palette[addr] }
+
+            ppu.set_palette_ram(palette);
+            assert_eq!(ppu.palette_ram(), palette);
+            assert_eq!(ppu.peek_palette(0x05), 0x05);
+        }
+
+        #[test]
+        fn mirrors() {
+            let mut ppu = PpuData::new();
+            let mut palette = [0; PALETTE_CAPACITY];
+
+            palette[0x00] = 0x2a;
+            palette[0x10] = 0x0f;
+            ppu.set_palette_ram(palette);
+            assert_eq!(ppu.palette_ram()[0x00], 0x2a);
+            assert_eq!(ppu.palette_ram()[0x10], 0x2a);
+
+            ppu.poke_palette(0x10, 0x27);
+            assert_eq!(ppu.peek_palette(0x00), 0x27);
+
+            ppu.poke_palette(0x00, 0x16);
+            assert_eq!(ppu.palette_ram()[0x10], 0x16);
+            assert_eq!(ppu.peek_palette(0x10), 0x16);
+
+            ppu.poke_palette(0x1c, 0x30);
+            assert_eq!(ppu.peek_palette(0x0c), 0x30);
+        }
+
+        #[test]
+        fn sees_bus_writes() {
+            let mut bus = Bus::new();
+            bus.write_ppu(0x3f05, 0x16);
+            bus.write_ppu(0x3f3f, 0x2a);
+
+            let palette = bus.ppu.palette_ram();
+            assert_eq!(palette[0x05], 0x16);
+            assert_eq!(palette[0x1f], 0x2a);
+        }
+    }
+
+    mod sprite_pattern {
+        use super::*;
+
+        #[test]
+        fn s8_table_from_control() {
+            let mut ppu = PpuData::new();
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 3), 0x0213);
+
+            ppu.write_control(0b0000_1000);
+            assert_eq!(ppu.sprite_height(), 8);
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 3), 0x1213);
+            assert_eq!(ppu.sprite_pattern_addr(0x20, 7), 0x1207);
+        }
+
+        #[test]
+        fn s16_table_from_tile() {
+            let mut ppu = PpuData::new();
+            ppu.write_control(0b0010_1000);
+            assert_eq!(ppu.sprite_height(), 16);
+
+            // PPUCTRL is ignored. Even tiles are on the first table.
+            assert_eq!(ppu.sprite_pattern_addr(0x20, 3), 0x0203);
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 3), 0x1203);
+
+            ppu.write_control(0b0010_0000);
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 3), 0x1203);
+        }
+
+        #[test]
+        fn s16_bottom_half() {
+            let mut ppu = PpuData::new();
+            ppu.write_control(0b0010_0000);
+
+            // The bottom half is the next tile.
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 8), 0x1210);
+            assert_eq!(ppu.sprite_pattern_addr(0x21, 15), 0x1217);
+            assert_eq!(ppu.sprite_pattern_addr(0x20, 9), 0x0211);
+        }
+    }
+
+    #[test]
+    fn write_oam_data_increments() {
+        let mut ppu = PpuData::new();
+        ppu.write_oam_addr(0xff);
+        for &data in &[0x11, 0x22, 0x33] { ppu.write_oam_data(data) }
+
+        let oam = ppu.oam_snapshot();
+        assert_eq!(oam[0xff], 0x11);
+        assert_eq!(oam[0x00..0x02], [0x22, 0x33]);
+        assert_eq!(ppu.oam_addr, 0x02);
+    }
+
+    #[test]
+    fn write_oam_attribute() {
+        let mut ppu = PpuData::new();
+        ppu.write_oam_addr(0x06);
+        ppu.write_oam_data(0xff);
+        ppu.write_oam_data(0xff);
+
+        let oam = ppu.oam_snapshot();
+        assert_eq!(oam[0x06..0x08], [0b1110_0011, 0xff]);
+    }
+
+    #[test]
+    fn read_oam_data_keeps_address() {
+        let mut ppu = PpuData::new();
+        ppu.write_oam_addr(0x10);
+        ppu.write_oam_data(0x5a);
+        ppu.write_oam_addr(0x10);
+
+        assert_eq!(ppu.read_oam_data(), 0x5a);
+        assert_eq!(ppu.read_oam_data(), 0x5a);
+        assert_eq!(ppu.oam_addr, 0x10);
+    }
+
+    #[test]
+    fn write_oam_data_while_rendering() {
+        let mut ppu = PpuData::new();
+        ppu.write_oam_addr(0x05);
+        ppu.rendering = true;
+        ppu.write_oam_data(0x5a);
+
+        assert_eq!(ppu.oam_addr, 0x09);
+        assert!(ppu.oam_snapshot().iter().all(|&data| data == 0));
+    }
+
+    #[test]
+    fn write_scroll_x() {
+        let mut ppu = PpuData::new();
+        ppu.write_scroll(0b1010_1101);
+
+        let t = VRamAddr::new(ppu.t);
+        assert_eq!(t.coarse_x, 0b1_0101);
+        assert_eq!(ppu.x, 0b101);
+        assert!(ppu.w);
+    }
+
+    #[test]
+    fn write_scroll_y() {
+        let mut ppu = PpuData::new();
+        ppu.write_scroll(0x00);
+        ppu.write_scroll(0b0111_1110);
+
+        let t = VRamAddr::new(ppu.t);
+        assert_eq!(t.coarse_y, 0b0_1111);
+        assert_eq!(t.fine_y, 0b110);
+        assert!(!ppu.w);
+    }
+
+    #[test]
+    fn write_addr() {
+        let mut ppu = PpuData::new();
+        ppu.write_addr(0x21);
+        assert_eq!(ppu.t, 0x2100);
+        assert_eq!(ppu.v, 0x0000);
+        assert!(ppu.w);
+
+        ppu.write_addr(0x08);
+        assert_eq!(ppu.t, 0x2108);
+        assert_eq!(ppu.v, 0x2108);
+        assert!(!ppu.w);
+    }
+
+    #[test]
+    fn write_addr_clears_high_bits() {
+        let mut ppu = PpuData::new();
+        ppu.t = 0b111_1111_1111_1111;
+
+        ppu.write_addr(0xff);
+        assert_eq!(ppu.t, 0b011_1111_1111_1111);
+
+        ppu.write_addr(0x00);
+        assert_eq!(ppu.t, 0b011_1111_0000_0000);
+        assert_eq!(ppu.v, 0b011_1111_0000_0000);
+    }
+
+    #[test]
+    fn write_addr_after_scroll() {
+        let mut ppu = PpuData::new();
+        ppu.write_scroll(0b0111_1101);
+        ppu.write_scroll(0b0111_1101);
+        ppu.write_addr(0x3f);
+        ppu.write_addr(0x10);
+
+        assert_eq!(ppu.t, 0x3f10);
+        assert_eq!(ppu.v, 0x3f10);
+        assert_eq!(ppu.x, 0b101);
+    }
+
+    #[test]
+    fn write_scroll_keeps_nametable() {
+        let mut ppu = PpuData::new();
+        ppu.write_control(0b0000_0011);
+        ppu.write_scroll(0xff);
+        ppu.write_scroll(0xff);
+
+        assert_eq!(ppu.t, 0b111_11_11111_11111);
+        assert_eq!(ppu.x, 0b111);
+    }
+
+    mod status {
+        use super::*;
+
+        #[test]
+        fn every_flag() {
+            let mut ppu = PpuData::new();
+            ppu.vblank_set();
+            ppu.sprite_zero_hit_set();
+            ppu.sprite_overflow_set();
+
+            assert_eq!(ppu.peek_status() & 0b1110_0000, 0b1110_0000);
+            assert_eq!(ppu.read_status() & 0b1110_0000, 0b1110_0000);
+
+            // Only vblank is cleared by reading.
+            assert_eq!(ppu.read_status() & 0b1110_0000, 0b0110_0000);
+        }
+
+        #[test]
+        fn sprite_zero_hit() {
+            let mut ppu = PpuData::new();
+            ppu.write_scroll(0x10);
+            assert!(ppu.w);
+
+            ppu.sprite_zero_hit_set();
+            assert_eq!(ppu.read_status() & 0b1110_0000, 0b0100_0000);
+            assert!(!ppu.w);
+
+            ppu.vblank_set();
+            assert_eq!(ppu.read_status() & 0b1110_0000, 0b1100_0000);
+            assert!(!ppu.is_vblank());
+            assert!(ppu.is_sprite_zero_hit());
+        }
+
+        #[test]
+        fn latch_fills_low_bits() {
+            let mut ppu = PpuData::new();
+            ppu.write(0b1111_1111);
+            ppu.sprite_overflow_set();
+
+            assert_eq!(ppu.read_status(), 0b0011_1111);
+            ppu.status_clear();
+            assert_eq!(ppu.read_status(), 0b0001_1111);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use pretty_hex::PrettyHex;
+
+use crate::cartridge::CartridgeLike;
+use crate::cartridge::location::Location;
+use crate::utils::mem;
+
+const CAPACITY: usize = 0x10000;
+
+// 64kb of RAM covering the whole CPU address space. No console RAM, PPU or APU registers.
+// Lets pure 6502 programs, like functional tests, run on the CPU alone.
+pub struct FlatMemory {
+    ram: Vec<u8>,
+}
+
+impl FlatMemory {
+    // The image is loaded from $0000. Bigger images are truncated.
+    pub fn new(image: &[u8]) -> Self {
+        let mut ram = vec![0; CAPACITY];
+        let len = CAPACITY.min(image.len());
+        ram[..len].copy_from_slice(&image[..len]);
+        Self { ram }
+    }
+}
+
+impl CartridgeLike for FlatMemory {
+    fn cpu_read_location(&self, addr: u16) -> Location { Location::PrgRam(addr) }
+    fn cpu_write_location(&self, addr: u16) -> Location { Location::PrgRam(addr) }
+
+    fn ppu_read_location(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+    fn ppu_write_location(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+
+    fn read_prg_rom(&self, _addr: u16) -> u8 { 0 }
+    fn read_chr_rom(&self, _addr: u16) -> u8 { 0 }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 { mem::read(&self.ram, addr as usize) }
+    fn write_prg_ram(&mut self, addr: u16, data: u8) { mem::write(&mut self.ram, addr as usize, data) }
+}
+
+impl fmt::Debug for FlatMemory {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "RAM | {:?}", (&self.ram[..]).hex_dump())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::asm::{lda, Operand, sta};
+    use crate::bus::Bus;
+    use crate::cpu::Cpu;
+
+    use super::*;
+
+    fn cpu(image: &[u8], pc: u16) -> Cpu {
+        let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_cartridge(FlatMemory::new(image)))));
+        cpu.reg.s_pc(pc);
+        cpu
+    }
+
+    #[test]
+    fn whole_address_space_is_ram() {
+        let cpu = cpu(&[], 0x0000);
+        let mut bus = cpu.bus.borrow_mut();
+        for &addr in &[0x0000, 0x0800, 0x2002, 0x4016, 0x6000, 0x8000, 0xfffc] {
+            bus.write_cpu(addr, 0x5a);
+            assert_eq!(bus.read_cpu(addr), 0x5a, "0x{:04x}", addr);
+        }
+        drop(bus);
+
+        // Not mirrored like console RAM.
+        cpu.bus.borrow_mut().write_cpu(0x0001, 0x11);
+        assert_eq!(cpu.bus.borrow_mut().read_cpu(0x0801), 0x00);
+    }
+
+    #[test]
+    fn run_until_trap() {
+        let mut image = vec![0; 0x0400];
+        image.extend([lda(Operand::Immediate(0x42)), sta(Operand::Absolute(0x9000)), sta(Operand::Absolute(0x2002))].concat());
+
+        // JMP to itself
+        image.extend(&[0x4c, 0x08, 0x04]);
+
+        let mut cpu = cpu(&image, 0x0400);
+        assert_eq!(cpu.run_until_trap(1000), Some(0x0408));
+
+        let mut bus = cpu.bus.borrow_mut();
+        assert_eq!(bus.read_cpu(0x9000), 0x42);
+        assert_eq!(bus.read_cpu(0x2002), 0x42);
+    }
+
+    #[test]
+    fn run_until_trap_gives_up() {
+        let mut image = vec![0; 0x0400];
+
+        // INX and JMP back to it. Loops forever without trapping.
+        image.extend(&[0xe8, 0x4c, 0x00, 0x04]);
+
+        let mut cpu = cpu(&image, 0x0400);
+        assert_eq!(cpu.run_until_trap(1000), None);
+        assert!(cpu.get_clock() >= 1000);
+    }
+
+    // Instructions that touch memory above console RAM and the registers.
+    mod instructions {
+        use super::*;
+
+        #[test]
+        fn sta() {
+            let mut cpu = cpu(&[0x8d, 0x00, 0xc0], 0x0000);
+            cpu.reg.s_a(0x37);
+            cpu.step_instruction();
+            assert_eq!(cpu.bus.borrow_mut().read_cpu(0xc000), 0x37);
+        }
+
+        #[test]
+        fn adc() {
+            let mut image = vec![0x6d, 0x00, 0x40];
+            image.resize(0x4001, 0);
+            image[0x4000] = 0x50;
+
+            let mut cpu = cpu(&image, 0x0000);
+            cpu.reg.s_a(0x50);
+            cpu.reg.s_c(true);
+            cpu.step_instruction();
+
+            assert_eq!(cpu.reg.get_a(), 0xa1);
+            assert!(cpu.reg.get_p().get_overflow());
+            assert!(!cpu.reg.get_p().get_carry());
+        }
+
+        #[test]
+        fn branch() {
+            // BNE back into $7ffe. On a cartridge it would go from PRG ROM to PRG RAM.
+            let mut image = vec![0; 0x8000];
+            image.extend(&[0xd0, 0xfc]);
+
+            let mut cpu = cpu(&image, 0x8000);
+            cpu.reg.s_z(false);
+            cpu.step_instruction();
+            assert_eq!(cpu.reg.get_pc(), 0x7ffe);
+        }
+    }
+}
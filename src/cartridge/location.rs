@@ -1,3 +1,5 @@
+use std::fmt;
+
 // Describes a location on the console for reading and writing
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Location {
@@ -18,9 +20,50 @@ pub enum Location {
     PpuScroll,
     PpuData,
     OamDma,
+    Controller(u8),
+
+    // Addresses accessed by the PPU.
+    Vram(u16),
+    Palette(u16),
 
     // Addresses on the cartridge. Can be accessed by anyone.
     PrgRam(u16),
     PrgRom(u16),
     ChrRom(u16),
+    CartridgeVram(u16),
+
+    // Registers of the cartridge, like bank selects.
+    CartridgeRegister(u16),
+}
+
+// Registers by their usual names. Other locations show the offset into their memory.
+impl fmt::Display for Location {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Nowhere(addr) => write!(formatter, "Nowhere ${:04x}", addr),
+            Location::Apu(addr) => write!(formatter, "APU +{:04x}", addr),
+
+            Location::CpuRam(addr) => write!(formatter, "CPU RAM +{:04x}", addr),
+            Location::PpuCtrl => write!(formatter, "PPUCTRL"),
+            Location::PpuMask => write!(formatter, "PPUMASK"),
+            Location::PpuStatus => write!(formatter, "PPUSTATUS"),
+            Location::OamAddr => write!(formatter, "OAMADDR"),
+            Location::OamData => write!(formatter, "OAMDATA"),
+            Location::PpuAddr => write!(formatter, "PPUADDR"),
+            Location::PpuScroll => write!(formatter, "PPUSCROLL"),
+            Location::PpuData => write!(formatter, "PPUDATA"),
+            Location::OamDma => write!(formatter, "OAMDMA"),
+            Location::Controller(port) => write!(formatter, "Controller {}", port + 1),
+
+            Location::Vram(addr) => write!(formatter, "VRAM +{:04x}", addr),
+            Location::Palette(addr) => write!(formatter, "Palette +{:04x}", addr),
+
+            Location::PrgRam(addr) => write!(formatter, "PRG RAM +{:04x}", addr),
+            Location::PrgRom(addr) => write!(formatter, "PRG ROM +{:04x}", addr),
+            Location::ChrRom(addr) => write!(formatter, "CHR ROM +{:04x}", addr),
+            Location::CartridgeVram(addr) => write!(formatter, "Cartridge VRAM +{:04x}", addr),
+
+            Location::CartridgeRegister(addr) => write!(formatter, "Cartridge register ${:04x}", addr),
+        }
+    }
 }
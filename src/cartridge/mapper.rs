@@ -6,4 +6,78 @@ pub trait Mapper {
 
     fn read_ppu(&self, addr: u16) -> Location;
     fn write_ppu(&self, addr: u16) -> Location;
+
+    // Written through `Location::CartridgeRegister`.
+    fn write_register(&mut self, _addr: u16, _data: u8) {}
+
+    // Discrete logic boards don't disable the ROM on writes, so both drive the bus and zeroes win.
+    // The value written to a register is ANDed with the ROM byte at the same address.
+    fn bus_conflicts(&self) -> bool { false }
+    fn set_bus_conflicts(&mut self, _enabled: bool) {}
+
+    // Called by the PPU on every pattern fetch with the address on its bus.
+    // Scanline counters are clocked by the rising edge of A12.
+    fn clock_a12(&mut self, _addr: u16) {}
+
+    // PRG RAM can be disabled or write protected by some mappers, like MMC1 and MMC3.
+    fn prg_ram_enabled(&self) -> bool { true }
+    fn prg_ram_writable(&self) -> bool { true }
+}
+
+// Detects rising edges on the A12 line of the PPU address bus.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct A12Edge {
+    high: bool,
+}
+
+impl A12Edge {
+    pub fn new() -> Self { Self { high: false } }
+
+    // Update the line with a new address and check if it went from low to high.
+    pub fn rising(&mut self, addr: u16) -> bool {
+        let high = (addr & 0x1000) != 0;
+        let res = high && !self.high;
+        self.high = high;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter {
+        edge: A12Edge,
+        clocks: u32,
+    }
+
+    impl Mapper for Counter {
+        fn read_cpu(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+        fn write_cpu(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+        fn read_ppu(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+        fn write_ppu(&self, addr: u16) -> Location { Location::Nowhere(addr) }
+
+        fn clock_a12(&mut self, addr: u16) {
+            if self.edge.rising(addr) { self.clocks += 1 }
+        }
+    }
+
+    #[test]
+    fn rising_edge() {
+        let mut edge = A12Edge::new();
+        assert!(!edge.rising(0x0fff));
+        assert!(edge.rising(0x1000));
+        assert!(!edge.rising(0x1ff0));
+        assert!(!edge.rising(0x0000));
+        assert!(edge.rising(0x1008));
+    }
+
+    #[test]
+    fn clock_on_rising_edges_only() {
+        let mut mapper = Counter { edge: A12Edge::new(), clocks: 0 };
+        for &addr in &[0x0000, 0x1000, 0x1008, 0x0010, 0x0018, 0x1020, 0x0000, 0x1fff, 0x1000] {
+            mapper.clock_a12(addr);
+        }
+        assert_eq!(mapper.clocks, 3);
+    }
 }
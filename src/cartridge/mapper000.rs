@@ -1,38 +1,47 @@
 use crate::cartridge::location::Location;
 use crate::cartridge::mapper::Mapper;
 
-pub struct Mapper000;
+// NROM. Either 32kb of PRG ROM, or 16kb mirrored into both halves of $8000-$ffff.
+pub struct Mapper000 {
+    mirror_prg_rom: bool,
+}
+
+impl Mapper000 {
+    pub fn new() -> Self { Self::with_prg_rom_size(0x8000) }
 
-impl Mapper000 { pub fn new() -> Self { Self } }
+    pub fn with_prg_rom_size(size: usize) -> Self { Self { mirror_prg_rom: size <= 0x4000 } }
+}
 
 impl Mapper for Mapper000 {
     fn read_cpu(&self, addr: u16) -> Location {
         match addr {
-            0x6000...0x7fff => Location::PrgRam(addr - 0x6000),
-            0x8000...0xffff => Location::PrgRom(addr - 0x8000),
+            0x6000..=0x7fff => Location::PrgRam(addr - 0x6000),
+            0x8000..=0xbfff => Location::PrgRom(addr - 0x8000),
+            0xc000..=0xffff if self.mirror_prg_rom => Location::PrgRom(addr - 0xc000),
+            0xc000..=0xffff => Location::PrgRom(addr - 0x8000),
             _ => Location::Nowhere(addr),
         }
     }
 
     fn write_cpu(&self, addr: u16) -> Location {
         match addr {
-            0x6000...0x7fff => Location::PrgRam(addr - 0x6000),
+            0x6000..=0x7fff => Location::PrgRam(addr - 0x6000),
             _ => Location::Nowhere(addr),
         }
     }
 
     fn read_ppu(&self, addr: u16) -> Location {
         match addr {
-            0x0000...0x1fff => Location::ChrRom(addr),
-            0x2000...0x2fff => Location::PrgRam(addr - 0x2000),
+            0x0000..=0x1fff => Location::ChrRom(addr),
+            0x2000..=0x3eff => Location::Vram(addr - 0x2000),
             _ => Location::Nowhere(addr),
         }
     }
 
     fn write_ppu(&self, addr: u16) -> Location {
         match addr {
-            0x0000...0x1fff => Location::ChrRom(addr),
-            0x2000...0x2fff => Location::PrgRam(addr - 0x2000),
+            0x0000..=0x1fff => Location::ChrRom(addr),
+            0x2000..=0x3eff => Location::Vram(addr - 0x2000),
             _ => Location::Nowhere(addr),
         }
     }
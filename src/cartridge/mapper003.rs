@@ -0,0 +1,48 @@
+use crate::cartridge::location::Location;
+use crate::cartridge::mapper000::Mapper000;
+use crate::cartridge::mapper::Mapper;
+
+const CHR_BANK_SIZE: u16 = 0x2000;
+
+// CNROM. PRG ROM is fixed like NROM and any write to it selects one of four 8kb CHR banks.
+// It's discrete logic, so the ROM drives the bus during writes too. See `Mapper::bus_conflicts`.
+pub struct Mapper003 {
+    nrom: Mapper000,
+    chr_bank: u8,
+    bus_conflicts: bool,
+}
+
+impl Mapper003 {
+    pub fn new(prg_rom_size: usize) -> Self {
+        Self { nrom: Mapper000::with_prg_rom_size(prg_rom_size), chr_bank: 0, bus_conflicts: true }
+    }
+}
+
+impl Mapper for Mapper003 {
+    fn read_cpu(&self, addr: u16) -> Location { self.nrom.read_cpu(addr) }
+
+    fn write_cpu(&self, addr: u16) -> Location {
+        match addr {
+            0x8000..=0xffff => Location::CartridgeRegister(addr),
+            _ => self.nrom.write_cpu(addr),
+        }
+    }
+
+    fn read_ppu(&self, addr: u16) -> Location {
+        match addr {
+            0x0000..=0x1fff => Location::ChrRom(u16::from(self.chr_bank) * CHR_BANK_SIZE + addr),
+            _ => self.nrom.read_ppu(addr),
+        }
+    }
+
+    fn write_ppu(&self, addr: u16) -> Location { self.read_ppu(addr) }
+
+    fn write_register(&mut self, _addr: u16, data: u8) { self.chr_bank = data & 0x03 }
+
+    fn bus_conflicts(&self) -> bool { self.bus_conflicts }
+    fn set_bus_conflicts(&mut self, enabled: bool) { self.bus_conflicts = enabled }
+}
+
+impl Default for Mapper003 {
+    fn default() -> Self { Self::new(0x8000) }
+}
@@ -6,14 +6,21 @@ use std::io::Read;
 
 use pretty_hex::PrettyHex;
 
+use crate::bus::ppu_data::VRAM_CAPACITY;
 use crate::cartridge::location::Location;
 use crate::cartridge::mapper000::Mapper000;
+use crate::cartridge::mapper003::Mapper003;
 use crate::cartridge::mapper::Mapper;
+use crate::ppu::Region;
 use crate::utils::bits;
+use crate::utils::hash;
+use crate::utils::mem;
 
+pub mod flat;
 pub mod mapper;
 pub mod location;
 pub mod mapper000;
+pub mod mapper003;
 
 const EIGHT_KBYTES: usize = 0x2000;
 const SIXTEEN_KBYTES: usize = 2 * EIGHT_KBYTES;
@@ -26,6 +33,7 @@ pub enum LoadError {
     UnableToReadPrgRom,
     UnableToReadChrRom,
     MapperNotImplemented(u8),
+    SongNotFound(u8),
 }
 
 impl From<io::Error> for LoadError {
@@ -36,13 +44,67 @@ impl From<io::Error> for LoadError {
 pub enum PpuMirror {
     Horizontal,
     Vertical,
+
+    // Cartridge has VRAM for the other two nametables.
+    FourScreen,
+
+    // Every nametable points to the same one. Selected by the mapper.
+    SingleScreen(u8),
+}
+
+// Memory map of whatever is plugged in the console.
+// Lets tests and players supply their own memory without an iNES file.
+pub trait CartridgeLike: fmt::Debug {
+    fn cpu_read_location(&self, addr: u16) -> Location;
+    fn cpu_write_location(&self, addr: u16) -> Location;
+
+    fn ppu_read_location(&self, addr: u16) -> Location;
+    fn ppu_write_location(&self, addr: u16) -> Location;
+
+    fn read_prg_rom(&self, addr: u16) -> u8;
+    fn read_chr_rom(&self, addr: u16) -> u8;
+
+    fn read_prg_ram(&self, _addr: u16) -> u8 { 0 }
+    fn write_prg_ram(&mut self, _addr: u16, _data: u8) {}
+
+    // Extra nametables of four screen cartridges.
+    fn read_vram(&self, _addr: u16) -> u8 { 0 }
+    fn write_vram(&mut self, _addr: u16, _data: u8) {}
+
+    // Registers mapped by the cartridge. Only written.
+    fn write_register(&mut self, _addr: u16, _data: u8) {}
+
+    fn clock_a12(&mut self, _addr: u16) {}
+    fn set_ppu_mirror(&mut self, _mirror: PpuMirror) {}
+
+    // Where a CPU read of the address lands, like "PPUSTATUS" or "PRG ROM +0000".
+    fn describe_location(&self, addr: u16) -> String { self.cpu_read_location(addr).to_string() }
+}
+
+// What the iNES header says about the cartridge.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct CartridgeHeader {
+    pub mapper: u8,
+    pub ppu_mirror: PpuMirror,
+
+    // Sizes in bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+
+    // PRG RAM is kept by a battery. Front ends should save it.
+    pub battery: bool,
+
+    pub region: Region,
 }
 
 pub struct Cartridge {
+    header: CartridgeHeader,
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
     prg_ram: Vec<u8>,
-    mapper: Box<Mapper>,
+    vram: Vec<u8>,
+    mapper: Box<dyn Mapper>,
     pub ppu_mirror: PpuMirror,
 }
 
@@ -62,20 +124,25 @@ impl Cartridge {
         let chr_rom = data.get(chr_rom).ok_or(LoadError::UnableToReadChrRom)?;
         let chr_rom = chr_rom.to_owned();
 
-        // PPU mirror type.
-        let ppu_mirror = if bits::is_set(data[0x05], 0) {
+        // PPU mirror type. Four screen overrides the other bit.
+        let ppu_mirror = if bits::is_set(data[0x06], 3) {
+            PpuMirror::FourScreen
+        } else if bits::is_set(data[0x06], 0) {
             PpuMirror::Vertical
         } else {
             PpuMirror::Horizontal
         };
 
+        let vram_capacity = if ppu_mirror == PpuMirror::FourScreen { VRAM_CAPACITY } else { 0 };
+
         // Mapper.
         // High nybble of 6 contains the lower nybble of the mapper.
         // High nybble of 7 contains the higher nybble of the mapper.
-        let mapper = ((data[0x06] & 0b1111_0000) >> 4) | (data[0x07] & 0b1111_0000);
-        let mapper = match mapper {
-            0 => box Mapper000::new(),
-            _ => return Result::Err(LoadError::MapperNotImplemented(mapper)),
+        let mapper_number = ((data[0x06] & 0b1111_0000) >> 4) | (data[0x07] & 0b1111_0000);
+        let mapper: Box<dyn Mapper> = match mapper_number {
+            0 => Box::new(Mapper000::with_prg_rom_size(prg_rom.len())),
+            3 => Box::new(Mapper003::new(prg_rom.len())),
+            _ => return Result::Err(LoadError::MapperNotImplemented(mapper_number)),
         };
 
         // PRG RAM is present if bit is not set.
@@ -86,11 +153,23 @@ impl Cartridge {
                 EIGHT_KBYTES * cmp::max(data[0x08] as usize, 1)
             };
 
+        let header = CartridgeHeader {
+            mapper: mapper_number,
+            ppu_mirror,
+            prg_rom_size: prg_rom.len(),
+            chr_rom_size: chr_rom.len(),
+            prg_ram_size: prg_ram_capacity,
+            battery: bits::is_set(data[0x06], 1),
+            region: if bits::is_set(data[0x09], 0) { Region::Pal } else { Region::Ntsc },
+        };
+
         Ok(
             Self {
+                header,
                 prg_rom,
                 chr_rom,
                 prg_ram: vec![0; prg_ram_capacity],
+                vram: vec![0; vram_capacity],
                 mapper,
                 ppu_mirror,
             }
@@ -105,118 +184,245 @@ impl Cartridge {
 
     pub fn empty() -> Self {
         Self {
+            header: CartridgeHeader {
+                mapper: 0,
+                ppu_mirror: PpuMirror::Horizontal,
+                prg_rom_size: SIXTEEN_KBYTES,
+                chr_rom_size: EIGHT_KBYTES,
+                prg_ram_size: 0,
+                battery: false,
+                region: Region::Ntsc,
+            },
             prg_rom: vec![0; SIXTEEN_KBYTES],
             chr_rom: vec![0; EIGHT_KBYTES],
             prg_ram: vec![0; 0],
-            mapper: box Mapper000::new(),
+            vram: vec![0; 0],
+            mapper: Box::new(Mapper000::with_prg_rom_size(SIXTEEN_KBYTES)),
             ppu_mirror: PpuMirror::Horizontal,
         }
     }
 
-    pub fn read_prg_rom(&self, addr: u16) -> u8 {
-        let index = addr as usize % self.prg_rom.len();
-        unsafe { *self.prg_rom.get_unchecked(index) }
+    // Metadata as loaded. The mirroring may have been changed by the mapper since.
+    pub fn header(&self) -> CartridgeHeader { self.header }
+
+    // Checksums over PRG and CHR ROM without the header, as ROM databases list them.
+    pub fn crc32(&self) -> u32 { hash::crc32(&[&self.prg_rom, &self.chr_rom]) }
+
+    #[cfg(feature = "sha1")]
+    pub fn sha1(&self) -> [u8; 20] { hash::sha1(&[&self.prg_rom, &self.chr_rom]) }
+
+    // Pixels of a tile in CHR ROM as palette indices, row by row.
+    // Decoded from the two bit planes like the PPU does. The index wraps around the ROM.
+    pub fn chr_tile(&self, index: usize) -> [u8; 64] {
+        let mut res = [0; 64];
+        if self.chr_rom.is_empty() { return res; }
+
+        let start = index * 16;
+        for (y, row) in res.chunks_mut(8).enumerate() {
+            let low = mem::read(&self.chr_rom, start + y);
+            let high = mem::read(&self.chr_rom, start + y + 8);
+            row.copy_from_slice(&bits::interlace(low, high));
+        }
+        res
     }
 
-    pub fn read_chr_rom(&self, addr: u16) -> u8 {
+    // Emulate bus conflicts on mappers that have them. Boards that avoid them can turn it off.
+    pub fn set_bus_conflicts(&mut self, enabled: bool) { self.mapper.set_bus_conflicts(enabled) }
+
+    // Common PRG RAM location. Disabled RAM is open bus.
+    fn prg_ram_location(&self, addr: u16) -> Location {
+        if self.prg_ram.is_empty() || !self.mapper.prg_ram_enabled() { return Location::Nowhere(addr); }
+        Location::PrgRam(addr - 0x6000)
+    }
+
+    // Nametables are mirrored into the two available in the console.
+    // Four screen cartridges supply the last two themselves.
+    fn nametable_location(&self, addr: u16) -> Location {
+        let addr = addr & 0x0fff;
+        match self.ppu_mirror {
+            PpuMirror::Vertical => Location::Vram(addr & 0x07ff),
+            PpuMirror::Horizontal => Location::Vram(((addr & 0x0800) >> 1) | (addr & 0x03ff)),
+            PpuMirror::SingleScreen(screen) => Location::Vram((u16::from(screen & 1) << 10) | (addr & 0x03ff)),
+            PpuMirror::FourScreen => match addr {
+                0x0000..=0x07ff => Location::Vram(addr),
+                _ => Location::CartridgeVram(addr & 0x07ff),
+            }
+        }
+    }
+
+    // Common ppu locations
+    fn ppu_location(&self, addr: u16, mapped: Location) -> Location {
+        match addr {
+            0x3f00..=0x3fff => Location::Palette(addr - 0x3f00),
+            _ => match mapped {
+                Location::Vram(addr) => self.nametable_location(addr),
+                _ => mapped,
+            }
+        }
+    }
+}
+
+impl CartridgeLike for Cartridge {
+    // Location for reading from the CPU
+    fn cpu_read_location(&self, addr: u16) -> Location {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram_location(addr),
+            0x8000..=0xffff => self.mapper.read_cpu(addr),
+            _ => console_read_location(addr)
+        }
+    }
+
+    // Location for writing from the CPU
+    fn cpu_write_location(&self, addr: u16) -> Location {
+        match addr {
+            0x6000..=0x7fff if !self.mapper.prg_ram_writable() => Location::Nowhere(addr),
+            0x6000..=0x7fff => self.prg_ram_location(addr),
+            0x8000..=0xffff => self.mapper.write_cpu(addr),
+            _ => console_write_location(addr)
+        }
+    }
+
+    // Location for reading from the PPU
+    fn ppu_read_location(&self, addr: u16) -> Location {
+        let addr = addr & 0x3fff;
+        self.ppu_location(addr, self.mapper.read_ppu(addr))
+    }
+
+    // Location for writing from the PPU
+    fn ppu_write_location(&self, addr: u16) -> Location {
+        let addr = addr & 0x3fff;
+        self.ppu_location(addr, self.mapper.write_ppu(addr))
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        mem::read(&self.prg_rom, addr as usize)
+    }
+
+    fn read_chr_rom(&self, addr: u16) -> u8 {
         if self.chr_rom.is_empty() {
             error!("Attempt to read from CHR ROM, but cartridge reports it's not present. Defaulting to zero. 0x{:04x}", addr);
             return 0;
         }
 
-        let index = addr as usize % self.chr_rom.len();
-        unsafe { *self.chr_rom.get_unchecked(index) }
+        mem::read(&self.chr_rom, addr as usize)
     }
 
-    pub fn read_prg_ram(&self, addr: u16) -> u8 {
+    fn write_register(&mut self, addr: u16, data: u8) {
+        let data = if self.mapper.bus_conflicts() {
+            match self.mapper.read_cpu(addr) {
+                Location::PrgRom(rom_addr) => data & self.read_prg_rom(rom_addr),
+                _ => data,
+            }
+        } else {
+            data
+        };
+
+        self.mapper.write_register(addr, data)
+    }
+
+    // Let the mapper watch the pattern table address for its scanline counter.
+    fn clock_a12(&mut self, addr: u16) {
+        self.mapper.clock_a12(addr)
+    }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 {
         if self.prg_ram.is_empty() {
             error!("Attempt to read from PRG RAM, but cartridge reports it's not present. Defaulting to zero. 0x{:04x}", addr);
             return 0;
         }
 
-        let index = addr as usize % self.prg_ram.len();
-        unsafe { *self.prg_ram.get_unchecked(index) }
+        mem::read(&self.prg_ram, addr as usize)
     }
 
-    pub fn write_prg_ram(&mut self, addr: u16, data: u8) {
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
         if self.prg_ram.is_empty() {
             error!("Attempt to write to PRG RAM, but cartridge reports it's not present. 0x{:04x}, 0x{:02x}",
                    addr, data);
             return;
         }
 
-        let index = addr as usize % self.prg_ram.len();
-        unsafe { *self.prg_ram.get_unchecked_mut(index) = data }
+        mem::write(&mut self.prg_ram, addr as usize, data)
     }
 
-    // Canonize a PPU register from the mirrored area
-    fn canon_ppu_register_addr(addr: u16) -> u16 {
-        const PPU_REG_AMOUNT: u16 = 0x08;
-        const PPU_REGS_ADDR_START: u16 = 0x2000;
-        PPU_REGS_ADDR_START + (addr - PPU_REGS_ADDR_START) % PPU_REG_AMOUNT
+    fn read_vram(&self, addr: u16) -> u8 {
+        if self.vram.is_empty() {
+            error!("Attempt to read from cartridge VRAM, but it's not four screen. Defaulting to zero. 0x{:04x}", addr);
+            return 0;
+        }
+
+        mem::read(&self.vram, addr as usize)
     }
 
-    // Common PRG RAM location
-    fn prg_ram_location(&self, addr: u16) -> Location {
-        match self.prg_ram.len() {
-            0 => Location::Nowhere(addr),
-            _ => Location::PrgRam(addr - 0x6000),
-        }
-    }
-
-    // Common cpu locations
-    fn cpu_location(&self, addr: u16) -> Location {
-        #[allow(clippy::match_overlapping_arm)] match addr {
-            0x0000...0x1fff => Location::CpuRam(addr),
-
-            0x2000...0x3fff => {
-                let canon_addr = Self::canon_ppu_register_addr(addr);
-                match canon_addr {
-                    0x2000 => Location::PpuCtrl,
-                    0x2001 => Location::PpuMask,
-                    0x2002 => Location::PpuStatus,
-                    0x2003 => Location::OamAddr,
-                    0x2004 => Location::OamData,
-                    0x2005 => Location::PpuScroll,
-                    0x2006 => Location::PpuAddr,
-                    0x2007 => Location::PpuData,
-                    _ => unimplemented!("Canonical PPU register doesn't exist for address 0x{:04x}.", addr),
-                }
-            }
+    fn write_vram(&mut self, addr: u16, data: u8) {
+        if self.vram.is_empty() {
+            error!("Attempt to write to cartridge VRAM, but it's not four screen. 0x{:04x}, 0x{:02x}", addr, data);
+            return;
+        }
 
-            0x4014 => Location::OamDma,
+        mem::write(&mut self.vram, addr as usize, data)
+    }
 
-            0x4000...0x4017 => {
-                warn!("Reading from APU address 0x{:04x}.", addr);
-                Location::Apu(addr - 0x4000)
-            }
+    // Change the mirroring. Four screen needs VRAM on the cartridge.
+    fn set_ppu_mirror(&mut self, mirror: PpuMirror) {
+        if mirror == PpuMirror::FourScreen && self.vram.is_empty() {
+            self.vram = vec![0; VRAM_CAPACITY];
+        }
+        self.ppu_mirror = mirror;
+    }
+}
+
+// Canonize a PPU register from the mirrored area
+fn canon_ppu_register_addr(addr: u16) -> u16 {
+    const PPU_REG_AMOUNT: u16 = 0x08;
+    const PPU_REGS_ADDR_START: u16 = 0x2000;
+    PPU_REGS_ADDR_START + (addr - PPU_REGS_ADDR_START) % PPU_REG_AMOUNT
+}
 
-            _ => {
-                error!("Reading from area not mapped in CPU. Addr 0x{:04x}.", addr);
-                Location::Nowhere(addr)
+// Locations outside the cartridge. Every cartridge maps them the same way.
+pub fn console_read_location(addr: u16) -> Location {
+    #[allow(clippy::match_overlapping_arm)] match addr {
+        0x0000..=0x1fff => Location::CpuRam(addr),
+
+        0x2000..=0x3fff => {
+            let canon_addr = canon_ppu_register_addr(addr);
+            match canon_addr {
+                0x2000 => Location::PpuCtrl,
+                0x2001 => Location::PpuMask,
+                0x2002 => Location::PpuStatus,
+                0x2003 => Location::OamAddr,
+                0x2004 => Location::OamData,
+                0x2005 => Location::PpuScroll,
+                0x2006 => Location::PpuAddr,
+                _ => Location::PpuData,
             }
         }
-    }
 
-    // Location for reading from the CPU
-    pub fn cpu_read_location(&self, addr: u16) -> Location {
-        match addr {
-            0x6000...0x7fff => self.prg_ram_location(addr),
-            0x8000...0xffff => self.mapper.read_cpu(addr),
-            _ => self.cpu_location(addr)
+        0x4014 => Location::OamDma,
+        0x4015 => Location::Apu(0x15),
+
+        0x4016 => Location::Controller(0),
+        0x4017 => Location::Controller(1),
+
+        0x4000..=0x4017 => {
+            warn!("Reading from APU address 0x{:04x}.", addr);
+            Location::Apu(addr - 0x4000)
         }
-    }
 
-    // Location for writing from the CPU
-    pub fn cpu_write_location(&self, addr: u16) -> Location {
-        match addr {
-            0x6000...0x7fff => self.prg_ram_location(addr),
-            0x8000...0xffff => self.mapper.write_cpu(addr),
-            _ => self.cpu_location(addr)
+        _ => {
+            error!("Reading from area not mapped in CPU. Addr 0x{:04x}.", addr);
+            Location::Nowhere(addr)
         }
     }
 }
 
+pub fn console_write_location(addr: u16) -> Location {
+    match addr {
+        // Both controllers are strobed by $4016. $4017 is the APU frame counter.
+        0x4017 => Location::Apu(addr - 0x4000),
+        _ => console_read_location(addr),
+    }
+}
+
 impl fmt::Debug for Cartridge {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         writeln!(formatter, "PRG ROM | {:?}\n", &self.prg_rom.hex_dump())?;
@@ -249,6 +455,43 @@ mod tests {
         });
     }
 
+    #[test]
+    fn header() {
+        let header = load_test().header();
+        assert_eq!(header.mapper, 0);
+        assert_eq!(header.ppu_mirror, PpuMirror::Horizontal);
+        assert_eq!(header.prg_rom_size, SIXTEEN_KBYTES);
+        assert_eq!(header.chr_rom_size, EIGHT_KBYTES);
+        assert_eq!(header.prg_ram_size, EIGHT_KBYTES);
+        assert!(!header.battery);
+        assert_eq!(header.region, Region::Ntsc);
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(load_test().crc32(), 0x158b_0388);
+    }
+
+    #[cfg(feature = "sha1")]
+    #[test]
+    fn sha1() {
+        let sha1 = load_test().sha1();
+        assert_eq!(&sha1[..4], &[0x41, 0x31, 0x30, 0x7f]);
+        assert_eq!(&sha1[16..], &[0xa5, 0xed, 0x08, 0x20]);
+    }
+
+    #[test]
+    fn header_flags() {
+        let mut rom = b"NES\x1a\x01\x00\x03\x00\x00\x01".to_vec();
+        rom.resize(PRG_ROM_START + SIXTEEN_KBYTES, 0);
+
+        let header = Cartridge::new(&rom).unwrap().header();
+        assert_eq!(header.ppu_mirror, PpuMirror::Vertical);
+        assert!(header.battery);
+        assert_eq!(header.region, Region::Pal);
+        assert_eq!(header.chr_rom_size, 0);
+    }
+
     #[test]
     fn prg_rom_start() {
         let cartridge = load_test();
@@ -260,4 +503,431 @@ mod tests {
         let cartridge = load_test();
         assert_eq!(cartridge.cpu_read_location(0x8000 + 0x3fff), Location::PrgRom(0x3fff));
     }
+
+    #[test]
+    fn ppu_nametable_vertical() {
+        let mut cartridge = Cartridge::empty();
+        cartridge.ppu_mirror = PpuMirror::Vertical;
+        assert_eq!(cartridge.ppu_read_location(0x2000), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_read_location(0x2400), Location::Vram(0x0400));
+        assert_eq!(cartridge.ppu_read_location(0x2800), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_write_location(0x2c01), Location::Vram(0x0401));
+    }
+
+    #[test]
+    fn ppu_nametable_horizontal() {
+        let mut cartridge = Cartridge::empty();
+        cartridge.ppu_mirror = PpuMirror::Horizontal;
+        assert_eq!(cartridge.ppu_read_location(0x2000), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_read_location(0x2400), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_read_location(0x2800), Location::Vram(0x0400));
+        assert_eq!(cartridge.ppu_write_location(0x3c01), Location::Vram(0x0401));
+    }
+
+    #[test]
+    fn ppu_nametable_single_screen() {
+        let mut cartridge = Cartridge::empty();
+        cartridge.set_ppu_mirror(PpuMirror::SingleScreen(0));
+        assert_eq!(cartridge.ppu_read_location(0x2000), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_read_location(0x2401), Location::Vram(0x0001));
+        assert_eq!(cartridge.ppu_read_location(0x2bff), Location::Vram(0x03ff));
+
+        cartridge.set_ppu_mirror(PpuMirror::SingleScreen(1));
+        assert_eq!(cartridge.ppu_read_location(0x2000), Location::Vram(0x0400));
+        assert_eq!(cartridge.ppu_write_location(0x2c01), Location::Vram(0x0401));
+    }
+
+    #[test]
+    fn ppu_nametable_four_screen() {
+        let mut cartridge = Cartridge::empty();
+        cartridge.set_ppu_mirror(PpuMirror::FourScreen);
+        assert_eq!(cartridge.ppu_read_location(0x2000), Location::Vram(0x0000));
+        assert_eq!(cartridge.ppu_read_location(0x2401), Location::Vram(0x0401));
+        assert_eq!(cartridge.ppu_read_location(0x2800), Location::CartridgeVram(0x0000));
+        assert_eq!(cartridge.ppu_write_location(0x2fff), Location::CartridgeVram(0x07ff));
+        assert_eq!(cartridge.vram.len(), VRAM_CAPACITY);
+    }
+
+    #[test]
+    fn four_screen_header() {
+        let mut rom = b"NES\x1a\x01\x01\x09\x00".to_vec();
+        rom.resize(PRG_ROM_START + SIXTEEN_KBYTES + EIGHT_KBYTES, 0);
+
+        let cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.ppu_mirror, PpuMirror::FourScreen);
+        assert_eq!(cartridge.vram.len(), VRAM_CAPACITY);
+    }
+
+    #[test]
+    fn vertical_header() {
+        let mut rom = b"NES\x1a\x01\x01\x01\x00".to_vec();
+        rom.resize(PRG_ROM_START + SIXTEEN_KBYTES + EIGHT_KBYTES, 0);
+
+        let cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.ppu_mirror, PpuMirror::Vertical);
+        assert!(cartridge.vram.is_empty());
+    }
+
+    // No PRG ROM and no CHR ROM. Only defined behaviour with checked memory.
+    #[cfg(feature = "safe_mem")]
+    #[test]
+    fn degenerate_rom() {
+        use crate::console::Console;
+
+        let mut rom = b"NES\x1a\x00\x00\x00\x00".to_vec();
+        rom.resize(PRG_ROM_START, 0);
+
+        let cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.read_prg_rom(0xfffc), 0);
+        assert_eq!(cartridge.read_chr_rom(0x0000), 0);
+
+        let mut console = Console::from_bytes(&rom).unwrap();
+        console.run_frames(1);
+    }
+
+    #[test]
+    fn ppu_palette() {
+        let cartridge = Cartridge::empty();
+        assert_eq!(cartridge.ppu_read_location(0x3f00), Location::Palette(0x00));
+        assert_eq!(cartridge.ppu_write_location(0x3f1f), Location::Palette(0x1f));
+        assert_eq!(cartridge.ppu_read_location(0x7f01), Location::Palette(0x01));
+    }
+
+    mod nrom {
+        use crate::bus::Bus;
+
+        use super::*;
+
+        // PRG ROM filled with its bank number, so the halves can be told apart.
+        fn cartridge(banks: u8) -> Cartridge {
+            let mut rom = b"NES\x1a\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+            rom[4] = banks;
+            for bank in 0..banks { rom.extend(vec![0x10 + bank; SIXTEEN_KBYTES]) }
+            rom.extend(vec![0; EIGHT_KBYTES]);
+            Cartridge::new(&rom).unwrap()
+        }
+
+        #[test]
+        fn mirrored_16k() {
+            let cartridge = cartridge(1);
+            assert_eq!(cartridge.cpu_read_location(0x8000), Location::PrgRom(0x0000));
+            assert_eq!(cartridge.cpu_read_location(0xc000), Location::PrgRom(0x0000));
+            assert_eq!(cartridge.cpu_read_location(0xfffc), Location::PrgRom(0x3ffc));
+
+            let bus = Bus::with_cartridge(cartridge);
+            assert_eq!(bus.peek_cpu(0x8000), 0x10);
+            assert_eq!(bus.peek_cpu(0xc000), 0x10);
+        }
+
+        #[test]
+        fn distinct_32k() {
+            let cartridge = cartridge(2);
+            assert_eq!(cartridge.cpu_read_location(0xc000), Location::PrgRom(0x4000));
+            assert_eq!(cartridge.cpu_read_location(0xfffc), Location::PrgRom(0x7ffc));
+
+            let bus = Bus::with_cartridge(cartridge);
+            assert_eq!(bus.peek_cpu(0x8000), 0x10);
+            assert_eq!(bus.peek_cpu(0xc000), 0x11);
+        }
+    }
+
+    #[test]
+    fn describe_location() {
+        let cartridge = load_test();
+        assert_eq!(cartridge.describe_location(0x2002), "PPUSTATUS");
+        assert_eq!(cartridge.describe_location(0x3ffa), "PPUSTATUS");
+        assert_eq!(cartridge.describe_location(0x8000), "PRG ROM +0000");
+        assert_eq!(cartridge.describe_location(0x0812), "CPU RAM +0812");
+        assert_eq!(cartridge.describe_location(0x6010), "PRG RAM +0010");
+        assert_eq!(cartridge.describe_location(0x4017), "Controller 2");
+        assert_eq!(cartridge.describe_location(0x4015), "APU +0015");
+        assert_eq!(cartridge.describe_location(0x5000), "Nowhere $5000");
+    }
+
+    mod cnrom {
+        use crate::bus::Bus;
+
+        use super::*;
+
+        // 16kb of PRG ROM and four CHR banks filled with their own number.
+        fn cartridge() -> Cartridge {
+            let mut data = b"NES\x1a".to_vec();
+            data.extend(&[1, 4, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+            let mut prg_rom = vec![0xff; SIXTEEN_KBYTES];
+            prg_rom[0x0000] = 0x01;
+            data.extend(prg_rom);
+
+            for bank in 0..4 { data.extend(vec![bank; EIGHT_KBYTES]); }
+
+            Cartridge::new(&data).unwrap()
+        }
+
+        fn selected_bank(cartridge: Cartridge, addr: u16, data: u8) -> u8 {
+            let mut bus = Bus::with_cartridge(cartridge);
+            bus.write_cpu(addr, data);
+            bus.peek_ppu(0x0123)
+        }
+
+        #[test]
+        fn header() {
+            assert_eq!(cartridge().header().mapper, 3);
+        }
+
+        #[test]
+        fn select_bank() {
+            for bank in 0..4 {
+                assert_eq!(selected_bank(cartridge(), 0x8001, bank), bank);
+            }
+        }
+
+        #[test]
+        fn bus_conflict() {
+            // The ROM has $01 at $8000, so writing $03 there selects bank 1.
+            assert_eq!(selected_bank(cartridge(), 0x8000, 0x03), 0x01);
+        }
+
+        #[test]
+        fn bus_conflict_disabled() {
+            let mut cartridge = cartridge();
+            cartridge.set_bus_conflicts(false);
+            assert_eq!(selected_bank(cartridge, 0x8000, 0x03), 0x03);
+        }
+
+        #[test]
+        fn mirrored_prg_rom() {
+            // The ROM at $C000 is a mirror of $8000.
+            assert_eq!(selected_bank(cartridge(), 0xc000, 0x02), 0x00);
+        }
+    }
+
+    mod chr_tile {
+        use super::*;
+
+        fn cartridge() -> Cartridge {
+            let mut cartridge = Cartridge::empty();
+
+            // Tile 1. Low plane then high plane.
+            let tile = [
+                0xff, 0x00, 0xff, 0x0f, 0x00, 0x00, 0x00, 0x81,
+                0x00, 0xff, 0xff, 0x33, 0x00, 0x00, 0x00, 0x80,
+            ];
+            cartridge.chr_rom[16..32].copy_from_slice(&tile);
+            cartridge
+        }
+
+        #[test]
+        fn decode() {
+            let mut expected = [0; 64];
+            expected[0..8].copy_from_slice(&[1; 8]);
+            expected[8..16].copy_from_slice(&[2; 8]);
+            expected[16..24].copy_from_slice(&[3; 8]);
+            expected[24..32].copy_from_slice(&[0, 0, 2, 2, 1, 1, 3, 3]);
+            expected[56..64].copy_from_slice(&[3, 0, 0, 0, 0, 0, 0, 1]);
+
+            assert_eq!(&cartridge().chr_tile(1)[..], &expected[..]);
+        }
+
+        #[test]
+        fn blank() {
+            assert_eq!(&cartridge().chr_tile(0)[..], &[0; 64][..]);
+        }
+
+        #[test]
+        fn wraps_around() {
+            let cartridge = cartridge();
+            assert_eq!(&cartridge.chr_tile(513)[..], &cartridge.chr_tile(1)[..]);
+        }
+
+        #[test]
+        fn nestest() {
+            let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+            let tile = cartridge.chr_tile(0x41);
+
+            // Every pixel is a palette index built from the matching bits of both planes.
+            for (i, &pixel) in tile.iter().enumerate() {
+                let (x, y) = (i % 8, i / 8);
+                let low = cartridge.read_chr_rom((0x41 * 16 + y) as u16);
+                let high = cartridge.read_chr_rom((0x41 * 16 + y + 8) as u16);
+                let bit = 7 - x;
+                assert_eq!(pixel, (((high >> bit) & 1) << 1) | ((low >> bit) & 1));
+            }
+            assert!(tile.iter().any(|&pixel| pixel != 0));
+        }
+    }
+
+    mod chr_bank {
+        use crate::bus::Bus;
+
+        use super::*;
+
+        // Always maps the second CHR bank.
+        struct Banked;
+
+        impl Mapper for Banked {
+            fn read_cpu(&self, addr: u16) -> Location { Mapper000::new().read_cpu(addr) }
+            fn write_cpu(&self, addr: u16) -> Location { Mapper000::new().write_cpu(addr) }
+
+            fn read_ppu(&self, addr: u16) -> Location {
+                match addr {
+                    0x0000..=0x1fff => Location::ChrRom(addr + EIGHT_KBYTES as u16),
+                    _ => Mapper000::new().read_ppu(addr),
+                }
+            }
+
+            fn write_ppu(&self, addr: u16) -> Location { self.read_ppu(addr) }
+        }
+
+        fn bus() -> Bus {
+            let mut chr_rom = vec![0x11; EIGHT_KBYTES];
+            chr_rom.extend(vec![0x22; EIGHT_KBYTES]);
+
+            let mut cartridge = Cartridge::empty();
+            cartridge.chr_rom = chr_rom;
+            cartridge.mapper = Box::new(Banked);
+
+            Bus::with_cartridge(cartridge)
+        }
+
+        #[test]
+        fn location() {
+            let bus = bus();
+            assert_eq!(bus.cartridge.ppu_read_location(0x0010), Location::ChrRom(0x2010));
+            assert_eq!(bus.peek_ppu(0x0010), 0x22);
+        }
+
+        #[test]
+        fn read_through_ppudata() {
+            let mut bus = bus();
+            bus.write_cpu(0x2006, 0x00);
+            bus.write_cpu(0x2006, 0x10);
+
+            // First read fills the buffer.
+            bus.read_cpu(0x2007);
+            assert_eq!(bus.read_cpu(0x2007), 0x22);
+        }
+
+        #[test]
+        fn four_screen_vram() {
+            let mut bus = bus();
+            bus.cartridge.set_ppu_mirror(PpuMirror::FourScreen);
+            bus.write_ppu(0x2c05, 0x44);
+            assert_eq!(bus.cartridge.read_vram(0x0405), 0x44);
+            assert_eq!(bus.peek_ppu(0x2c05), 0x44);
+            assert_eq!(bus.peek_ppu(0x2405), 0x00);
+        }
+
+        #[test]
+        fn nametable_through_mapper() {
+            let mut bus = bus();
+            bus.write_ppu(0x2005, 0x33);
+            assert_eq!(bus.ppu.peek_vram(0x0005), 0x33);
+            assert_eq!(bus.peek_ppu(0x2005), 0x33);
+        }
+    }
+
+    mod prg_ram_protect {
+        use crate::bus::Bus;
+
+        use super::*;
+
+        // PRG RAM control bits as set by MMC1 and MMC3 registers.
+        struct Protected {
+            enabled: bool,
+            writable: bool,
+        }
+
+        impl Mapper for Protected {
+            fn read_cpu(&self, addr: u16) -> Location { Mapper000::new().read_cpu(addr) }
+            fn write_cpu(&self, addr: u16) -> Location { Mapper000::new().write_cpu(addr) }
+            fn read_ppu(&self, addr: u16) -> Location { Mapper000::new().read_ppu(addr) }
+            fn write_ppu(&self, addr: u16) -> Location { Mapper000::new().write_ppu(addr) }
+
+            fn prg_ram_enabled(&self) -> bool { self.enabled }
+            fn prg_ram_writable(&self) -> bool { self.writable }
+        }
+
+        fn bus(enabled: bool, writable: bool) -> Bus {
+            let mut cartridge = Cartridge::empty();
+            cartridge.prg_ram = vec![0x55; EIGHT_KBYTES];
+            cartridge.mapper = Box::new(Protected { enabled, writable });
+            Bus::with_cartridge(cartridge)
+        }
+
+        #[test]
+        fn enabled() {
+            let mut bus = bus(true, true);
+            bus.write_cpu(0x6010, 0x42);
+            assert_eq!(bus.read_cpu(0x6010), 0x42);
+        }
+
+        #[test]
+        fn disabled() {
+            let mut bus = bus(false, true);
+            assert_eq!(bus.cartridge.cpu_read_location(0x6010), Location::Nowhere(0x6010));
+            bus.write_cpu(0x6010, 0x42);
+            assert_eq!(bus.cartridge.read_prg_ram(0x0010), 0x55);
+        }
+
+        #[test]
+        fn write_protected() {
+            let mut bus = bus(true, false);
+            bus.write_cpu(0x6010, 0x42);
+            assert_eq!(bus.read_cpu(0x6010), 0x55);
+        }
+    }
+
+    mod custom {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        use crate::bus::Bus;
+        use crate::cpu::Cpu;
+
+        use super::*;
+
+        // 32kb of PRG ROM and nothing else.
+        #[derive(Debug)]
+        struct Flat {
+            prg_rom: Vec<u8>,
+        }
+
+        impl CartridgeLike for Flat {
+            fn cpu_read_location(&self, addr: u16) -> Location {
+                match addr {
+                    0x8000..=0xffff => Location::PrgRom(addr - 0x8000),
+                    _ => console_read_location(addr),
+                }
+            }
+
+            fn cpu_write_location(&self, addr: u16) -> Location { console_write_location(addr) }
+
+            fn ppu_read_location(&self, addr: u16) -> Location { Location::ChrRom(addr & 0x1fff) }
+            fn ppu_write_location(&self, addr: u16) -> Location { Location::ChrRom(addr & 0x1fff) }
+
+            fn read_prg_rom(&self, addr: u16) -> u8 { self.prg_rom[addr as usize] }
+            fn read_chr_rom(&self, _addr: u16) -> u8 { 0 }
+        }
+
+        #[test]
+        fn run_instructions() {
+            let mut prg_rom = vec![0xea; 2 * SIXTEEN_KBYTES];
+
+            // LDA #$42; STA $10; INX; JMP $8004
+            prg_rom[..8].copy_from_slice(&[0xa9, 0x42, 0x85, 0x10, 0xe8, 0x4c, 0x04, 0x80]);
+
+            // Reset vector
+            prg_rom[0x7ffc] = 0x00;
+            prg_rom[0x7ffd] = 0x80;
+
+            let bus = Rc::new(RefCell::new(Bus::with_cartridge(Flat { prg_rom })));
+            let mut cpu = Cpu::new(bus.clone());
+            assert_eq!(cpu.reg.get_pc(), 0x8000);
+
+            for _ in 0..5 { cpu.step_instruction(); }
+
+            assert_eq!(bus.borrow().peek_cpu(0x0010), 0x42);
+            assert_eq!(cpu.reg.get_x(), 0x02);
+        }
+    }
 }
@@ -5,27 +5,199 @@ use std::io::Read;
 use std::rc::Rc;
 
 use crate::bus::Bus;
+use crate::bus::controller::Buttons;
+use crate::bus::cpu_data::RamInit;
+use crate::bus::game_genie::GameGenie;
+use crate::bus::game_genie::GgError;
 use crate::cartridge::Cartridge;
+use crate::cartridge::CartridgeLike;
+use crate::cartridge::LoadError;
 use crate::cpu::Cpu;
 use crate::cpu::cycle;
+use crate::movie::Movie;
+use crate::movie::Playback;
+use crate::nsf::Nsf;
+use crate::nsf::NsfCartridge;
+use crate::ppu;
 use crate::ppu::Ppu;
+use crate::ppu::Region;
+use crate::ui::palette;
+use crate::ui::palette::Palette;
+
+// CPU and PPU counters taken at the same moment.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Timing {
+    pub cpu_cycle: u32,
+    pub ppu_frame: u32,
+    pub scanline: i32,
+    pub dot: u32,
+}
 
 pub struct Console {
     pub bus: Rc<RefCell<Bus>>,
     pub cpu: Cpu,
     pub ppu: Ppu,
+
+    // Colors used by front ends. Set by the builder.
+    pub palette: Option<Palette>,
+
+    // Input log
+    recording: Option<Movie>,
+    playback: Option<Playback>,
 }
 
 impl Console {
-    pub fn new(cartridge: Cartridge) -> Self {
-        let bus = Rc::new(RefCell::new(Bus::with_cartridge(cartridge)));
+    pub fn new(cartridge: impl CartridgeLike + 'static) -> Self {
+        Self::new_with_power_state(cartridge, RamInit::Zeroed)
+    }
+
+    // Build a console specifying how its RAM looks like when turned on.
+    pub fn new_with_power_state(cartridge: impl CartridgeLike + 'static, ram_init: RamInit) -> Self {
+        let bus = Rc::new(RefCell::new(Bus::with_power_state(cartridge, ram_init)));
         let cpu = Cpu::new(bus.clone());
-        let ppu = Ppu::new(bus.clone());
+        let mut ppu = Ppu::new(bus.clone());
+        ppu.start_warmup();
 
         Self {
             bus,
             cpu,
             ppu,
+            palette: None,
+            recording: None,
+            playback: None,
+        }
+    }
+
+    // Change the television standard. Affects frame timing.
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.region = region;
+        self.bus.borrow_mut().apu.region = region;
+    }
+
+    // Buttons currently held on a controller. Port is either 0 or 1.
+    // Ignored while a movie is playing.
+    pub fn set_buttons(&mut self, port: u8, buttons: Buttons) {
+        if self.playback.is_some() { return; }
+        self.bus.borrow_mut().controllers[port as usize % 2].buttons = buttons;
+    }
+
+    // Buttons fired automatically while held. Rates are set on the controller.
+    pub fn set_turbo_buttons(&mut self, port: u8, buttons: Buttons) {
+        if self.playback.is_some() { return; }
+        self.bus.borrow_mut().controllers[port as usize % 2].turbo_buttons = buttons;
+    }
+
+    // Emulate controller reads being corrupted by DMC fetches. Off by default.
+    pub fn set_dmc_read_corruption(&mut self, enabled: bool) { self.cpu.dmc_read_corruption = enabled }
+
+    // Record the input of every frame from now on.
+    pub fn start_recording(&mut self) { self.recording = Some(Movie::new()) }
+
+    pub fn stop_recording(&mut self) -> Movie { self.recording.take().unwrap_or_default() }
+
+    // Replace live input with the movie until it's over.
+    pub fn play_movie(&mut self, movie: Movie) { self.playback = Some(Playback::new(movie)) }
+
+    pub fn is_playing_movie(&self) -> bool { self.playback.is_some() }
+
+    // Input is sampled or replayed when a frame starts.
+    fn next_movie_frame(&mut self) {
+        if let Some(playback) = self.playback.as_mut() {
+            match playback.next_frame() {
+                Some((first, second)) => {
+                    // Movies already have autofire applied.
+                    let mut bus = self.bus.borrow_mut();
+                    bus.controllers[0].buttons = Buttons::from_bits(first);
+                    bus.controllers[1].buttons = Buttons::from_bits(second);
+                    bus.controllers.iter_mut().for_each(|controller| controller.turbo_buttons = Buttons::empty());
+                }
+                None => self.playback = None,
+            }
+        }
+
+        self.bus.borrow_mut().controllers.iter_mut().for_each(|controller| controller.next_frame());
+
+        if let Some(movie) = self.recording.as_mut() {
+            let bus = self.bus.borrow();
+            movie.frames.push((bus.controllers[0].pressed().bits(), bus.controllers[1].pressed().bits()));
+        }
+    }
+
+    // Build a console from a ROM already in memory.
+    pub fn from_bytes(rom: &[u8]) -> Result<Self, LoadError> {
+        Ok(Self::new(Cartridge::new(rom)?))
+    }
+
+    pub fn from_file(file: &str) -> Result<Self, LoadError> {
+        Ok(Self::new(Cartridge::from_file(file)?))
+    }
+
+    pub fn builder() -> ConsoleBuilder { ConsoleBuilder::new() }
+
+    // Play a song of a NSF file. Song is zero based.
+    pub fn from_nsf(data: &[u8], song: u8) -> Result<Self, LoadError> {
+        let nsf = Nsf::new(data)?;
+        let region = nsf.region;
+
+        let mut console = Self::new(NsfCartridge::new(nsf, song)?);
+        console.set_region(region);
+        Ok(console)
+    }
+
+    // Press the reset button. Runs the reset vector again but keeps RAM intact.
+    pub fn reset(&mut self) {
+        self.bus.borrow_mut().soft_reset();
+        self.cpu.reset();
+        self.ppu.start_warmup();
+    }
+
+    // Swap the cartridge and press reset. Like switching games on a console that keeps power.
+    pub fn load_cartridge(&mut self, cartridge: impl CartridgeLike + 'static) {
+        self.bus.borrow_mut().cartridge = Box::new(cartridge);
+        self.reset();
+    }
+
+    // Read the CPU bus without side effects.
+    pub fn peek(&self, addr: u16) -> u8 { self.bus.borrow().peek_cpu(addr) }
+
+    // Write to the CPU bus like the CPU would. Registers react as usual and ROM ignores it.
+    pub fn poke(&mut self, addr: u16, data: u8) { self.bus.borrow_mut().write_cpu(addr, data) }
+
+    // Patch the game with a Game Genie code. Codes stay until cleared, even across resets.
+    pub fn add_game_genie(&mut self, code: &str) -> Result<(), GgError> {
+        let code = GameGenie::decode(code)?;
+        self.bus.borrow_mut().game_genie.push(code);
+        Ok(())
+    }
+
+    pub fn clear_game_genie(&mut self) { self.bus.borrow_mut().game_genie.clear() }
+
+    // Current frame as RGBA. Used by front ends that don't rely on the image crate.
+    pub fn frame_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut res = vec![0; ppu::SCREEN_SIZE * palette::RGBA_DEPTH];
+        palette.map_rgba(&self.ppu.screen, &mut res);
+        res
+    }
+
+    // Snapshot of the CPU address space. Uses peeks, so no register is affected.
+    pub fn dump_cpu(&self, start: u16, len: u16) -> Vec<u8> {
+        let bus = self.bus.borrow();
+        (0..len).map(|offset| bus.peek_cpu(start.wrapping_add(offset))).collect()
+    }
+
+    // Snapshot of the PPU address space. Nametables are resolved by the cartridge mirroring.
+    pub fn dump_ppu(&self, start: u16, len: u16) -> Vec<u8> {
+        let bus = self.bus.borrow();
+        (0..len).map(|offset| bus.peek_ppu(start.wrapping_add(offset))).collect()
+    }
+
+    // Where the CPU and PPU are. Used by trace logs.
+    pub fn timing(&self) -> Timing {
+        Timing {
+            cpu_cycle: self.cpu.get_clock(),
+            ppu_frame: self.ppu.frame,
+            scanline: self.ppu.scanline,
+            dot: self.ppu.dot,
         }
     }
 
@@ -42,10 +214,11 @@ impl Console {
         loop {
             let mut should_finish = false;
 
-            // Every third PPU clock, run one cycle of the CPU.
-            if self.ppu.clock % 3 == 0 {
-                // Save logs on the first cycle and report it on the last
+            // Every third PPU clock, run one cycle of the CPU. PAL is a little slower.
+            if self.ppu.region.is_cpu_dot(self.ppu.clock) {
+                // Save logs on the first cycle and report it on the last. DMC fetches don't move the cycle.
                 match self.cpu.reg.get_cycle() {
+                    _ if self.cpu.is_dmc_dma() => {}
                     cycle::FIRST => {
                         let reg = &self.cpu.reg;
                         self.cpu.log.set_reg(reg.clone_with_pc(reg.get_pc() - 1));
@@ -68,9 +241,12 @@ impl Console {
                 }
 
                 self.cpu.step();
+                self.bus.borrow_mut().apu.step();
             }
 
+            let frame = self.ppu.frame;
             self.ppu.step();
+            if self.ppu.frame != frame { self.next_movie_frame() }
 
             if should_finish || condition(&self) { break; }
         }
@@ -101,6 +277,37 @@ impl Console {
             Self::dismiss_log);
     }
 
+    // Run frame after frame, yielding each finished screen. Endless, so use take to stop.
+    pub fn frames(&mut self) -> Frames<'_> { Frames { console: self } }
+
+    // Run an exact number of CPU cycles. The PPU and APU are kept in step with them.
+    pub fn run_cycles(&mut self, cycles: u64) {
+        if cycles == 0 { return; }
+
+        let mut remaining = cycles;
+        let mut clock = self.cpu.get_clock();
+
+        self.run_until(
+            |console| {
+                let now = console.cpu.get_clock();
+                if now != clock {
+                    clock = now;
+                    remaining -= 1;
+                }
+
+                // Stop right before the next CPU cycle so the PPU has caught up.
+                remaining == 0 && console.ppu.region.is_cpu_dot(console.ppu.clock)
+            },
+            Self::dismiss_log);
+    }
+
+    // Run until the PPU sets the vblank flag, right after dot 1 of scanline 241.
+    pub fn run_until_vblank(&mut self) {
+        self.run_until(
+            |console| console.ppu.scanline == 241 && console.ppu.dot == 2,
+            Self::dismiss_log);
+    }
+
     pub fn run_log(&mut self, log: &str) {
         let mut log_file = File::open(log).unwrap();
         let mut log = String::new();
@@ -123,9 +330,463 @@ impl Console {
     }
 }
 
+// Screens of the following frames. Copies, since the console keeps drawing on its own.
+pub struct Frames<'a> {
+    console: &'a mut Console,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = [u8; ppu::SCREEN_SIZE];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.console.run_frames(1);
+        Some(self.console.ppu.screen)
+    }
+}
+
+// Console with every setting available when turning it on.
+pub struct ConsoleBuilder {
+    rom: Vec<u8>,
+    region: Region,
+    ram_init: RamInit,
+    palette: Option<Palette>,
+}
+
+impl ConsoleBuilder {
+    pub fn new() -> Self {
+        Self {
+            rom: Vec::new(),
+            region: Region::Ntsc,
+            ram_init: RamInit::Zeroed,
+            palette: None,
+        }
+    }
+
+    pub fn rom_bytes(mut self, rom: &[u8]) -> Self {
+        self.rom = rom.to_vec();
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.ram_init = ram_init;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    // Fails if the ROM can't be loaded. Building without a ROM is an invalid header.
+    pub fn build(self) -> Result<Console, LoadError> {
+        let cartridge = Cartridge::new(&self.rom)?;
+
+        let mut console = Console::new_with_power_state(cartridge, self.ram_init);
+        console.set_region(self.region);
+        console.palette = self.palette;
+
+        Ok(console)
+    }
+}
+
+impl Default for ConsoleBuilder {
+    fn default() -> Self { Self::new() }
+}
+
 impl fmt::Debug for Console {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         writeln!(formatter, "{:?}\n", self.cpu)?;
         write!(formatter, "{:?}", self.bus)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::controller::TurboConfig;
+    use crate::cartridge::PpuMirror;
+    use crate::utils::bits;
+
+    use super::*;
+
+    fn palette() -> Palette { Palette::from_file("tests/resources/palettes/RP2C03.pal").unwrap() }
+
+    #[test]
+    fn from_bytes() {
+        let mut rom = Vec::new();
+        File::open("tests/resources/cpu/nestest.nes").unwrap().read_to_end(&mut rom).unwrap();
+
+        let console = Console::from_bytes(&rom).unwrap();
+        assert_eq!(console.cpu.reg.get_pc(), 0xc004);
+    }
+
+    #[test]
+    fn from_bytes_invalid() {
+        assert!(match Console::from_bytes(&b"666"[..]) {
+            Err(LoadError::InvalidHeader) => true,
+            _ => false
+        });
+    }
+
+    #[test]
+    fn timing_follows_instruction() {
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+
+        // Move to the end of an instruction
+        let next_instruction = |console: &mut Console| {
+            console.run_until(|console| console.cpu.reg.get_cycle() != cycle::LAST, Console::dismiss_log);
+            console.run_until(|console| console.cpu.reg.get_cycle() == cycle::LAST, Console::dismiss_log);
+        };
+
+        next_instruction(&mut console);
+        let before = console.timing();
+        next_instruction(&mut console);
+        let after = console.timing();
+
+        let dots = |timing: Timing| timing.scanline as i64 * 341 + timing.dot as i64;
+        let cycles = after.cpu_cycle - before.cpu_cycle;
+        assert!(cycles >= 2);
+        assert_eq!(after.ppu_frame, before.ppu_frame);
+        assert_eq!(dots(after) - dots(before), 3 * cycles as i64);
+    }
+
+    #[test]
+    fn reset_keeps_ram() {
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+        console.run_frames(2);
+
+        {
+            let mut bus = console.bus.borrow_mut();
+            bus.write_cpu(0x0010, 0x42);
+            bus.write_cpu(0x6000, 0x24);
+            bus.write_cpu(0x2000, 0x80);
+        }
+
+        console.reset();
+
+        let bus = console.bus.borrow();
+        assert_eq!(console.cpu.reg.get_pc(), bits::word(bus.peek_cpu(0xfffd), bus.peek_cpu(0xfffc)));
+        assert_eq!(bus.peek_cpu(0x0010), 0x42);
+        assert_eq!(bus.peek_cpu(0x6000), 0x24);
+        assert!(!bus.ppu.generate_nmi_at_vblank);
+    }
+
+    #[test]
+    fn run_cycles() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        console.run_frames(1);
+
+        // Frames may end between CPU cycles. Afterwards every cycle takes three dots.
+        console.run_cycles(1);
+
+        for &cycles in &[1, 2, 3, 113, 29_781, 100_000] {
+            let clock = console.cpu.get_clock();
+            let ppu_clock = console.ppu.clock;
+            console.run_cycles(cycles);
+
+            assert_eq!(console.cpu.get_clock(), clock + cycles as u32);
+            assert_eq!(console.ppu.clock - ppu_clock, 3 * cycles as u32);
+        }
+
+        let clock = console.cpu.get_clock();
+        console.run_cycles(0);
+        assert_eq!(console.cpu.get_clock(), clock);
+    }
+
+    #[test]
+    fn load_cartridge() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        console.run_frames(2);
+        assert_eq!(console.bus.borrow().peek_cpu(0xfffc), 0x04);
+
+        let cartridge = Cartridge::from_file("tests/resources/cpu/instr_test/01-basics.nes").unwrap();
+        console.load_cartridge(cartridge);
+        assert_eq!(console.cpu.reg.get_pc(), 0xe683);
+
+        // Runs the new game.
+        console.run_frames(2);
+        assert_ne!(console.cpu.reg.get_pc(), 0xe683);
+    }
+
+    #[test]
+    fn peek_poke() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+
+        console.poke(0x0123, 0x45);
+        assert_eq!(console.peek(0x0123), 0x45);
+        assert_eq!(console.peek(0x0923), 0x45);
+
+        // PRG RAM on the cartridge.
+        console.poke(0x6010, 0x67);
+        assert_eq!(console.peek(0x6010), 0x67);
+
+        // ROM can't be changed.
+        let rom = console.peek(0xc000);
+        console.poke(0xc000, rom.wrapping_add(1));
+        assert_eq!(console.peek(0xc000), rom);
+    }
+
+    #[test]
+    fn game_genie() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let original = console.bus.borrow().peek_cpu(0x91d9);
+
+        assert_eq!(console.add_game_genie("SXIOPB"), Err(GgError::Letter('B')));
+        assert_eq!(console.add_game_genie("SXIOPO"), Ok(()));
+        assert_eq!(console.bus.borrow().peek_cpu(0x91d9), 0xad);
+
+        console.reset();
+        assert_eq!(console.bus.borrow().peek_cpu(0x91d9), 0xad);
+
+        console.clear_game_genie();
+        assert_eq!(console.bus.borrow().peek_cpu(0x91d9), original);
+    }
+
+    fn ram(console: &Console) -> Vec<u8> {
+        let bus = console.bus.borrow();
+        (0..0x0800).map(|addr| bus.peek_cpu(addr)).collect()
+    }
+
+    #[test]
+    fn power_state_filled() {
+        let console = Console::new_with_power_state(Cartridge::empty(), RamInit::Filled(0xea));
+        assert!(ram(&console).iter().all(|&data| data == 0xea));
+    }
+
+    #[test]
+    fn power_state_random() {
+        let first = Console::new_with_power_state(Cartridge::empty(), RamInit::Random(0x1234));
+        let second = Console::new_with_power_state(Cartridge::empty(), RamInit::Random(0x1234));
+        let other = Console::new_with_power_state(Cartridge::empty(), RamInit::Random(0x4321));
+
+        assert_eq!(ram(&first), ram(&second));
+        assert_ne!(ram(&first), ram(&other));
+        assert!(ram(&first).iter().any(|&data| data != 0));
+    }
+
+    mod builder {
+        use super::*;
+
+        fn rom() -> Vec<u8> {
+            let mut rom = Vec::new();
+            File::open("tests/resources/cpu/nestest.nes").unwrap().read_to_end(&mut rom).unwrap();
+            rom
+        }
+
+        #[test]
+        fn defaults() {
+            let console = Console::builder().rom_bytes(&rom()).build().unwrap();
+            assert_eq!(console.ppu.region, Region::Ntsc);
+            assert!(console.palette.is_none());
+            assert!(ram(&console).iter().all(|&data| data == 0));
+        }
+
+        #[test]
+        fn settings() {
+            let console = Console::builder()
+                .rom_bytes(&rom())
+                .region(Region::Pal)
+                .ram_init(RamInit::Filled(0xea))
+                .palette(palette())
+                .build()
+                .unwrap();
+
+            assert_eq!(console.ppu.region, Region::Pal);
+            assert_eq!(console.bus.borrow().apu.region, Region::Pal);
+            assert!(ram(&console).iter().all(|&data| data == 0xea));
+            assert!(console.palette.is_some());
+            assert_eq!(console.cpu.reg.get_pc(), 0xc004);
+        }
+
+        #[test]
+        fn random_ram() {
+            let build = |seed| Console::builder().rom_bytes(&rom()).ram_init(RamInit::Random(seed)).build().unwrap();
+            assert_eq!(ram(&build(0x1234)), ram(&Console::new_with_power_state(
+                Cartridge::new(&rom()).unwrap(), RamInit::Random(0x1234))));
+            assert_ne!(ram(&build(0x1234)), ram(&build(0x4321)));
+        }
+
+        #[test]
+        fn without_rom() {
+            assert!(match Console::builder().build() {
+                Err(LoadError::InvalidHeader) => true,
+                _ => false
+            });
+        }
+    }
+
+    #[test]
+    fn frames() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let frame = console.ppu.frame;
+
+        let screens = console.frames().skip(10).take(3).collect::<Vec<_>>();
+        assert_eq!(screens.len(), 3);
+        assert_eq!(console.ppu.frame, frame + 13);
+        assert_eq!(&screens[2][..], &console.ppu.screen[..]);
+
+        // nestest shows its menu by then, so the screen is not blank.
+        assert!(screens.iter().all(|screen| screen.iter().all(|&color| color < 0x40)));
+        assert!(screens[2].iter().any(|&color| color != screens[2][0]));
+    }
+
+    #[test]
+    fn run_until_vblank() {
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+
+        for _ in 0..2 {
+            let frame = console.ppu.frame;
+            console.run_until_vblank();
+
+            assert_eq!((console.ppu.scanline, console.ppu.dot), (241, 2));
+            assert_eq!(console.ppu.frame, frame + 1);
+            assert!(bits::is_set(console.bus.borrow().ppu.peek_status(), 7));
+        }
+    }
+
+    #[test]
+    fn set_buttons() {
+        let mut console = Console::new(Cartridge::empty());
+        console.set_buttons(0, Buttons::B | Buttons::UP);
+        console.set_buttons(1, Buttons::SELECT);
+
+        let mut bus = console.bus.borrow_mut();
+        bus.write_cpu(0x4016, 1);
+        bus.write_cpu(0x4016, 0);
+
+        let first = (0..8).map(|_| bus.read_cpu(0x4016) & 1).collect::<Vec<_>>();
+        let second = (0..8).map(|_| bus.read_cpu(0x4017) & 1).collect::<Vec<_>>();
+        assert_eq!(first, vec![0, 1, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(second, vec![0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    fn read_controller(console: &Console, addr: u16) -> u8 {
+        let mut bus = console.bus.borrow_mut();
+        bus.write_cpu(0x4016, 1);
+        bus.write_cpu(0x4016, 0);
+        (0..8).fold(0, |res, i| res | ((bus.read_cpu(addr) & 1) << i))
+    }
+
+    #[test]
+    fn turbo_recorded() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        console.bus.borrow_mut().controllers[0].turbo = TurboConfig { a_rate: 1, b_rate: 0 };
+        console.set_turbo_buttons(0, Buttons::A | Buttons::B);
+        console.set_buttons(0, Buttons::START);
+
+        console.start_recording();
+        let mut reads = vec![];
+        for _ in 0..4 {
+            console.run_frames(1);
+            reads.push(read_controller(&console, 0x4016));
+        }
+
+        assert_eq!(reads, vec![0x09, 0x08, 0x09, 0x08]);
+        assert_eq!(console.stop_recording().frames, reads.iter().map(|&read| (read, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn movie_record_and_play() {
+        let rom = std::fs::read("tests/resources/cpu/nestest.nes").unwrap();
+        let input = [
+            (Buttons::A, Buttons::empty()),
+            (Buttons::START | Buttons::DOWN, Buttons::B),
+            (Buttons::empty(), Buttons::LEFT | Buttons::RIGHT),
+        ];
+
+        let mut console = Console::from_bytes(&rom).unwrap();
+        let mut reads = vec![];
+        console.start_recording();
+        for &(first, second) in &input {
+            console.set_buttons(0, first);
+            console.set_buttons(1, second);
+            console.run_frames(1);
+            reads.push((read_controller(&console, 0x4016), read_controller(&console, 0x4017)));
+        }
+        let movie = console.stop_recording();
+        assert_eq!(movie.frames, vec![(0x01, 0x00), (0x28, 0x02), (0x00, 0xc0)]);
+        assert_eq!(movie.frames, reads);
+
+        let mut console = Console::from_bytes(&rom).unwrap();
+        console.play_movie(movie);
+        let mut replay = vec![];
+        for _ in 0..input.len() {
+            // Live input is ignored.
+            console.set_buttons(0, Buttons::SELECT);
+            console.run_frames(1);
+            replay.push((read_controller(&console, 0x4016), read_controller(&console, 0x4017)));
+        }
+        assert_eq!(replay, reads);
+
+        // Live input comes back after the movie.
+        console.run_frames(1);
+        assert!(!console.is_playing_movie());
+        console.set_buttons(0, Buttons::SELECT);
+        assert_eq!(read_controller(&console, 0x4016), 0x04);
+    }
+
+    #[test]
+    fn dump_cpu() {
+        let console = Console::new(Cartridge::empty());
+        {
+            let mut bus = console.bus.borrow_mut();
+            for addr in 0x0200..0x0208 { bus.write_cpu(addr, addr as u8 * 3) }
+        }
+
+        assert_eq!(console.dump_cpu(0x0202, 4), vec![0x06, 0x09, 0x0c, 0x0f]);
+
+        // RAM is mirrored.
+        assert_eq!(console.dump_cpu(0x0a00, 2), vec![0x00, 0x03]);
+    }
+
+    #[test]
+    fn dump_cpu_keeps_vblank() {
+        let console = Console::new(Cartridge::empty());
+        console.bus.borrow_mut().ppu.vblank_set();
+
+        assert_eq!(console.dump_cpu(0x2000, 8)[2] & 0x80, 0x80);
+        assert!(console.bus.borrow().ppu.is_vblank());
+    }
+
+    #[test]
+    fn dump_ppu_mirroring() {
+        let console = Console::new(Cartridge::empty());
+        {
+            let mut bus = console.bus.borrow_mut();
+            bus.cartridge.set_ppu_mirror(PpuMirror::Horizontal);
+            bus.write_ppu(0x2003, 0x11);
+            bus.write_ppu(0x2804, 0x22);
+        }
+
+        assert_eq!(console.dump_ppu(0x2002, 3), vec![0x00, 0x11, 0x00]);
+        assert_eq!(console.dump_ppu(0x2403, 1), vec![0x11]);
+        assert_eq!(console.dump_ppu(0x2c04, 1), vec![0x22]);
+    }
+
+    #[test]
+    fn frame_rgba_len() {
+        let console = Console::new(Cartridge::empty());
+        assert_eq!(console.frame_rgba(&palette()).len(), 245_760);
+    }
+
+    #[test]
+    fn frame_rgba_color() {
+        let mut palette_file = File::open("tests/resources/palettes/RP2C03.pal").unwrap();
+        let mut rgb = [0_u8; 0x40 * 3];
+        palette_file.read_exact(&mut rgb).unwrap();
+
+        let mut console = Console::new(Cartridge::empty());
+        console.ppu.screen[1] = 0x21;
+
+        let frame = console.frame_rgba(&palette());
+        assert_eq!(frame[4..8], [rgb[0x63], rgb[0x64], rgb[0x65], 0xff]);
+        assert_eq!(frame[0..4], [rgb[0], rgb[1], rgb[2], 0xff]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::utils::bits;
+
+use self::Mode::*;
+
+// Addressing mode as seen in the instruction stream.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Mode {
+    Imp,
+    Acc,
+    Imm,
+    Zp,
+    Zpx,
+    Zpy,
+    Abs,
+    Abx,
+    Aby,
+    Ind,
+    Izx,
+    Izy,
+    Rel,
+}
+
+impl Mode {
+    // Bytes taken by the instruction, counting the opcode.
+    pub fn size(self) -> u16 {
+        match self {
+            Imp | Acc => 1,
+            Imm | Zp | Zpx | Zpy | Izx | Izy | Rel => 2,
+            Abs | Abx | Aby | Ind => 3,
+        }
+    }
+}
+
+// Mnemonic and addressing mode of every opcode.
+const OPCODES: [(&str, Mode); 256] = [
+    ("BRK", Imp), ("ORA", Izx), ("KIL", Imp), ("SLO", Izx), // 0x00
+    ("NOP", Zp), ("ORA", Zp), ("ASL", Zp), ("SLO", Zp), // 0x04
+    ("PHP", Imp), ("ORA", Imm), ("ASL", Acc), ("ANC", Imm), // 0x08
+    ("NOP", Abs), ("ORA", Abs), ("ASL", Abs), ("SLO", Abs), // 0x0C
+    ("BPL", Rel), ("ORA", Izy), ("KIL", Imp), ("SLO", Izy), // 0x10
+    ("NOP", Zpx), ("ORA", Zpx), ("ASL", Zpx), ("SLO", Zpx), // 0x14
+    ("CLC", Imp), ("ORA", Aby), ("NOP", Imp), ("SLO", Aby), // 0x18
+    ("NOP", Abx), ("ORA", Abx), ("ASL", Abx), ("SLO", Abx), // 0x1C
+    ("JSR", Abs), ("AND", Izx), ("KIL", Imp), ("RLA", Izx), // 0x20
+    ("BIT", Zp), ("AND", Zp), ("ROL", Zp), ("RLA", Zp), // 0x24
+    ("PLP", Imp), ("AND", Imm), ("ROL", Acc), ("ANC", Imm), // 0x28
+    ("BIT", Abs), ("AND", Abs), ("ROL", Abs), ("RLA", Abs), // 0x2C
+    ("BMI", Rel), ("AND", Izy), ("KIL", Imp), ("RLA", Izy), // 0x30
+    ("NOP", Zpx), ("AND", Zpx), ("ROL", Zpx), ("RLA", Zpx), // 0x34
+    ("SEC", Imp), ("AND", Aby), ("NOP", Imp), ("RLA", Aby), // 0x38
+    ("NOP", Abx), ("AND", Abx), ("ROL", Abx), ("RLA", Abx), // 0x3C
+    ("RTI", Imp), ("EOR", Izx), ("KIL", Imp), ("SRE", Izx), // 0x40
+    ("NOP", Zp), ("EOR", Zp), ("LSR", Zp), ("SRE", Zp), // 0x44
+    ("PHA", Imp), ("EOR", Imm), ("LSR", Acc), ("ALR", Imm), // 0x48
+    ("JMP", Abs), ("EOR", Abs), ("LSR", Abs), ("SRE", Abs), // 0x4C
+    ("BVC", Rel), ("EOR", Izy), ("KIL", Imp), ("SRE", Izy), // 0x50
+    ("NOP", Zpx), ("EOR", Zpx), ("LSR", Zpx), ("SRE", Zpx), // 0x54
+    ("CLI", Imp), ("EOR", Aby), ("NOP", Imp), ("SRE", Aby), // 0x58
+    ("NOP", Abx), ("EOR", Abx), ("LSR", Abx), ("SRE", Abx), // 0x5C
+    ("RTS", Imp), ("ADC", Izx), ("KIL", Imp), ("RRA", Izx), // 0x60
+    ("NOP", Zp), ("ADC", Zp), ("ROR", Zp), ("RRA", Zp), // 0x64
+    ("PLA", Imp), ("ADC", Imm), ("ROR", Acc), ("ARR", Imm), // 0x68
+    ("JMP", Ind), ("ADC", Abs), ("ROR", Abs), ("RRA", Abs), // 0x6C
+    ("BVS", Rel), ("ADC", Izy), ("KIL", Imp), ("RRA", Izy), // 0x70
+    ("NOP", Zpx), ("ADC", Zpx), ("ROR", Zpx), ("RRA", Zpx), // 0x74
+    ("SEI", Imp), ("ADC", Aby), ("NOP", Imp), ("RRA", Aby), // 0x78
+    ("NOP", Abx), ("ADC", Abx), ("ROR", Abx), ("RRA", Abx), // 0x7C
+    ("NOP", Imm), ("STA", Izx), ("NOP", Imm), ("SAX", Izx), // 0x80
+    ("STY", Zp), ("STA", Zp), ("STX", Zp), ("SAX", Zp), // 0x84
+    ("DEY", Imp), ("NOP", Imm), ("TXA", Imp), ("XAA", Imm), // 0x88
+    ("STY", Abs), ("STA", Abs), ("STX", Abs), ("SAX", Abs), // 0x8C
+    ("BCC", Rel), ("STA", Izy), ("KIL", Imp), ("AHX", Izy), // 0x90
+    ("STY", Zpx), ("STA", Zpx), ("STX", Zpy), ("SAX", Zpy), // 0x94
+    ("TYA", Imp), ("STA", Aby), ("TXS", Imp), ("TAS", Aby), // 0x98
+    ("SHY", Abx), ("STA", Abx), ("SHX", Aby), ("AHX", Aby), // 0x9C
+    ("LDY", Imm), ("LDA", Izx), ("LDX", Imm), ("LAX", Izx), // 0xA0
+    ("LDY", Zp), ("LDA", Zp), ("LDX", Zp), ("LAX", Zp), // 0xA4
+    ("TAY", Imp), ("LDA", Imm), ("TAX", Imp), ("LAX", Imm), // 0xA8
+    ("LDY", Abs), ("LDA", Abs), ("LDX", Abs), ("LAX", Abs), // 0xAC
+    ("BCS", Rel), ("LDA", Izy), ("KIL", Imp), ("LAX", Izy), // 0xB0
+    ("LDY", Zpx), ("LDA", Zpx), ("LDX", Zpy), ("LAX", Zpy), // 0xB4
+    ("CLV", Imp), ("LDA", Aby), ("TSX", Imp), ("LAS", Aby), // 0xB8
+    ("LDY", Abx), ("LDA", Abx), ("LDX", Aby), ("LAX", Aby), // 0xBC
+    ("CPY", Imm), ("CMP", Izx), ("NOP", Imm), ("DCP", Izx), // 0xC0
+    ("CPY", Zp), ("CMP", Zp), ("DEC", Zp), ("DCP", Zp), // 0xC4
+    ("INY", Imp), ("CMP", Imm), ("DEX", Imp), ("AXS", Imm), // 0xC8
+    ("CPY", Abs), ("CMP", Abs), ("DEC", Abs), ("DCP", Abs), // 0xCC
+    ("BNE", Rel), ("CMP", Izy), ("KIL", Imp), ("DCP", Izy), // 0xD0
+    ("NOP", Zpx), ("CMP", Zpx), ("DEC", Zpx), ("DCP", Zpx), // 0xD4
+    ("CLD", Imp), ("CMP", Aby), ("NOP", Imp), ("DCP", Aby), // 0xD8
+    ("NOP", Abx), ("CMP", Abx), ("DEC", Abx), ("DCP", Abx), // 0xDC
+    ("CPX", Imm), ("SBC", Izx), ("NOP", Imm), ("ISC", Izx), // 0xE0
+    ("CPX", Zp), ("SBC", Zp), ("INC", Zp), ("ISC", Zp), // 0xE4
+    ("INX", Imp), ("SBC", Imm), ("NOP", Imp), ("SBC", Imm), // 0xE8
+    ("CPX", Abs), ("SBC", Abs), ("INC", Abs), ("ISC", Abs), // 0xEC
+    ("BEQ", Rel), ("SBC", Izy), ("KIL", Imp), ("ISC", Izy), // 0xF0
+    ("NOP", Zpx), ("SBC", Zpx), ("INC", Zpx), ("ISC", Zpx), // 0xF4
+    ("SED", Imp), ("SBC", Aby), ("NOP", Imp), ("ISC", Aby), // 0xF8
+    ("NOP", Abx), ("SBC", Abx), ("INC", Abx), ("ISC", Abx), // 0xFC
+];
+
+// Mnemonics only found among the unofficial opcodes.
+const UNOFFICIAL: [&str; 19] = [
+    "AHX", "ALR", "ANC", "ARR", "AXS", "DCP", "ISC", "KIL", "LAS", "LAX",
+    "RLA", "RRA", "SAX", "SHX", "SHY", "SLO", "SRE", "TAS", "XAA",
+];
+
+// Whether an opcode is outside the documented instruction set.
+// Besides the unofficial mnemonics, every NOP but $EA and the SBC at $EB are unofficial too.
+pub fn is_unofficial(opcode: u8) -> bool {
+    let mnemonic = OPCODES[opcode as usize].0;
+    UNOFFICIAL.contains(&mnemonic) || (mnemonic == "NOP" && opcode != 0xea) || opcode == 0xeb
+}
+
+// A decoded instruction.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub operand: u16,
+}
+
+impl Instruction {
+    pub fn size(&self) -> u16 { self.mode.size() }
+
+    // Address of the next instruction in memory.
+    pub fn next(&self) -> u16 { self.addr.wrapping_add(self.size()) }
+
+    pub fn is_unofficial(&self) -> bool { is_unofficial(self.opcode) }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = self.operand;
+        match self.mode {
+            Imp => write!(f, "{}", self.mnemonic),
+            Acc => write!(f, "{} A", self.mnemonic),
+            Imm => write!(f, "{} #${:02X}", self.mnemonic, op),
+            Zp => write!(f, "{} ${:02X}", self.mnemonic, op),
+            Zpx => write!(f, "{} ${:02X},X", self.mnemonic, op),
+            Zpy => write!(f, "{} ${:02X},Y", self.mnemonic, op),
+            Abs => write!(f, "{} ${:04X}", self.mnemonic, op),
+            Abx => write!(f, "{} ${:04X},X", self.mnemonic, op),
+            Aby => write!(f, "{} ${:04X},Y", self.mnemonic, op),
+            Ind => write!(f, "{} (${:04X})", self.mnemonic, op),
+            Izx => write!(f, "{} (${:02X},X)", self.mnemonic, op),
+            Izy => write!(f, "{} (${:02X}),Y", self.mnemonic, op),
+            // Branches show where they go.
+            Rel => {
+                let target = self.next().wrapping_add(op as i8 as u16);
+                write!(f, "{} ${:04X}", self.mnemonic, target)
+            }
+        }
+    }
+}
+
+// Decode the instruction at an address. Uses peeks, so no register is affected.
+pub fn decode(bus: &Bus, addr: u16) -> Instruction {
+    let opcode = bus.peek_cpu(addr);
+    let (mnemonic, mode) = OPCODES[opcode as usize];
+
+    let low = bus.peek_cpu(addr.wrapping_add(1));
+    let operand = match mode.size() {
+        1 => 0,
+        2 => u16::from(low),
+        _ => bits::set_high(u16::from(low), bus.peek_cpu(addr.wrapping_add(2))),
+    };
+
+    Instruction { addr, opcode, mnemonic, mode, operand }
+}
+
+// Text of the instruction at an address.
+pub fn disassemble(bus: &Bus, addr: u16) -> String { decode(bus, addr).to_string() }
+
+// Listing of a number of instructions following each other in memory.
+pub fn disassemble_range(bus: &Bus, start: u16, count: usize) -> Vec<(u16, String)> {
+    let mut addr = start;
+    (0..count).map(|_| {
+        let instr = decode(bus, addr);
+        addr = instr.next();
+        (instr.addr, instr.to_string())
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus_with(program: &[u8]) -> Bus {
+        let mut bus = Bus::new();
+        for (addr, &data) in program.iter().enumerate() {
+            bus.write_cpu(0x0200 + addr as u16, data);
+        }
+        bus
+    }
+
+    #[test]
+    fn every_opcode_has_a_mnemonic() {
+        assert!(OPCODES.iter().all(|(mnemonic, _)| mnemonic.len() == 3));
+        assert_eq!(OPCODES[0x4c], ("JMP", Abs));
+        assert_eq!(OPCODES[0x6c], ("JMP", Ind));
+        assert_eq!(OPCODES[0x4a], ("LSR", Acc));
+    }
+
+    #[test]
+    fn official_count() {
+        assert_eq!((0..=0xff).filter(|&opcode| !is_unofficial(opcode)).count(), 151);
+        assert!(!is_unofficial(0xea));
+        assert!(!is_unofficial(0xe9));
+        assert!(is_unofficial(0xeb));
+        assert!(is_unofficial(0x1a));
+        assert!(is_unofficial(0x02));
+    }
+
+    #[test]
+    fn range() {
+        let bus = bus_with(&[
+            0xa2, 0x00,       // LDX #$00
+            0xbd, 0x00, 0x03, // LDA $0300,X
+            0x95, 0x10,       // STA $10,X
+            0xe8,             // INX
+            0xd0, 0xf8,       // BNE $0202
+            0x0a,             // ASL A
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+            0xb1, 0x20,       // LDA ($20),Y
+            0x81, 0x30,       // STA ($30,X)
+        ]);
+
+        let listing = disassemble_range(&bus, 0x0200, 9);
+        let expected = vec![
+            (0x0200, "LDX #$00"),
+            (0x0202, "LDA $0300,X"),
+            (0x0205, "STA $10,X"),
+            (0x0207, "INX"),
+            (0x0208, "BNE $0202"),
+            (0x020a, "ASL A"),
+            (0x020b, "JMP ($FFFC)"),
+            (0x020e, "LDA ($20),Y"),
+            (0x0210, "STA ($30,X)"),
+        ];
+
+        assert_eq!(listing, expected.into_iter().map(|(addr, text)| (addr, text.to_owned())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn forward_branch() {
+        let bus = bus_with(&[0xf0, 0x04]);
+        assert_eq!(disassemble(&bus, 0x0200), "BEQ $0206");
+    }
+
+    #[test]
+    fn wraps_around_memory() {
+        let bus = bus_with(&[]);
+        let listing = disassemble_range(&bus, 0xffff, 2);
+        assert_eq!(listing[0].0, 0xffff);
+        assert_eq!(listing[1].0, 0xffff_u16.wrapping_add(decode(&bus, 0xffff).size()));
+    }
+}
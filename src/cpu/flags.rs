@@ -16,6 +16,7 @@ pub const LEAST_BIT: u8 = 0b1000_0000;
 
 // Flags for the P register
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Flags(pub u8);
 
 impl Flags {
@@ -1,35 +1,116 @@
+use std::cmp;
+#[cfg(feature = "logger")]
 use std::io::Write;
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+#[cfg(feature = "logger")]
 use chrono::Local;
+#[cfg(feature = "logger")]
 use env_logger::Builder;
-use log::LevelFilter;
+use log::{LevelFilter, Metadata, Record};
 
 pub use crate::cpu::log::logging::*;
 use crate::cpu::reg::Reg;
 
+// Which records get logged.
+// Targets are matched by prefix and the longest match wins, so "opcode" or "nespera::apu" can be tuned on their own.
+// The CPU logs to "opcode" and the bus to "memory". Tracing is very verbose, so logging can start after some records.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    level: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+    start_after: usize,
+}
+
+impl LogFilter {
+    // Level of every target not configured.
+    pub fn new(level: LevelFilter) -> Self { Self { level, targets: Vec::new(), start_after: 0 } }
+
+    pub fn target(mut self, target: &str, level: LevelFilter) -> Self {
+        self.targets.retain(|(other, _)| other != target);
+        self.targets.push((target.to_owned(), level));
+        self
+    }
+
+    pub fn enable(self, target: &str) -> Self { self.target(target, LevelFilter::Trace) }
+    pub fn disable(self, target: &str) -> Self { self.target(target, LevelFilter::Off) }
+
+    // Skip the first records that pass the filter.
+    pub fn start_after(mut self, records: usize) -> Self {
+        self.start_after = records;
+        self
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |&(_, level)| level)
+    }
+
+    // Most verbose level of any target.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.level, cmp::max)
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool { metadata.level() <= self.level_for(metadata.target()) }
+
+    // Install as the global logger.
+    #[cfg(feature = "logger")]
+    pub fn init(self) {
+        let inner = Builder::new()
+            .format(|buf, record| {
+                writeln!(buf,
+                         "{} [{}] - {}",
+                         Local::now().format("%Y-%m-%dT%H:%M:%S.%f"),
+                         record.level(),
+                         record.args()
+                )
+            })
+            .filter(None, self.max_level())
+            .build();
+
+        log::set_max_level(self.max_level());
+        log::set_boxed_logger(Box::new(FilteredLogger::new(self, inner))).unwrap();
+    }
+
+    // Without the logging backend only the level is set. Log calls are discarded.
+    #[cfg(not(feature = "logger"))]
+    pub fn init(self) {
+        log::set_max_level(self.max_level());
+    }
+}
+
+// Passes the records allowed by a filter to another logger.
+pub struct FilteredLogger<L> {
+    filter: LogFilter,
+    inner: L,
+    records: AtomicUsize,
+}
+
+impl<L: log::Log> FilteredLogger<L> {
+    pub fn new(filter: LogFilter, inner: L) -> Self { Self { filter, inner, records: AtomicUsize::new(0) } }
+}
+
+impl<L: log::Log> log::Log for FilteredLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool { self.filter.enabled(metadata) && self.inner.enabled(metadata) }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.enabled(record.metadata()) { return; }
+        if self.records.fetch_add(1, Ordering::Relaxed) < self.filter.start_after { return; }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) { self.inner.flush() }
+}
+
 // Starts logging after the specified amount of logs has passed.
 // Tracing is very verbose so you might need to limit how much is logged in order to speed up execution.
 pub fn setup(level: LevelFilter, start_after: usize) {
-    let counter = RwLock::new(0);
-    Builder::new()
-        .format(move |buf, record| {
-            let mut counter = counter.write().unwrap();
-            *counter += 1;
-            if *counter < start_after { return Result::Ok(()); }
-            writeln!(buf,
-                     "{} [{}] - {}",
-                     Local::now().format("%Y-%m-%dT%H:%M:%S.%f"),
-                     record.level(),
-                     record.args()
-            )
-        })
-        .filter(None, level)
-        .init();
+    LogFilter::new(level).start_after(start_after).init()
 }
 
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum AddrMode {
     Unknown,
     Implied,
@@ -48,9 +129,26 @@ pub enum AddrMode {
     IndirectY(u8, u16, u16, u8),
 }
 
+impl AddrMode {
+    // Address the instruction operates on. Jumps give their target.
+    pub fn effective_addr(self) -> Option<u16> {
+        match self {
+            AddrMode::ZeroPage(addr, _) => Some(u16::from(addr)),
+            AddrMode::ZeroPageX(_, addr, _) | AddrMode::ZeroPageY(_, addr, _) => Some(u16::from(addr)),
+            AddrMode::Absolute(addr, _) => Some(addr),
+            AddrMode::AbsoluteX(_, addr, _) | AddrMode::AbsoluteY(_, addr, _) => Some(addr),
+            AddrMode::Direct(addr) | AddrMode::Indirect(_, addr) => Some(addr),
+            AddrMode::IndirectX(_, _, addr, _) | AddrMode::IndirectY(_, _, addr, _) => Some(addr),
+            AddrMode::Unknown | AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate(_) | AddrMode::Relative(..) => None,
+        }
+    }
+}
+
 // Dummy implementation. Used when not logging.
 #[cfg(not(debug_assertions))]
 pub mod logging {
+    use crate::bus::Bus;
+
     use super::*;
 
     #[derive(Debug, Clone)]
@@ -59,15 +157,18 @@ pub mod logging {
     impl Log {
         pub fn new() -> Self { Self }
 
-        pub fn get(&self) -> String { "".to_owned() }
+        pub fn get(&self, _: &Bus) -> String { "".to_owned() }
 
         pub fn set_skip(&self, _: bool) {}
         pub fn set_reg(&self, _: Reg) {}
         pub fn set_unofficial(&self, _: bool) {}
         pub fn set_mnemonic(&self, _: &'static str) {}
         pub fn set_mode(&self, _: AddrMode) {}
+        pub fn get_mode(&self) -> AddrMode { AddrMode::Unknown }
         pub fn set_dot(&self, _: u32) {}
         pub fn set_scanline(&self, _: i32) {}
+        pub fn set_frame(&self, _: u32) {}
+        pub fn set_clock(&self, _: u32) {}
     }
 
     impl Default for Log { fn default() -> Self { Self::new() } }
@@ -88,8 +189,8 @@ pub mod logging {
     fn f_mem(addr: u16, data: u8) -> String {
         // Mesen reports PPU and APU reads on the logs as zero for some reason.
         let data = match addr {
-            0x2000...0x3fff => 0,
-            0x4000...0x401f => 0,
+            0x2000..=0x3fff => 0,
+            0x4000..=0x401f => 0,
             _ => data,
         };
         format!("${} = ${}", f_u16(addr), f_u8(data))
@@ -185,9 +286,11 @@ pub mod logging {
                 && !self.skip
         }
 
-        pub fn set_mode(&mut self, mode: AddrMode) {
-            if self.is_logging() { self.mode = mode; }
-        }
+        // Kept even when not logging. The operands were already read by then, so it's cheap.
+        pub fn set_mode(&mut self, mode: AddrMode) { self.mode = mode }
+
+        // Addressing mode of the last instruction, as it'd be logged.
+        pub fn get_mode(&self) -> AddrMode { self.mode }
 
         pub fn get(&self, bus: &Bus) -> String {
             if !self.is_logging() { return "".to_owned(); }
@@ -213,3 +316,111 @@ pub mod logging {
 
     impl Default for Log { fn default() -> Self { Self::new() } }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::console::Console;
+
+    use super::*;
+
+    // Runs with or without the logging backend.
+    #[test]
+    fn setup_and_step() {
+        setup(LevelFilter::Off, 0);
+
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+        let clock = console.cpu.get_clock();
+
+        console.run_frames(1);
+        assert!(console.cpu.get_clock() > clock);
+    }
+
+    mod filter {
+        use std::sync::Mutex;
+
+        use log::{Level, Log};
+
+        use super::*;
+
+        // Keeps the target and message of every record.
+        #[derive(Default)]
+        struct Capture {
+            records: Mutex<Vec<(String, String)>>,
+        }
+
+        impl Log for Capture {
+            fn enabled(&self, _: &Metadata) -> bool { true }
+
+            fn log(&self, record: &Record) {
+                let record = (record.target().to_owned(), record.args().to_string());
+                self.records.lock().unwrap().push(record);
+            }
+
+            fn flush(&self) {}
+        }
+
+        fn log(logger: &impl Log, target: &str, level: Level, message: &str) {
+            logger.log(&Record::builder()
+                .target(target)
+                .level(level)
+                .args(format_args!("{}", message))
+                .build());
+        }
+
+        fn captured(logger: FilteredLogger<Capture>) -> Vec<String> {
+            logger.inner.records.into_inner().unwrap().into_iter().map(|(_, message)| message).collect()
+        }
+
+        #[test]
+        fn only_enabled_targets() {
+            let filter = LogFilter::new(LevelFilter::Off).enable("opcode");
+            let logger = FilteredLogger::new(filter, Capture::default());
+
+            log(&logger, "opcode", Level::Trace, "lda");
+            log(&logger, "memory", Level::Trace, "read");
+            log(&logger, "nespera::apu", Level::Error, "apu");
+
+            assert_eq!(captured(logger), vec!["lda"]);
+        }
+
+        #[test]
+        fn disable_one_target() {
+            let filter = LogFilter::new(LevelFilter::Trace).disable("memory");
+            let logger = FilteredLogger::new(filter, Capture::default());
+
+            log(&logger, "opcode", Level::Trace, "lda");
+            log(&logger, "memory", Level::Trace, "read");
+            log(&logger, "nespera::apu", Level::Debug, "apu");
+
+            assert_eq!(captured(logger), vec!["lda", "apu"]);
+        }
+
+        #[test]
+        fn longest_prefix_wins() {
+            let filter = LogFilter::new(LevelFilter::Error)
+                .target("nespera", LevelFilter::Off)
+                .target("nespera::apu", LevelFilter::Info);
+
+            assert_eq!(filter.level_for("nespera::apu::pulse"), LevelFilter::Info);
+            assert_eq!(filter.level_for("nespera::ppu"), LevelFilter::Off);
+            assert_eq!(filter.level_for("opcode"), LevelFilter::Error);
+            assert_eq!(filter.max_level(), LevelFilter::Info);
+        }
+
+        #[test]
+        fn start_after() {
+            let filter = LogFilter::new(LevelFilter::Off).enable("opcode").start_after(2);
+            let logger = FilteredLogger::new(filter, Capture::default());
+
+            // Filtered records don't count.
+            for message in &["1", "2", "3", "4"] {
+                log(&logger, "memory", Level::Trace, "read");
+                log(&logger, "opcode", Level::Trace, message);
+            }
+
+            assert_eq!(captured(logger), vec!["3", "4"]);
+        }
+    }
+}
@@ -7,11 +7,41 @@ use crate::cpu::log::Log;
 use crate::cpu::reg::Reg;
 
 pub mod cycle;
+pub mod disasm;
 pub mod flags;
 pub mod log;
 pub mod opc;
 pub mod reg;
 
+// Cycles the CPU is halted for when the DMC fetches a sample.
+const DMC_DMA_CYCLES: u8 = 4;
+
+// Whether the CPU can keep running.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum CpuStatus {
+    Running,
+
+    // Stopped by a KIL opcode.
+    Halted,
+}
+
+// What the last stepped instruction was, for debuggers.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StepInfo {
+    // Where the instruction started.
+    pub pc: u16,
+
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: disasm::Mode,
+    pub unofficial: bool,
+
+    // Cycles taken, including page crossings and taken branches.
+    pub cycles: u32,
+
+    pub status: CpuStatus,
+}
+
 pub struct Cpu {
     // Logger
     pub log: Log,
@@ -25,11 +55,41 @@ pub struct Cpu {
     // Number of cycles since the CPU has been turned on.
     clock: u32,
 
+    // Unstable opcodes like XAA depend on analog behaviour that varies between consoles.
+    // This defines which bits of A take part in the result.
+    pub unstable_magic: u8,
+
+    // Behave like a 65C02 where it fixes NMOS bugs. Off for the NES.
+    pub cmos: bool,
+
+    // The read a DMC fetch halts is done again, so registers like the controllers see an extra read.
+    pub dmc_read_corruption: bool,
+
+    // Cycles spent on each opcode. Only counted while profiling.
+    pub profiling: bool,
+    profile: [u64; 256],
+
     // Flags to indicate internal operations
     oam_transferring: bool,
     skip_oam_transferring: bool,
     resetting: bool,
     interrupting: bool,
+    halted: bool,
+
+    // Cycles left of a DMC fetch. Taken from the middle of whatever the CPU was doing.
+    dmc_stall: u8,
+    dmc_halted_read: bool,
+
+    // Set when the CPU reaches a state that shouldn't exist. Cleared on reset.
+    faulted: bool,
+
+    // A taken branch that doesn't cross a page doesn't poll interrupts on its last cycle.
+    // Interrupts raised then wait until the next instruction is done.
+    branch_polled_interrupt: bool,
+    delay_interrupt: bool,
+
+    // Memory operand of the last instruction.
+    effective_addr: Option<u16>,
 }
 
 impl Cpu {
@@ -38,11 +98,23 @@ impl Cpu {
             log: Log::new(),
             reg: Reg::new(),
             clock: 0,
+            unstable_magic: 0xff,
+            cmos: false,
+            dmc_read_corruption: false,
+            profiling: false,
+            profile: [0; 256],
             bus,
             oam_transferring: false,
             skip_oam_transferring: false,
             resetting: false,
             interrupting: false,
+            halted: false,
+            dmc_stall: 0,
+            dmc_halted_read: false,
+            faulted: false,
+            branch_polled_interrupt: false,
+            delay_interrupt: false,
+            effective_addr: None,
         };
         res.reset();
         res
@@ -51,12 +123,60 @@ impl Cpu {
     pub fn get_clock(&self) -> u32 { self.clock }
     pub fn set_clock(&mut self, value: u32) { self.clock = value }
 
+    pub fn is_halted(&self) -> bool { self.halted }
+    pub fn is_faulted(&self) -> bool { self.faulted }
+
+    // A DMC fetch is running or about to start.
+    pub fn is_dmc_dma(&self) -> bool { self.dmc_stall > 0 || self.bus.borrow().apu.dmc_dma.is_some() }
+
+    // NMI or an IRQ that's not masked.
+    fn is_interrupt_pending(&self) -> bool {
+        let bus = self.bus.borrow();
+        bus.nmi || (bus.irq && !self.reg.get_p().get_interrupt_disable())
+    }
+
+    // Address the last instruction read or wrote, or where it jumped to.
+    // None for instructions without a memory operand, like immediates and branches.
+    pub fn last_effective_addr(&self) -> Option<u16> { self.effective_addr }
+
+    pub fn status(&self) -> CpuStatus {
+        if self.halted { CpuStatus::Halted } else { CpuStatus::Running }
+    }
+
+    // Opcodes that took any cycle while profiling, most expensive first.
+    pub fn profile_report(&self) -> Vec<(u8, u64)> {
+        let mut res = self.profile.iter()
+            .enumerate()
+            .filter(|(_, &cycles)| cycles > 0)
+            .map(|(opcode, &cycles)| (opcode as u8, cycles))
+            .collect::<Vec<_>>();
+
+        res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        res
+    }
+
+    pub fn clear_profile(&mut self) { self.profile = [0; 256] }
+
+    // Count a cycle for the current opcode.
+    fn profile_cycle(&mut self) {
+        if self.profiling {
+            let opcode = self.reg.get_current_instr() as usize;
+            self.profile[opcode] += 1;
+        }
+    }
+
     // Step a cycle
     #[allow(clippy::cyclomatic_complexity)]
     pub fn step(&mut self) {
         trace!(target: "opcode", "T{}", self.reg.get_cycle());
         self.clock += 1;
 
+        // Time passes but nothing else happens.
+        if self.halted { return; }
+
+        // The DMC takes over the bus until its sample is read. The instruction resumes where it was.
+        if self.is_dmc_dma() { return self.dmc_dma(); }
+
         // Run an opcode
         macro_rules! run {
             ($code:ident) => {{
@@ -79,12 +199,17 @@ impl Cpu {
             // Last cycle fetches the opcode.
             let pc = self.fetch_pc();
             self.reg.set_current_instr(pc);
+            self.effective_addr = None;
             self.reg.set_next_cycle();
+            self.profile_cycle();
+
+            let delay_interrupt = self.delay_interrupt;
+            self.delay_interrupt = false;
 
             let bus = self.bus.borrow();
             if bus.reset {
                 self.resetting = true
-            } else if bus.nmi || (bus.irq && !self.reg.get_p().get_interrupt_disable()) {
+            } else if !delay_interrupt && self.is_interrupt_pending() {
                 self.interrupting = true;
             } else if bus.ppu.oam_transfer {
                 self.oam_transferring = true;
@@ -97,6 +222,9 @@ impl Cpu {
 
         self.log.set_skip(true);
 
+        // Interrupts and DMA aren't part of any opcode.
+        if !(self.resetting || self.interrupting || self.oam_transferring) { self.profile_cycle(); }
+
         // Reset subroutine. Will clear the flag when finished.
         if self.resetting { return run!(rst); }
 
@@ -287,7 +415,7 @@ impl Cpu {
             0xA8 => run!(tay, implied),         /*bytes: 1 cycles: 2  A____=>__Y_P __      Tay, Implied     */
             0xA9 => run!(lda, immediate),       /*bytes: 2 cycles: 2  _____=>A___P __      Lda, Immediate   */
             0xAA => run!(tax, implied),         /*bytes: 1 cycles: 2  A____=>_X__P __      Tax, Implied     */
-            0xAB => run!(lax, immediate),       /*bytes: 2 cycles: 2  A____=>AX__P __      Lax, Immediate   */
+            0xAB => run!(lxa, immediate),       /*bytes: 2 cycles: 2  A____=>AX__P __      Lax, Immediate   */
             0xAC => run!(ldy, r_absolute),      /*bytes: 3 cycles: 4  _____=>__Y_P R_ abs  Ldy, Absolute    */
             0xAD => run!(lda, r_absolute),      /*bytes: 3 cycles: 4  _____=>A___P R_ abs  Lda, Absolute    */
             0xAE => run!(ldx, r_absolute),      /*bytes: 3 cycles: 4  _____=>_X__P R_ abs  Ldx, Absolute    */
@@ -375,31 +503,81 @@ impl Cpu {
         }
     }
 
-    pub fn step_instruction(&mut self) {
+    pub fn step_instruction(&mut self) -> CpuStatus {
         loop {
+            // A DMC fetch between instructions belongs to the next one.
+            let dmc_dma = self.is_dmc_dma();
             self.step();
-            if self.reg.get_cycle() == cycle::LAST
-                || self.reg.get_current_instr() == 0x22 // KIL
-            { break; }
+            if (!dmc_dma && self.reg.get_cycle() == cycle::LAST) || self.halted { break; }
+        }
+        self.status()
+    }
+
+    // Run until an instruction jumps or branches to itself, the way test programs report their result.
+    // Returns the address of the trap, or None if it's not reached within a number of cycles.
+    pub fn run_until_trap(&mut self, max_cycles: u32) -> Option<u16> {
+        let start = self.clock;
+        while self.clock.wrapping_sub(start) < max_cycles {
+            let pc = self.reg.get_pc();
+            self.step_instruction();
+            if self.reg.get_pc() == pc || self.halted { return Some(pc); }
+        }
+        None
+    }
+
+    // Step an instruction and describe it.
+    // The instruction is decoded before running, so it's reported even if an interrupt is taken in its place.
+    pub fn step_info(&mut self) -> StepInfo {
+        let instr = disasm::decode(&self.bus.borrow(), self.reg.get_pc());
+        let clock = self.clock;
+        let status = self.step_instruction();
+
+        StepInfo {
+            pc: instr.addr,
+            opcode: instr.opcode,
+            mnemonic: instr.mnemonic,
+            mode: instr.mode,
+            unofficial: instr.is_unofficial(),
+            cycles: self.clock.wrapping_sub(clock),
+            status,
         }
     }
 
-    // Step until a condition is met
-    pub fn step_until(&mut self, condition: fn(&Self) -> bool) {
+    // Step until a condition is met or the CPU halts
+    pub fn step_until(&mut self, condition: fn(&Self) -> bool) -> CpuStatus {
         loop {
             self.step();
-            if condition(&self) { break; }
+            if condition(self) || self.halted { break; }
+        }
+        self.status()
+    }
+
+    // Step until a condition is met, giving up after a number of cycles or if the CPU halts.
+    // Returns whether the condition was met.
+    pub fn step_until_bounded(&mut self, condition: fn(&Self) -> bool, max_cycles: u32) -> bool {
+        for _ in 0..max_cycles {
+            self.step();
+            if condition(self) { return true; }
+            if self.halted { return false; }
         }
+        false
     }
 
     pub fn reset(&mut self) {
+        // A halted CPU is stuck in the middle of KIL. Resume from an opcode fetch so the reset is seen.
+        if self.halted {
+            self.halted = false;
+            self.reg.set_last_cycle();
+        }
+
+        self.faulted = false;
         self.bus.borrow_mut().reset = true;
         while self.bus.borrow().reset { self.step(); }
     }
 
     // Run the instruction passed.
     // This has horrible side effects and should be used only for testing.
-    pub fn run(&mut self, code: &[u8]) {
+    pub fn run(&mut self, code: &[u8]) -> CpuStatus {
         let pc = self.reg.get_pc();
 
         // Copy into memory at PC
@@ -411,7 +589,7 @@ impl Cpu {
     }
 
     // Run a full cycle of the OAM DMA
-    pub fn run_oam_dma(&mut self) { self.step_instruction() }
+    pub fn run_oam_dma(&mut self) -> CpuStatus { self.step_instruction() }
 }
 
 impl fmt::Debug for Cpu {
@@ -442,11 +620,23 @@ mod tests {
             log: Default::default(),
             reg: cpu.reg.clone(),
             clock: clock + 7, // Account for reset routine
+            unstable_magic: cpu.unstable_magic,
+            cmos: cpu.cmos,
+            dmc_read_corruption: false,
+            profiling: false,
+            profile: [0; 256],
             bus: bus_ref.clone(),
             oam_transferring: false,
             skip_oam_transferring: false,
             resetting: false,
             interrupting: false,
+            halted: false,
+            dmc_stall: 0,
+            dmc_halted_read: false,
+            faulted: false,
+            branch_polled_interrupt: false,
+            delay_interrupt: false,
+            effective_addr: None,
         };
 
         // Force PC to zero
@@ -474,51 +664,1210 @@ mod tests {
 
     fn as_is(_: &mut Cpu) {}
 
-    #[test]
-    #[should_panic(expected = "Kil opcode finished running. Aborting program.")]
-    fn kil() { run(vec![0x02], 0, 0, as_is, as_is); }
+    mod kil {
+        use super::*;
 
-    #[test]
-    fn brk() {
-        run(vec![0x00], 0, 7,
-            as_is,
-            |cpu| {
-                cpu.reg.s_pc(0x00);
-                cpu.reg.s_s(0xfa);
-                cpu.bus.borrow_mut().write_cpu(0x01fd, 0x00);
-                cpu.bus.borrow_mut().write_cpu(0x01fc, 0x02);
-                cpu.bus.borrow_mut().write_cpu(0x01fb, 0x34);
-            },
-        );
+        fn halted() -> Cpu {
+            let bus = Bus::with_mem(&[0x02, 0xea]);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+
+            assert_eq!(cpu.step_instruction(), CpuStatus::Halted);
+            cpu
+        }
+
+        #[test]
+        fn halts() {
+            let mut cpu = halted();
+            assert!(cpu.is_halted());
+
+            let pc = cpu.reg.get_pc();
+            for _ in 0..10 { assert_eq!(cpu.step_instruction(), CpuStatus::Halted) }
+            assert_eq!(cpu.reg.get_pc(), pc);
+        }
+
+        #[test]
+        fn step_until_stops() {
+            let mut cpu = halted();
+            assert_eq!(cpu.step_until(|_| false), CpuStatus::Halted);
+        }
+
+        #[test]
+        fn step_until_bounded_stops() {
+            let mut cpu = halted();
+            assert!(!cpu.step_until_bounded(|_| false, 100));
+        }
+
+        #[test]
+        fn reset_resumes() {
+            let mut cpu = halted();
+            cpu.reset();
+            assert_eq!(cpu.status(), CpuStatus::Running);
+        }
     }
 
-    mod nop {
+    mod step_until_bounded {
         use super::*;
 
+        // Zeroed memory runs BRK to $0000 forever.
+        fn cpu() -> Cpu {
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_mem(&[]))));
+            cpu.reg.s_pc(0);
+            cpu
+        }
+
         #[test]
-        fn implied() { run(vec![0xea], 1, 2, as_is, as_is); }
+        fn budget_runs_out() {
+            let mut cpu = cpu();
+            let clock = cpu.get_clock();
+            assert!(!cpu.step_until_bounded(|_| false, 1000));
+            assert_eq!(cpu.get_clock(), clock + 1000);
+        }
 
         #[test]
-        fn immediate() { run(vec![0xe2], 2, 2, as_is, as_is); }
+        fn condition_met() {
+            let mut cpu = cpu();
+            let clock = cpu.get_clock();
+            assert!(cpu.step_until_bounded(|cpu| cpu.reg.get_s() == 0xfa, 1000));
+            assert!(cpu.get_clock() < clock + 1000);
+        }
+    }
+
+    // Unofficial NOPs read their operand like the instructions they share the addressing mode with.
+    #[cfg(debug_assertions)]
+    mod unofficial_nop {
+        use crate::cpu::log::AddrMode;
+
+        use super::*;
+
+        fn mode(program: &[u8], x: u8) -> AddrMode {
+            let mut mem = program.to_vec();
+            mem.resize(0x0800, 0);
+            mem[0x0010] = 0x11;
+            mem[0x0234] = 0x22;
+            mem[0x0244] = 0x33;
+
+            let bus = Bus::with_mem(&mem);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.reg.s_x(x);
+
+            cpu.step_instruction();
+            assert_eq!(cpu.reg.get_pc(), program.len() as u16);
+            cpu.log.get_mode()
+        }
 
         #[test]
-        fn zero_page() { run(vec![0x64], 2, 3, as_is, as_is); }
+        fn zero_page() {
+            assert_eq!(mode(&[0x04, 0x10], 0), AddrMode::ZeroPage(0x10, 0x11));
+        }
 
         #[test]
-        fn zero_page_x() { run(vec![0x14], 2, 4, as_is, as_is); }
+        fn absolute() {
+            assert_eq!(mode(&[0x0c, 0x34, 0x02], 0), AddrMode::Absolute(0x0234, 0x22));
+        }
 
         #[test]
-        fn absolute() { run(vec![0x0C], 3, 4, as_is, as_is); }
+        fn absolute_x() {
+            assert_eq!(mode(&[0x1c, 0x34, 0x02], 0x10), AddrMode::AbsoluteX(0x0234, 0x0244, 0x33));
+        }
 
         #[test]
-        fn absolute_x() { run(vec![0x1C, 0xfe, 0x00], 3, 4, as_is, as_is); }
+        fn immediate() {
+            assert_eq!(mode(&[0x80, 0x42], 0), AddrMode::Immediate(0x42));
+        }
 
         #[test]
-        fn absolute_x_cross_page() {
-            run(vec![0x1C, 0xff, 0x00], 3, 5,
-                |cpu| { cpu.reg.s_x(1) },
-                as_is,
-            );
+        fn implied() {
+            assert_eq!(mode(&[0x1a], 0), AddrMode::Implied);
+        }
+    }
+
+    mod sbc_alias {
+        use crate::cpu::flags::Flags;
+
+        use super::*;
+
+        const VALUES: [u8; 9] = [0x00, 0x01, 0x02, 0x7f, 0x80, 0x81, 0xc0, 0xfe, 0xff];
+
+        // Registers and clock after subtracting with an opcode.
+        fn subtract(opcode: u8, a: u8, data: u8, carry: bool) -> (u8, u8, u32) {
+            let bus = Bus::with_mem(&[opcode, data]);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.reg.s_a(a);
+            cpu.reg.s_c(carry);
+
+            let clock = cpu.clock;
+            cpu.step_instruction();
+            (cpu.reg.get_a(), cpu.reg.get_p().into(), cpu.clock - clock)
+        }
+
+        #[test]
+        fn same_as_official() {
+            for &a in &VALUES {
+                for &data in &VALUES {
+                    for &carry in &[false, true] {
+                        let official = subtract(0xe9, a, data, carry);
+                        let alias = subtract(0xeb, a, data, carry);
+                        assert_eq!(official, alias, "a: {:02x}, data: {:02x}, carry: {}", a, data, carry);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn boundaries() {
+            // A, operand, carry in => A, carry, overflow, negative, zero
+            let cases = [
+                (0x00, 0x01, true, 0xff, false, false, true, false),
+                (0x00, 0x01, false, 0xfe, false, false, true, false),
+                (0x00, 0x00, true, 0x00, true, false, false, true),
+                (0x00, 0x00, false, 0xff, false, false, true, false),
+                (0x80, 0x01, true, 0x7f, true, true, false, false),
+                (0x7f, 0xff, true, 0x80, false, true, true, false),
+                (0x01, 0x01, true, 0x00, true, false, false, true),
+            ];
+
+            for &(a, data, carry, res, c, v, n, z) in &cases {
+                for &opcode in &[0xe9, 0xeb] {
+                    let (res_a, p, _) = subtract(opcode, a, data, carry);
+                    let p = Flags::from(p);
+                    let actual = (res_a, p.get_carry(), p.get_overflow(), p.get_negative(), p.get_zero());
+                    assert_eq!(actual, (res, c, v, n, z), "opcode: {:02x}, a: {:02x}, data: {:02x}, carry: {}", opcode, a, data, carry);
+                }
+            }
+        }
+    }
+
+    // OAM DMA reads through the CPU bus, so register pages see the same side effects as a CPU read.
+    mod oam_dma {
+        use super::*;
+
+        fn dma(bus: Bus, page: u8) -> Rc<RefCell<Bus>> {
+            let bus = Rc::new(RefCell::new(bus));
+            let mut cpu = Cpu::new(bus.clone());
+
+            // STA $4014
+            cpu.reg.s_a(page);
+            cpu.run(&[0x8d, 0x14, 0x40]);
+            cpu.run_oam_dma();
+            bus
+        }
+
+        #[test]
+        fn ram_page() {
+            let mem = (0..0x0800).map(|addr| (addr >> 1) as u8).collect::<Vec<_>>();
+            let bus = dma(Bus::with_mem(&mem), 0x07);
+
+            // Attributes don't keep bits 2 to 4.
+            let oam = bus.borrow().ppu.oam_snapshot();
+            for (i, &data) in oam.iter().enumerate() {
+                let expected = ((0x0700 + i) >> 1) as u8;
+                let expected = if i % 4 == 2 { expected & 0b1110_0011 } else { expected };
+                assert_eq!(data, expected, "{:02x}", i);
+            }
+        }
+
+        #[test]
+        fn ram_mirror_page() {
+            let mem = (0..0x0800).map(|addr| (addr >> 1) as u8).collect::<Vec<_>>();
+            let bus = dma(Bus::with_mem(&mem), 0x0f);
+            assert_eq!(bus.borrow().ppu.oam_snapshot()[0x10], ((0x0710) >> 1) as u8);
+        }
+
+        #[test]
+        fn ppu_register_page() {
+            let mut bus = Bus::new();
+            bus.ppu.vblank_set();
+            bus.write_cpu(0x2006, 0x24);
+            bus.write_cpu(0x2006, 0x00);
+
+            let bus = dma(bus, 0x20);
+            let bus = bus.borrow();
+            let oam = bus.ppu.oam_snapshot();
+
+            // The first read of $2002 sees vblank and clears it.
+            assert_eq!(oam[0x02] & 0x80, 0x80);
+            assert_eq!(oam[0x0a] & 0x80, 0x00);
+            assert!(!bus.ppu.is_vblank());
+
+            // $2007 is read every eight bytes, incrementing the VRAM address each time.
+            assert_eq!(bus.ppu.v, 0x2400 + 0x100 / 8);
+        }
+
+        #[test]
+        fn io_page() {
+            let mut bus = Bus::new();
+            bus.apu.frame_irq = true;
+
+            let bus = dma(bus, 0x40);
+            let bus = bus.borrow();
+
+            // Reading $4015 reports the frame interrupt and clears it.
+            assert_eq!(bus.ppu.oam_snapshot()[0x15] & 0x40, 0x40);
+            assert!(!bus.apu.frame_irq);
+        }
+    }
+
+    mod dmc_dma {
+        use crate::bus::controller::Buttons;
+
+        use super::*;
+
+        // LDA $0300, with the sample at $0010.
+        fn cpu() -> Cpu {
+            let mut mem = vec![0; 0x0301];
+            mem[0..3].copy_from_slice(&[0xad, 0x00, 0x03]);
+            mem[0x0010] = 0x99;
+            mem[0x0300] = 0x42;
+
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_mem(&mem))));
+            cpu.reg.s_pc(0);
+            cpu
+        }
+
+        fn cycles(cpu: &mut Cpu) -> u32 {
+            let clock = cpu.get_clock();
+            cpu.step_instruction();
+            cpu.get_clock() - clock
+        }
+
+        #[test]
+        fn without_fetch() {
+            let mut cpu = cpu();
+            assert_eq!(cycles(&mut cpu), 4);
+            assert_eq!(cpu.bus.borrow().apu.dmc_sample, None);
+        }
+
+        #[test]
+        fn mid_instruction() {
+            let mut cpu = cpu();
+            cpu.step();
+            cpu.step();
+            cpu.bus.borrow_mut().apu.dmc_dma = Some(0x0010);
+
+            assert_eq!(cycles(&mut cpu) + 2, 4 + u32::from(DMC_DMA_CYCLES));
+            assert_eq!(cpu.reg.get_a(), 0x42);
+            assert_eq!(cpu.reg.get_pc(), 0x0003);
+
+            let bus = cpu.bus.borrow();
+            assert_eq!(bus.apu.dmc_sample, Some(0x99));
+            assert_eq!(bus.apu.dmc_dma, None);
+        }
+
+        // Four reads of the first controller, with A and SELECT pressed.
+        // The fetch halts the second read.
+        fn controller_reads(corruption: bool) -> Vec<u8> {
+            let program = [0xad, 0x16, 0x40].repeat(4);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_mem(&program))));
+            cpu.reg.s_pc(0);
+            cpu.dmc_read_corruption = corruption;
+
+            {
+                let mut bus = cpu.bus.borrow_mut();
+                bus.controllers[0].buttons = Buttons::A | Buttons::SELECT;
+                bus.write_cpu(0x4016, 1);
+                bus.write_cpu(0x4016, 0);
+            }
+
+            let mut res = vec![];
+            for i in 0..4 {
+                if i == 1 {
+                    // Opcode and operand fetched, the read of $4016 comes next.
+                    for _ in 0..3 { cpu.step() }
+                    cpu.bus.borrow_mut().apu.dmc_dma = Some(0x0010);
+                }
+                cpu.step_instruction();
+                res.push(cpu.reg.get_a() & 1);
+            }
+            res
+        }
+
+        #[test]
+        fn controller_read() {
+            assert_eq!(controller_reads(false), vec![1, 0, 1, 0]);
+        }
+
+        #[test]
+        fn controller_read_corrupted() {
+            // B is clocked out by the repeated read and never seen.
+            assert_eq!(controller_reads(true), vec![1, 1, 0, 0]);
+        }
+
+        #[test]
+        fn only_once() {
+            let mut cpu = cpu();
+            cpu.bus.borrow_mut().apu.dmc_dma = Some(0x0010);
+            assert_eq!(cycles(&mut cpu), 4 + u32::from(DMC_DMA_CYCLES));
+
+            cpu.reg.s_pc(0);
+            assert_eq!(cycles(&mut cpu), 4);
+        }
+    }
+
+    mod controller_open_bus {
+        use crate::bus::controller::Buttons;
+
+        use super::*;
+
+        fn cpu_with_a_pressed() -> Cpu {
+            let mut bus = Bus::new();
+            bus.controllers[0].buttons = Buttons::A;
+            bus.write_cpu(0x4016, 1);
+            bus.write_cpu(0x4016, 0);
+            Cpu::new(Rc::new(RefCell::new(bus)))
+        }
+
+        #[test]
+        fn absolute() {
+            // LDA $4016
+            let mut cpu = cpu_with_a_pressed();
+            cpu.run(&[0xad, 0x16, 0x40]);
+            assert_eq!(cpu.reg.get_a(), 0x41);
+        }
+
+        #[test]
+        fn indexed() {
+            // LDA $4000,X
+            let mut cpu = cpu_with_a_pressed();
+            cpu.reg.s_x(0x16);
+            cpu.run(&[0xbd, 0x00, 0x40]);
+            assert_eq!(cpu.reg.get_a(), 0x41);
+        }
+    }
+
+    // Read-modify-write instructions write the original value back before the new one. Both reach the bus.
+    mod rmw_dummy_write {
+        use super::*;
+
+        // INC $2007 with VRAM at $2400 holding $40.
+        fn inc_ppudata() -> Cpu {
+            let mut bus = Bus::with_mem(&[0xee, 0x07, 0x20]);
+            bus.write_ppu(0x2400, 0x40);
+            bus.write_cpu(0x2006, 0x24);
+            bus.write_cpu(0x2006, 0x00);
+
+            // Fill the read buffer with $40.
+            bus.read_cpu(0x2007);
+
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.step_instruction();
+            cpu
+        }
+
+        #[test]
+        fn both_writes_reach_vram() {
+            let cpu = inc_ppudata();
+            let bus = cpu.bus.borrow();
+            assert_eq!(bus.peek_ppu(0x2402), 0x40);
+            assert_eq!(bus.peek_ppu(0x2403), 0x41);
+        }
+
+        #[test]
+        fn address_increments_on_each_access() {
+            // One read and two writes.
+            let cpu = inc_ppudata();
+            assert_eq!(cpu.bus.borrow().ppu.v, 0x2404);
+        }
+    }
+
+    mod effective_addr {
+        use super::*;
+
+        fn step(program: &[u8], setup: fn(&mut Cpu)) -> Option<u16> {
+            let mut mem = program.to_vec();
+            mem.resize(0x0800, 0);
+            mem[0x0020] = 0x34;
+            mem[0x0021] = 0x02;
+
+            let bus = Bus::with_mem(&mem);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            setup(&mut cpu);
+
+            cpu.step_instruction();
+            cpu.last_effective_addr()
+        }
+
+        #[test]
+        fn absolute() {
+            assert_eq!(step(&[0xad, 0x34, 0x12], |_| {}), Some(0x1234));
+        }
+
+        #[test]
+        fn immediate() {
+            assert_eq!(step(&[0xa9, 0x01], |_| {}), None);
+        }
+
+        #[test]
+        fn indexed() {
+            assert_eq!(step(&[0xb5, 0xf0], |cpu| cpu.reg.s_x(0x20)), Some(0x0010));
+            assert_eq!(step(&[0xb9, 0xf0, 0x12], |cpu| cpu.reg.s_y(0x20)), Some(0x1310));
+        }
+
+        #[test]
+        fn indirect_y() {
+            assert_eq!(step(&[0xb1, 0x20], |cpu| cpu.reg.s_y(0x01)), Some(0x0235));
+        }
+
+        #[test]
+        fn store() {
+            assert_eq!(step(&[0x8d, 0x00, 0x03], |_| {}), Some(0x0300));
+        }
+
+        #[test]
+        fn implied_clears() {
+            let bus = Bus::with_mem(&[0xad, 0x34, 0x12, 0xe8]);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+
+            cpu.step_instruction();
+            assert_eq!(cpu.last_effective_addr(), Some(0x1234));
+            cpu.step_instruction();
+            assert_eq!(cpu.last_effective_addr(), None);
+        }
+    }
+
+    mod jmp_indirect {
+        use super::*;
+
+        // JMP ($02FF). The pointer high byte is either at $0200 or $0300.
+        // Returns where it jumped to and the cycles taken.
+        fn jump(cmos: bool) -> (u16, u32) {
+            let mut mem = vec![0x6c, 0xff, 0x02];
+            mem.resize(0x0800, 0);
+            mem[0x02ff] = 0x34;
+            mem[0x0200] = 0x12;
+            mem[0x0300] = 0x56;
+
+            let bus = Bus::with_mem(&mem);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.cmos = cmos;
+            cpu.reg.s_pc(0x00);
+
+            let clock = cpu.clock;
+            cpu.step_instruction();
+            (cpu.reg.get_pc(), cpu.clock - clock)
+        }
+
+        #[test]
+        fn nmos_wraps_within_page() {
+            assert_eq!(jump(false).0, 0x1234);
+        }
+
+        #[test]
+        fn cmos_crosses_page() {
+            assert_eq!(jump(true).0, 0x5634);
+        }
+
+        #[test]
+        fn nmos_takes_five_cycles() {
+            assert_eq!(jump(false).1, 5);
+        }
+
+        #[test]
+        fn cmos_takes_six_cycles() {
+            assert_eq!(jump(true).1, 6);
+        }
+    }
+
+    mod step_info {
+        use super::*;
+
+        fn step(program: &[u8]) -> StepInfo {
+            let bus = Bus::with_mem(program);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.step_info()
+        }
+
+        #[test]
+        fn lda_immediate() {
+            let info = step(&[0xa9, 0x01]);
+            assert_eq!(info, StepInfo {
+                pc: 0x0000,
+                opcode: 0xa9,
+                mnemonic: "LDA",
+                mode: disasm::Mode::Imm,
+                unofficial: false,
+                cycles: 2,
+                status: CpuStatus::Running,
+            });
+        }
+
+        #[test]
+        fn unofficial() {
+            let info = step(&[0x04, 0x10]);
+            assert_eq!((info.mnemonic, info.mode, info.unofficial, info.cycles), ("NOP", disasm::Mode::Zp, true, 3));
+        }
+
+        #[test]
+        fn page_crossing() {
+            let mut program = vec![0xbd, 0xff, 0x00];
+            program.resize(0x0800, 0);
+
+            let bus = Bus::with_mem(&program);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.reg.s_x(0x01);
+
+            let info = cpu.step_info();
+            assert_eq!((info.mnemonic, info.mode, info.cycles), ("LDA", disasm::Mode::Abx, 5));
+        }
+
+        #[test]
+        fn halted() {
+            let info = step(&[0x02]);
+            assert_eq!((info.mnemonic, info.unofficial, info.status), ("KIL", true, CpuStatus::Halted));
+        }
+    }
+
+    mod arr {
+        use super::*;
+
+        // A, carry, overflow, negative, zero after ARR.
+        fn arr(cpu: &mut Cpu, a: u8, data: u8, carry: bool, decimal: bool) -> (u8, bool, bool, bool, bool) {
+            cpu.bus.borrow_mut().write_cpu(0x0001, data);
+            cpu.reg.s_pc(0x00);
+            cpu.reg.s_a(a);
+            cpu.reg.s_c(carry);
+            cpu.reg.s_d(decimal);
+
+            cpu.step_instruction();
+            let p = cpu.reg.get_p();
+            (cpu.reg.get_a(), p.get_carry(), p.get_overflow(), p.get_negative(), p.get_zero())
+        }
+
+        fn cpu() -> Cpu {
+            let bus = Bus::with_mem(&[0x6b, 0x00]);
+            Cpu::new(Rc::new(RefCell::new(bus)))
+        }
+
+        #[test]
+        fn sweep() {
+            let mut cpu = cpu();
+
+            for a in 0..=0xff {
+                for data in 0..=0xff {
+                    for &carry in &[false, true] {
+                        let res = ((a & data) >> 1) | ((carry as u8) << 7);
+                        let bit6 = res & 0x40 != 0;
+                        let bit5 = res & 0x20 != 0;
+                        let expected = (res, bit6, bit6 ^ bit5, res & 0x80 != 0, res == 0);
+
+                        assert_eq!(arr(&mut cpu, a, data, carry, false), expected, "a: {:02x}, data: {:02x}, carry: {}", a, data, carry);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn known_results() {
+            // A, operand, carry in => A, carry, overflow, negative, zero
+            let cases = [
+                (0x00, 0x00, false, 0x00, false, false, false, true),
+                (0x00, 0x00, true, 0x80, false, false, true, false),
+                (0xff, 0x01, false, 0x00, false, false, false, true),
+                (0xff, 0x40, false, 0x20, false, true, false, false),
+                (0xff, 0x80, false, 0x40, true, true, false, false),
+                (0xff, 0xc0, false, 0x60, true, false, false, false),
+                (0xff, 0xff, true, 0xff, true, false, true, false),
+                (0x0f, 0xf0, true, 0x80, false, false, true, false),
+            ];
+
+            let mut cpu = cpu();
+            for &(a, data, carry, res, c, v, n, z) in &cases {
+                assert_eq!(arr(&mut cpu, a, data, carry, false), (res, c, v, n, z), "a: {:02x}, data: {:02x}, carry: {}", a, data, carry);
+            }
+        }
+
+        #[test]
+        fn decimal_is_ignored() {
+            let mut cpu = cpu();
+
+            for &a in &[0x00, 0x19, 0x5a, 0x99, 0xff] {
+                for &data in &[0x00, 0x0f, 0x99, 0xaa, 0xff] {
+                    let binary = arr(&mut cpu, a, data, true, false);
+                    assert_eq!(arr(&mut cpu, a, data, true, true), binary);
+                    assert!(cpu.reg.get_p().get_decimal_mode());
+                }
+            }
+        }
+    }
+
+    // Unofficial read-modify-write opcodes, checked against the two official instructions they combine.
+    mod unofficial_rmw {
+        use super::*;
+
+        // A, memory, negative, overflow, zero, carry.
+        type Res = (u8, u8, bool, bool, bool, bool);
+
+        const A: [u8; 8] = [0x00, 0x01, 0x0f, 0x40, 0x7f, 0x80, 0xc3, 0xff];
+
+        // Runs the opcode on zero page $10 with overflow set, so it shows if it's kept.
+        fn run(cpu: &mut Cpu, opcode: u8, a: u8, data: u8, carry: bool) -> Res {
+            {
+                let mut bus = cpu.bus.borrow_mut();
+                bus.write_cpu(0x0000, opcode);
+                bus.write_cpu(0x0001, 0x10);
+                bus.write_cpu(0x0010, data);
+            }
+            cpu.reg.s_pc(0x00);
+            cpu.reg.s_a(a);
+            cpu.reg.s_c(carry);
+            cpu.reg.s_v(true);
+
+            cpu.step_instruction();
+            let p = cpu.reg.get_p();
+            let data = cpu.bus.borrow().peek_cpu(0x0010);
+            (cpu.reg.get_a(), data, p.get_negative(), p.get_overflow(), p.get_zero(), p.get_carry())
+        }
+
+        fn adc(a: u8, data: u8, carry: bool) -> (u8, bool, bool) {
+            let sum = u16::from(a) + u16::from(data) + u16::from(carry);
+            let res = sum as u8;
+            (res, (a ^ res) & (data ^ res) & 0x80 != 0, sum > 0xff)
+        }
+
+        fn sweep(opcode: u8, expected: fn(u8, u8, bool) -> Res) {
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_mem(&[]))));
+            for &a in &A {
+                for data in 0..=0xff {
+                    for &carry in &[false, true] {
+                        assert_eq!(run(&mut cpu, opcode, a, data, carry), expected(a, data, carry),
+                                   "0x{:02x}: a 0x{:02x}, data 0x{:02x}, carry {}", opcode, a, data, carry);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn slo() {
+            sweep(0x07, |a, data, _| {
+                let shifted = data << 1;
+                let res = a | shifted;
+                (res, shifted, res >= 0x80, true, res == 0, data >= 0x80)
+            });
+        }
+
+        #[test]
+        fn sre() {
+            sweep(0x47, |a, data, _| {
+                let shifted = data >> 1;
+                let res = a ^ shifted;
+                (res, shifted, res >= 0x80, true, res == 0, data & 1 != 0)
+            });
+        }
+
+        #[test]
+        fn rla() {
+            sweep(0x27, |a, data, carry| {
+                let rotated = (data << 1) | carry as u8;
+                let res = a & rotated;
+                (res, rotated, res >= 0x80, true, res == 0, data >= 0x80)
+            });
+        }
+
+        #[test]
+        fn rra() {
+            // The carry rotated out is the one added.
+            sweep(0x67, |a, data, carry| {
+                let rotated = (data >> 1) | ((carry as u8) << 7);
+                let (res, overflow, carry) = adc(a, rotated, data & 1 != 0);
+                (res, rotated, res >= 0x80, overflow, res == 0, carry)
+            });
+        }
+
+        #[test]
+        fn dcp() {
+            // Compares with the decremented value. A is kept.
+            sweep(0xc7, |a, data, _| {
+                let decremented = data.wrapping_sub(1);
+                let diff = a.wrapping_sub(decremented);
+                (a, decremented, diff >= 0x80, true, a == decremented, a >= decremented)
+            });
+        }
+
+        #[test]
+        fn isc() {
+            sweep(0xe7, |a, data, carry| {
+                let incremented = data.wrapping_add(1);
+                let (res, overflow, carry) = adc(a, !incremented, carry);
+                (res, incremented, res >= 0x80, overflow, res == 0, carry)
+            });
+        }
+    }
+
+    mod fuzz {
+        use crate::bus::cpu_data::RamInit;
+        use crate::cartridge::Cartridge;
+
+        use super::*;
+
+        #[test]
+        fn unexpected_cycle_faults() {
+            let bus = Bus::with_mem(&[0xea, 0xea]);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+
+            // Fetch NOP and corrupt its cycle.
+            cpu.step();
+            cpu.reg.s_t(cycle::T7);
+            cpu.step();
+            assert!(cpu.is_faulted());
+
+            // The next instruction goes on as usual.
+            assert_eq!(cpu.step_instruction(), CpuStatus::Running);
+            assert_eq!(cpu.reg.get_pc(), 0x02);
+
+            cpu.reset();
+            assert!(!cpu.is_faulted());
+        }
+
+        // PRG ROM is all zeros, so every vector points to random RAM.
+        #[test]
+        fn random_ram() {
+            for seed in 0..16 {
+                let bus = Bus::with_power_state(Cartridge::empty(), RamInit::Random(seed));
+                let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+
+                for _ in 0..20_000 {
+                    cpu.step();
+                    if cpu.is_halted() { cpu.reset() }
+                }
+            }
+        }
+    }
+
+    mod branch_interrupt {
+        use super::*;
+
+        // BEQ at PC with the branch taken and IRQs unmasked.
+        fn branch(page_cross: bool) -> Cpu {
+            let (pc, offset) = if page_cross { (0x00f0, 0x20) } else { (0x0000, 0x02) };
+
+            let mut mem = vec![0xea; 0x200];
+            mem[pc] = 0xf0;
+            mem[pc + 1] = offset;
+
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_mem(&mem))));
+            cpu.reg.s_pc(pc as u16);
+            cpu.reg.get_p_mut().set(flags::ZERO);
+            cpu.reg.get_p_mut().clear(flags::INTERRUPT_DISABLE);
+
+            // Fetch the branch
+            cpu.step();
+            cpu
+        }
+
+        fn cycles_until_interrupt(cpu: &mut Cpu) -> u32 {
+            let mut cycles = 0;
+            while !cpu.interrupting {
+                cpu.step();
+                cycles += 1;
+            }
+            cycles
+        }
+
+        #[test]
+        fn page_cross_services_one_cycle_later() {
+            let mut cpu = branch(false);
+            cpu.bus.borrow_mut().irq = true;
+            let same_page = cycles_until_interrupt(&mut cpu);
+
+            let mut cpu = branch(true);
+            cpu.bus.borrow_mut().irq = true;
+            let page_cross = cycles_until_interrupt(&mut cpu);
+
+            assert_eq!(same_page, 3);
+            assert_eq!(page_cross, same_page + 1);
+        }
+
+        #[test]
+        fn same_page_delays_late_irq() {
+            let mut cpu = branch(false);
+            cpu.step();
+            cpu.bus.borrow_mut().irq = true;
+
+            // The NOP after the branch runs before the interrupt.
+            assert_eq!(cycles_until_interrupt(&mut cpu), 4);
+            assert_eq!(cpu.reg.get_pc(), 0x0006);
+        }
+
+        #[test]
+        fn page_cross_doesnt_delay_late_irq() {
+            let mut cpu = branch(true);
+            cpu.step();
+            cpu.bus.borrow_mut().irq = true;
+            assert_eq!(cycles_until_interrupt(&mut cpu), 3);
+        }
+    }
+
+    #[test]
+    fn brk() {
+        run(vec![0x00], 0, 7,
+            as_is,
+            |cpu| {
+                cpu.reg.s_pc(0x00);
+                cpu.reg.s_s(0xfa);
+                cpu.bus.borrow_mut().write_cpu(0x01fd, 0x00);
+                cpu.bus.borrow_mut().write_cpu(0x01fc, 0x02);
+                cpu.bus.borrow_mut().write_cpu(0x01fb, 0x34);
+            },
+        );
+    }
+
+    mod nop {
+        use super::*;
+
+        #[test]
+        fn implied() { run(vec![0xea], 1, 2, as_is, as_is); }
+
+        #[test]
+        fn immediate() { run(vec![0xe2], 2, 2, as_is, as_is); }
+
+        #[test]
+        fn zero_page() { run(vec![0x64], 2, 3, as_is, as_is); }
+
+        #[test]
+        fn zero_page_x() { run(vec![0x14], 2, 4, as_is, as_is); }
+
+        #[test]
+        fn absolute() { run(vec![0x0C], 3, 4, as_is, as_is); }
+
+        #[test]
+        fn absolute_x() { run(vec![0x1C, 0xfe, 0x00], 3, 4, as_is, as_is); }
+
+        #[test]
+        fn absolute_x_cross_page() {
+            run(vec![0x1C, 0xff, 0x00], 3, 5,
+                |cpu| { cpu.reg.s_x(1) },
+                as_is,
+            );
+        }
+    }
+
+    mod xaa {
+        use super::*;
+
+        #[test]
+        fn default_magic() {
+            run(vec![0x8B, 0xf0], 2, 2,
+                |cpu| {
+                    cpu.reg.s_a(0x00);
+                    cpu.reg.s_x(0x3c);
+                },
+                |cpu| {
+                    cpu.reg.s_a(0x30);
+                    cpu.reg.s_x(0x3c);
+                },
+            );
+        }
+
+        #[test]
+        fn custom_magic() {
+            run(vec![0x8B, 0xf0], 2, 2,
+                |cpu| {
+                    cpu.unstable_magic = 0xee;
+                    cpu.reg.s_a(0x00);
+                    cpu.reg.s_x(0x3c);
+                },
+                |cpu| {
+                    cpu.reg.s_a(0x20);
+                    cpu.reg.s_x(0x3c);
+                },
+            );
+        }
+    }
+
+    mod lax_immediate {
+        use super::*;
+
+        #[test]
+        fn default_magic() {
+            run(vec![0xAB, 0x5a], 2, 2,
+                |cpu| cpu.reg.s_a(0x00),
+                |cpu| {
+                    cpu.reg.s_a(0x5a);
+                    cpu.reg.s_x(0x5a);
+                },
+            );
+        }
+
+        #[test]
+        fn custom_magic() {
+            run(vec![0xAB, 0x5a], 2, 2,
+                |cpu| {
+                    cpu.unstable_magic = 0xee;
+                    cpu.reg.s_a(0x01);
+                },
+                |cpu| {
+                    cpu.reg.s_a(0x4a);
+                    cpu.reg.s_x(0x4a);
+                },
+            );
+        }
+
+        #[test]
+        fn zero() {
+            run(vec![0xAB, 0x11], 2, 2,
+                |cpu| {
+                    cpu.unstable_magic = 0xee;
+                    cpu.reg.s_a(0x00);
+                },
+                |cpu| {
+                    cpu.reg.s_a(0x00);
+                    cpu.reg.s_x(0x00);
+                    cpu.reg.s_z(true);
+                },
+            );
+        }
+
+        #[test]
+        fn memory_ignores_magic() {
+            run(vec![0xA7, 0x02, 0x5a], 2, 3,
+                |cpu| {
+                    cpu.unstable_magic = 0xee;
+                    cpu.reg.s_a(0x01);
+                },
+                |cpu| {
+                    cpu.reg.s_a(0x5a);
+                    cpu.reg.s_x(0x5a);
+                },
+            );
+        }
+    }
+
+    mod shx {
+        use super::*;
+
+        #[test]
+        fn no_cross_page() {
+            run(vec![0x9E, 0x10, 0x00], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0xff);
+                    cpu.reg.s_y(0x01);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0xff);
+                    cpu.reg.s_y(0x01);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0011), 0x01);
+                },
+            );
+        }
+
+        #[test]
+        fn cross_page() {
+            run(vec![0x9E, 0xff, 0x00], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0xff);
+                    cpu.reg.s_y(0x02);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0xff);
+                    cpu.reg.s_y(0x02);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0101), 0x01);
+                },
+            );
+        }
+
+        #[test]
+        fn cross_page_corrupts_address() {
+            run(vec![0x9E, 0xff, 0x02], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x01);
+                    cpu.reg.s_y(0x01);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x01);
+                    cpu.reg.s_y(0x01);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0100), 0x01);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0300), 0x00);
+                },
+            );
+        }
+    }
+
+    mod shy {
+        use super::*;
+
+        #[test]
+        fn no_cross_page() {
+            run(vec![0x9C, 0x10, 0x01], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x01);
+                    cpu.reg.s_y(0xff);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x01);
+                    cpu.reg.s_y(0xff);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0111), 0x02);
+                },
+            );
+        }
+
+        #[test]
+        fn cross_page() {
+            run(vec![0x9C, 0xff, 0x01], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x02);
+                    cpu.reg.s_y(0xff);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x02);
+                    cpu.reg.s_y(0xff);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0201), 0x02);
+                },
+            );
+        }
+
+        #[test]
+        fn cross_page_corrupts_address() {
+            run(vec![0x9C, 0xfe, 0x04], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x02);
+                    cpu.reg.s_y(0x01);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x02);
+                    cpu.reg.s_y(0x01);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0100), 0x01);
+                    assert_eq!(cpu.bus.borrow().peek_cpu(0x0500), 0x00);
+                },
+            );
+        }
+    }
+
+    // Dummy reads must hit the bus. Reading PPUSTATUS clears vblank.
+    mod dummy_read {
+        use super::*;
+
+        fn set_vblank(cpu: &mut Cpu) { cpu.bus.borrow_mut().ppu.vblank_set() }
+
+        fn assert_vblank_clear(cpu: &mut Cpu) { assert!(!cpu.bus.borrow().ppu.is_vblank()) }
+
+        #[test]
+        fn rmw_absolute_x() {
+            run(vec![0xFE, 0xfa, 0x3f], 3, 7,
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+
+        #[test]
+        fn rmw_indirect_y() {
+            run(vec![0x13, 0x03, 0xff, 0xfa, 0x3f], 2, 8,
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    cpu.reg.s_z(true);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+
+        #[test]
+        fn write_indirect_y() {
+            run(vec![0x91, 0x03, 0xff, 0xfa, 0x3f], 2, 6,
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+
+        #[test]
+        fn write_absolute_x() {
+            run(vec![0x9D, 0xfa, 0x3f], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+
+        #[test]
+        fn write_absolute_y() {
+            run(vec![0x99, 0xfa, 0x3f], 3, 5,
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_y(0x08);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+
+        #[test]
+        fn read_absolute_x() {
+            run(vec![0xBD, 0xfa, 0x3f], 3, 5,
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    set_vblank(cpu);
+                },
+                |cpu| {
+                    cpu.reg.s_x(0x08);
+                    cpu.reg.s_z(true);
+                    assert_vblank_clear(cpu);
+                },
+            );
+        }
+    }
+
+    mod profile {
+        use super::*;
+
+        // LDX #$05; loop: DEX; BNE loop
+        fn run_loop(profiling: bool) -> Cpu {
+            let bus = Bus::with_mem(&[0xA2, 0x05, 0xCA, 0xD0, 0xFD]);
+            let mut cpu = Cpu::new(Rc::new(RefCell::new(bus)));
+            cpu.reg.s_pc(0x00);
+            cpu.profiling = profiling;
+
+            while cpu.reg.get_pc() != 0x05 { cpu.step_instruction(); }
+            cpu
+        }
+
+        #[test]
+        fn report() {
+            let cpu = run_loop(true);
+
+            // BNE is taken four times (3 cycles) and falls through once (2 cycles).
+            assert_eq!(cpu.profile_report(), vec![(0xD0, 14), (0xCA, 10), (0xA2, 2)]);
+        }
+
+        #[test]
+        fn disabled() {
+            let cpu = run_loop(false);
+            assert!(cpu.profile_report().is_empty());
+        }
+
+        #[test]
+        fn clear() {
+            let mut cpu = run_loop(true);
+            cpu.clear_profile();
+            assert!(cpu.profile_report().is_empty());
         }
     }
 
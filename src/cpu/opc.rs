@@ -1,4 +1,5 @@
 use crate::cpu::Cpu;
+use crate::cpu::DMC_DMA_CYCLES;
 use crate::cpu::cycle;
 use crate::cpu::flags;
 use crate::cpu::log::AddrMode;
@@ -13,7 +14,25 @@ impl Cpu {
     // First cycle is always fetching the opcode.
     fn finish(&mut self) { self.reg.set_next_to_last_cycle() }
 
+    // No instruction has this cycle, so the state is corrupted. Give up on the instruction.
+    fn unexpected_cycle(&mut self) {
+        error!("Shouldn't reach cycle {}", self.reg.get_cycle());
+        self.faulted = true;
+        self.finish();
+    }
+
+    // Addressing mode of the current instruction. Kept for debuggers besides the log.
+    fn set_mode(&mut self, mode: AddrMode) {
+        self.effective_addr = mode.effective_addr();
+        self.log.set_mode(mode);
+    }
+
     fn read(&mut self, addr: u16) -> u8 {
+        if self.dmc_halted_read {
+            self.dmc_halted_read = false;
+            self.bus.borrow_mut().read_cpu(addr);
+        }
+
         self.reg.addr_bus(addr, self.bus.borrow_mut().read_cpu(addr))
     }
 
@@ -108,6 +127,25 @@ impl Cpu {
         self.write(self.reg.get_absolute(), self.reg.get_q())
     }
 
+    // Unstable stores AND the value with the high byte of the base address plus one.
+    // On a page cross that's the high byte of the effective address. Otherwise it's the next page.
+    // This is what most consoles do as long as no DMA happens during the instruction.
+    fn unstable_high(&self, addr: u16) -> u8 {
+        let high = bits::high(addr);
+        match self.reg.get_internal_overflow() {
+            reg::InternalOverflow::None => high.wrapping_add(1),
+            _ => high,
+        }
+    }
+
+    // When an unstable store crosses a page the high byte of the address is replaced by the value stored.
+    fn unstable_addr(&self, addr: u16, data: u8) -> u16 {
+        match self.reg.get_internal_overflow() {
+            reg::InternalOverflow::None => addr,
+            _ => bits::set_high(addr, data),
+        }
+    }
+
     // Pull a value from the stack
     fn read_stack(&mut self) -> u8 {
         let addr = self.reg.get_stack_addr();
@@ -122,19 +160,20 @@ impl Cpu {
 
     // Kil opcode.
     // 2    PC     read next instruction byte (and throw it away).
-    // 3    PC     revert cycle to the first one so it won't advance.
+    // 3    PC     halt the CPU. Only a reset brings it back.
     pub fn kil(&mut self) {
         match self.reg.get_cycle() {
             cycle::T2 => {
                 self.log.set_mnemonic("KIL");
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
                 self.read_pc();
             }
             cycle::T3 => {
                 self.reg.set_first_cycle();
-                panic!("Kil opcode finished running. Aborting program.");
+                self.halted = true;
+                warn!("Kil opcode finished running. CPU is halted.");
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -153,7 +192,7 @@ impl Cpu {
                     trace!("{}", if bus.nmi { "NMI" } else if bus.irq { "IRC" } else { "BRK" });
                 }
                 self.log.set_mnemonic("BRK");
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
                 self.fetch_pc();
             }
             cycle::T3 => { self.push(self.reg.get_pch()); }
@@ -203,7 +242,7 @@ impl Cpu {
                 self.interrupting = false;
                 self.finish();
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -235,7 +274,7 @@ impl Cpu {
                 self.bus.borrow_mut().reset = false;
                 self.finish();
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -282,6 +321,12 @@ impl Cpu {
         ("LAX", data)
     }
 
+    // LAX immediate. Unstable like XAA, with "magic" deciding which bits of A take part.
+    pub fn lxa(&mut self, data: u8) -> (&'static str, u8) {
+        let data = (self.reg.get_a() | self.unstable_magic) & data;
+        self.lax(data)
+    }
+
     // endregion
 
     // region Store
@@ -306,7 +351,8 @@ impl Cpu {
 
     pub fn shx(&mut self, addr: u16) -> (&'static str, u8) {
         let x = self.reg.get_x();
-        let data = x & (bits::high(addr) + 1);
+        let data = x & self.unstable_high(addr);
+        let addr = self.unstable_addr(addr, data);
         self.write(addr, data);
 
         self.log.set_unofficial(true);
@@ -315,7 +361,8 @@ impl Cpu {
 
     pub fn shy(&mut self, addr: u16) -> (&'static str, u8) {
         let y = self.reg.get_y();
-        let data = y & (bits::high(addr) + 1);
+        let data = y & self.unstable_high(addr);
+        let addr = self.unstable_addr(addr, data);
         self.write(addr, data);
 
         self.log.set_unofficial(true);
@@ -326,7 +373,7 @@ impl Cpu {
         let a = self.reg.get_a();
         let x = self.reg.get_x();
 
-        let data = a & x & (bits::high(addr) + 1);
+        let data = a & x & self.unstable_high(addr);
         self.write(addr, data);
 
         self.log.set_unofficial(true);
@@ -339,7 +386,7 @@ impl Cpu {
         let s = a & x;
         self.reg.set_s(s);
 
-        let data = s & (bits::high(addr) + 1);
+        let data = s & self.unstable_high(addr);
         self.write(addr, data);
 
         self.log.set_unofficial(true);
@@ -414,6 +461,22 @@ impl Cpu {
         }
     }
 
+    // Halt, wait to align with the APU and then read the sample for the DMC.
+    pub fn dmc_dma(&mut self) {
+        if self.dmc_stall == 0 {
+            self.dmc_stall = DMC_DMA_CYCLES;
+            self.dmc_halted_read = self.dmc_read_corruption;
+        }
+        self.dmc_stall -= 1;
+        if self.dmc_stall > 0 { return; }
+
+        let mut bus = self.bus.borrow_mut();
+        if let Some(addr) = bus.apu.dmc_dma.take() {
+            trace!(target: "opcode", "dmc, 0x{:04x}", addr);
+            bus.apu.dmc_sample = Some(bus.read_cpu(addr));
+        }
+    }
+
     // endregion
 
     // region Stack
@@ -503,10 +566,7 @@ impl Cpu {
 
         // XAA has analogic behaviour.
         // "Magic" defines which bits of A will be used in the result and can vary wildly.
-        // Below values happen over 98% of the time.
-        let magic = if (a & 0x01) == 0 { 0xfe } else { 0xff };
-
-        let data = (a | magic) & self.reg.get_x() & data;
+        let data = (a | self.unstable_magic) & self.reg.get_x() & data;
         self.reg.write_a(data);
 
         self.log.set_unofficial(true);
@@ -796,23 +856,19 @@ impl Cpu {
 
     pub fn arr(&mut self, data: u8) -> (&'static str, u8) {
         let a = self.reg.get_a();
-        let p = self.reg.get_p_mut();
-        let carry = p.get_carry();
-
-        let value = (data & a) >> 1;
-        let res = value | ((carry as u8) << 7);
+        let carry = self.reg.get_p().get_carry();
 
-        p.toggle(flags::NEGATIVE);
-        p.clear(flags::OVERFLOW | flags::CARRY);
+        // AND then ROR. Zero and Negative come from the result.
+        let res = ((data & a) >> 1) | ((carry as u8) << 7);
+        self.reg.write_a(res);
 
-        match value & 0x60 {
-            0x20 => p.set(flags::OVERFLOW),
-            0x40 => p.set(flags::OVERFLOW | flags::CARRY),
-            0x60 => p.set(flags::CARRY),
-            _ => {}
-        }
+        // Carry is bit 6 of the result and Overflow is bit 6 xor bit 5. The 2A03 has no decimal mode.
+        let bit6 = res & 0x40 != 0;
+        let bit5 = res & 0x20 != 0;
 
-        self.reg.write_a(res);
+        let p = self.reg.get_p_mut();
+        p.change(flags::CARRY, bit6);
+        p.change(flags::OVERFLOW, bit6 ^ bit5);
 
         self.log.set_unofficial(true);
         ("ARR", res)
@@ -837,11 +893,11 @@ impl Cpu {
                 self.reg.write_pc(pc);
 
                 self.log.set_mnemonic("JMP");
-                self.log.set_mode(AddrMode::Direct(pc));
+                self.set_mode(AddrMode::Direct(pc));
 
                 self.finish();
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -850,28 +906,44 @@ impl Cpu {
     // 3     PC      fetch pointer address high, increment PC
     // 4   pointer   fetch low address to latch
     // 5  pointer+1* fetch PCH, copy latch to PCL
+    // * The NMOS 6502 doesn't carry into the pointer high byte. The 65C02 does.
+    //
+    // The 65C02 takes an extra cycle to fix it:
+    // 4    PC-1     read pointer address high again
+    // 5   pointer   fetch low address to latch
+    // 6  pointer+1  fetch PCH, copy latch to PCL
     pub fn jmp_indirect(&mut self) {
-        match self.reg.get_cycle() {
-            cycle::T2 => { self.fetch_into_m(); }
-            cycle::T3 => { self.fetch_into_n(); }
-            cycle::T4 => { self.read_absolute_to_q(); }
-            cycle::T5 => {
-                // Logs the correct address, respecting cross page.
-                let pcl = self.reg.get_q();
-                let pch = self.peek(self.reg.get_absolute().wrapping_add(1));
-                let addr = bits::word(pch, pcl);
-                self.log.set_mnemonic("JMP");
-                self.log.set_mode(AddrMode::Indirect(self.reg.get_absolute(), addr));
+        match (self.reg.get_cycle(), self.cmos) {
+            (cycle::T2, _) => { self.fetch_into_m(); }
+            (cycle::T3, _) => { self.fetch_into_n(); }
+            (cycle::T4, false) => { self.read_absolute_to_q(); }
+            (cycle::T4, true) => { self.read(self.reg.get_pc().wrapping_sub(1)); }
+            (cycle::T5, false) => { self.jmp_indirect_pch(); }
+            (cycle::T5, true) => { self.read_absolute_to_q(); }
+            (cycle::T6, true) => { self.jmp_indirect_pch(); }
+            _ => self.unexpected_cycle(),
+        }
+    }
 
-                // A bug on the hardware makes it not respect page cross. This is emulate here.
-                self.reg.write_inc_m(1);
-                self.read_absolute_to_q();
-                self.reg.write_pcl_pch(pcl, self.reg.get_q());
+    fn jmp_indirect_pch(&mut self) {
+        // Logs the correct address, respecting cross page.
+        let pcl = self.reg.get_q();
+        let pch = self.peek(self.reg.get_absolute().wrapping_add(1));
+        let addr = bits::word(pch, pcl);
+        self.log.set_mnemonic("JMP");
+        self.set_mode(AddrMode::Indirect(self.reg.get_absolute(), addr));
 
-                self.finish();
-            }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+        if self.cmos {
+            let pch = self.read(self.reg.get_absolute().wrapping_add(1));
+            self.reg.write_pcl_pch(pcl, pch);
+        } else {
+            // A bug on the hardware makes it not respect page cross. This is emulate here.
+            self.reg.write_inc_m(1);
+            self.read_absolute_to_q();
+            self.reg.write_pcl_pch(pcl, self.reg.get_q());
         }
+
+        self.finish();
     }
 
     // Jump to Subroutine
@@ -894,9 +966,9 @@ impl Cpu {
                 self.finish();
 
                 self.log.set_mnemonic("JSR");
-                self.log.set_mode(AddrMode::Direct(self.reg.get_pc()));
+                self.set_mode(AddrMode::Direct(self.reg.get_pc()));
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -926,9 +998,9 @@ impl Cpu {
                 trace!(target: "opcode", "rti, addr: 0x{:04x}", self.reg.get_pc());
 
                 self.log.set_mnemonic("RTI");
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -955,9 +1027,9 @@ impl Cpu {
                 self.finish();
 
                 self.log.set_mnemonic("RTS");
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -1038,12 +1110,15 @@ impl Cpu {
                 None
             }
             cycle::T3 => {
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
                 self.reg.set_inc_s(-1);
                 self.finish();
                 Some(self.reg.get_next_stack_addr())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1058,11 +1133,14 @@ impl Cpu {
             }
             cycle::T3 => {
                 let m = self.reg.get_m();
-                self.log.set_mode(AddrMode::ZeroPage(m, self.peek(u16::from(m))));
+                self.set_mode(AddrMode::ZeroPage(m, self.peek(u16::from(m))));
                 self.finish();
                 Some(u16::from(m))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1085,21 +1163,24 @@ impl Cpu {
                 self.finish();
                 Some(u16::from(self.reg.get_m()))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
     pub fn w_zero_page_x(&mut self) -> Option<u16> {
         let index = self.reg.get_x();
         let addr = self.w_zero_page_indexed(index)?;
-        self.log.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), addr as u8, self.peek(addr)));
+        self.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), addr as u8, self.peek(addr)));
         Some(addr)
     }
 
     pub fn w_zero_page_y(&mut self) -> Option<u16> {
         let index = self.reg.get_y();
         let addr = self.w_zero_page_indexed(index)?;
-        self.log.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), addr as u8, self.peek(addr)));
+        self.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), addr as u8, self.peek(addr)));
         Some(addr)
     }
 
@@ -1120,10 +1201,13 @@ impl Cpu {
             cycle::T4 => {
                 self.finish();
                 let addr = self.reg.get_absolute();
-                self.log.set_mode(AddrMode::Absolute(addr, self.peek(addr)));
+                self.set_mode(AddrMode::Absolute(addr, self.peek(addr)));
                 Some(addr)
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1144,7 +1228,7 @@ impl Cpu {
                 None
             }
             cycle::T4 => {
-                self.reg.get_absolute();
+                self.read_absolute();
                 self.reg.set_fix_carry_n();
                 None
             }
@@ -1152,14 +1236,17 @@ impl Cpu {
                 self.finish();
                 Some(self.reg.get_absolute())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
     pub fn w_absolute_x(&mut self) -> Option<u16> {
         let index = self.reg.get_x();
         let addr = self.w_absolute_indexed(index)?;
-        self.log.set_mode(AddrMode::AbsoluteX(
+        self.set_mode(AddrMode::AbsoluteX(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1171,7 +1258,7 @@ impl Cpu {
     pub fn w_absolute_y(&mut self) -> Option<u16> {
         let index = self.reg.get_y();
         let addr = self.w_absolute_indexed(index)?;
-        self.log.set_mode(AddrMode::AbsoluteY(
+        self.set_mode(AddrMode::AbsoluteY(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1210,7 +1297,7 @@ impl Cpu {
                 let addr = self.reg.get_absolute();
                 let op0 = self.peek(self.reg.get_pc() - 1);
 
-                self.log.set_mode(AddrMode::IndirectX(
+                self.set_mode(AddrMode::IndirectX(
                     op0,
                     op0.wrapping_add(self.reg.get_x()),
                     addr,
@@ -1220,7 +1307,10 @@ impl Cpu {
                 self.finish();
                 Some(addr)
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1247,14 +1337,14 @@ impl Cpu {
                 None
             }
             cycle::T5 => {
-                self.reg.get_absolute();
+                self.read_absolute();
                 self.reg.set_fix_carry_n();
                 None
             }
             cycle::T6 => {
                 let addr = self.reg.get_absolute();
                 let table = addr.wrapping_sub(u16::from(self.reg.get_y()));
-                self.log.set_mode(AddrMode::IndirectY(
+                self.set_mode(AddrMode::IndirectY(
                     self.peek(self.reg.get_pc() - 1),
                     table,
                     addr,
@@ -1264,7 +1354,10 @@ impl Cpu {
                 self.finish();
                 Some(addr)
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1277,14 +1370,17 @@ impl Cpu {
     pub fn implied(&mut self) -> Option<()> {
         match self.reg.get_cycle() {
             cycle::T2 => {
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
 
                 self.read_pc();
                 self.finish();
 
                 Some(())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1293,12 +1389,15 @@ impl Cpu {
     pub fn accumulator(&mut self) -> Option<u8> {
         match self.reg.get_cycle() {
             cycle::T2 => {
-                self.log.set_mode(AddrMode::Accumulator);
+                self.set_mode(AddrMode::Accumulator);
                 self.read_pc();
                 self.finish();
                 Some(self.reg.read_a())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1307,11 +1406,14 @@ impl Cpu {
     pub fn immediate(&mut self) -> Option<u8> {
         match self.reg.get_cycle() {
             cycle::T2 => {
-                self.log.set_mode(AddrMode::Immediate(self.peek(self.reg.get_pc())));
+                self.set_mode(AddrMode::Immediate(self.peek(self.reg.get_pc())));
                 self.finish();
                 Some(self.fetch_pc())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1330,11 +1432,14 @@ impl Cpu {
                 None
             }
             cycle::T4 => {
-                self.log.set_mode(AddrMode::Implied);
+                self.set_mode(AddrMode::Implied);
                 self.finish();
                 Some(self.read_stack())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1349,7 +1454,7 @@ impl Cpu {
         let addr = self.w_zero_page_indexed(index)?;
         let data = self.read_at_m(addr);
 
-        self.log.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), addr as u8, data));
+        self.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), addr as u8, data));
 
         Some(data)
     }
@@ -1359,7 +1464,7 @@ impl Cpu {
         let addr = self.w_zero_page_indexed(index)?;
         let data = self.read_at_m(addr);
 
-        self.log.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), addr as u8, data));
+        self.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), addr as u8, data));
 
         Some(data)
     }
@@ -1399,7 +1504,10 @@ impl Cpu {
                 self.finish();
                 Some((self.reg.get_absolute(), self.read_absolute()))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1407,7 +1515,7 @@ impl Cpu {
         let index = self.reg.get_x();
         let res = self.r_absolute_indexed(index)?;
 
-        self.log.set_mode(AddrMode::AbsoluteX(
+        self.set_mode(AddrMode::AbsoluteX(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1421,7 +1529,7 @@ impl Cpu {
         let index = self.reg.get_y();
         let res = self.r_absolute_indexed(index)?;
 
-        self.log.set_mode(AddrMode::AbsoluteY(
+        self.set_mode(AddrMode::AbsoluteY(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1459,7 +1567,7 @@ impl Cpu {
 
                 let absolute = self.reg.get_absolute();
                 let addr = absolute.wrapping_add(u16::from(self.reg.get_y()));
-                self.log.set_mode(AddrMode::IndirectY(
+                self.set_mode(AddrMode::IndirectY(
                     self.peek(self.reg.get_pc() - 1),
                     absolute,
                     addr,
@@ -1482,7 +1590,10 @@ impl Cpu {
                 self.finish();
                 Some(self.read_absolute())
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1501,8 +1612,10 @@ impl Cpu {
                     //let pc = (bits::high_word(pc)) | bits::low_word(pc).wrapping_add(u16::from(self.reg.get_m()));
 
                     let m = i16::from(self.reg.get_m() as i8) as u16;
-                    self.log.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc().wrapping_add(m)));
+                    self.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc().wrapping_add(m)));
                     self.finish();
+                } else {
+                    self.branch_polled_interrupt = self.is_interrupt_pending();
                 }
             }
             cycle::T3 => {
@@ -1512,17 +1625,18 @@ impl Cpu {
                 self.reg.write_inc_pcl(m);
 
                 if self.reg.get_internal_overflow() == reg::InternalOverflow::None {
-                    self.log.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc()));
+                    self.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc()));
+                    self.delay_interrupt = !self.branch_polled_interrupt;
                     self.finish();
                 }
             }
             cycle::T4 => {
                 self.prefetch_pc();
                 self.reg.set_fix_carry_pc();
-                self.log.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc()));
+                self.set_mode(AddrMode::Relative(self.reg.get_m(), self.reg.get_pc()));
                 self.finish()
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => self.unexpected_cycle(),
         }
     }
 
@@ -1552,11 +1666,14 @@ impl Cpu {
             cycle::T5 => {
                 let m = self.reg.get_m();
                 let n = self.reg.get_n();
-                self.log.set_mode(AddrMode::ZeroPage(m, n));
+                self.set_mode(AddrMode::ZeroPage(m, n));
                 self.finish();
                 Some((u16::from(m), n))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1589,21 +1706,24 @@ impl Cpu {
                 self.finish();
                 Some((u16::from(self.reg.get_m()), self.reg.get_n()))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
     pub fn rw_zero_page_x(&mut self) -> Option<(u16, u8)> {
         let index = self.reg.get_x();
         let res = self.rw_zero_page_indexed(index)?;
-        self.log.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), res.0 as u8, res.1));
+        self.set_mode(AddrMode::ZeroPageX(self.peek(self.reg.get_pc() - 1), res.0 as u8, res.1));
         Some(res)
     }
 
     pub fn rw_zero_page_y(&mut self) -> Option<(u16, u8)> {
         let index = self.reg.get_y();
         let res = self.rw_zero_page_indexed(index)?;
-        self.log.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), res.0 as u8, res.1));
+        self.set_mode(AddrMode::ZeroPageY(self.peek(self.reg.get_pc() - 1), res.0 as u8, res.1));
         Some(res)
     }
 
@@ -1633,11 +1753,14 @@ impl Cpu {
             }
             cycle::T6 => {
                 let res = (self.reg.get_absolute(), self.reg.get_q());
-                self.log.set_mode(AddrMode::Absolute(res.0, res.1));
+                self.set_mode(AddrMode::Absolute(res.0, res.1));
                 self.finish();
                 Some(res)
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1676,7 +1799,10 @@ impl Cpu {
                 self.finish();
                 Some((self.reg.get_absolute(), self.reg.get_q()))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1684,7 +1810,7 @@ impl Cpu {
         let index = self.reg.get_x();
         let res = self.rw_absolute_indexed(index)?;
 
-        self.log.set_mode(AddrMode::AbsoluteX(
+        self.set_mode(AddrMode::AbsoluteX(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1698,7 +1824,7 @@ impl Cpu {
         let index = self.reg.get_y();
         let res = self.rw_absolute_indexed(index)?;
 
-        self.log.set_mode(AddrMode::AbsoluteY(
+        self.set_mode(AddrMode::AbsoluteY(
             bits::word(
                 self.peek(self.reg.get_pc() - 1),
                 self.peek(self.reg.get_pc() - 2)),
@@ -1749,7 +1875,7 @@ impl Cpu {
                 let op0 = self.peek(self.reg.get_pc() - 1);
                 let data = self.reg.get_q();
 
-                self.log.set_mode(AddrMode::IndirectX(
+                self.set_mode(AddrMode::IndirectX(
                     op0,
                     op0.wrapping_add(self.reg.get_x()),
                     addr,
@@ -1759,7 +1885,10 @@ impl Cpu {
                 self.finish();
                 Some((addr, data))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -1807,7 +1936,7 @@ impl Cpu {
                 // Logs the correct address, respecting cross page.
                 let op0 = self.peek(self.reg.get_pc() - 1);
 
-                self.log.set_mode(AddrMode::IndirectY(
+                self.set_mode(AddrMode::IndirectY(
                     op0,
                     addr.wrapping_sub(u16::from(self.reg.get_y())),
                     addr,
@@ -1817,7 +1946,10 @@ impl Cpu {
                 self.finish();
                 Some((addr, data))
             }
-            _ => unimplemented!("Shouldn't reach cycle {}", self.reg.get_cycle()),
+            _ => {
+                self.unexpected_cycle();
+                None
+            }
         }
     }
 
@@ -6,10 +6,12 @@ use crate::cpu::flags::Flags;
 use crate::utils::bits;
 
 #[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InternalOverflow { None, Positive, Negative }
 
 // Registers
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Reg {
     // Accumulator
     a: u8,
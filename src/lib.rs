@@ -1,16 +1,14 @@
-#![feature(box_syntax)]
-#![feature(core_intrinsics)]
-#![feature(duration_float)]
-#![feature(nll)]
-
-extern crate env_logger;
 #[macro_use]
 extern crate log;
 
+pub mod apu;
+pub mod asm;
 pub mod bus;
 pub mod cartridge;
 pub mod console;
 pub mod cpu;
+pub mod movie;
+pub mod nsf;
 pub mod ppu;
 pub mod ui;
 pub mod utils;
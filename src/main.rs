@@ -5,11 +5,12 @@ use nespera::console::Console;
 use nespera::cpu::log::setup;
 use nespera::ui;
 use nespera::ui::palette::Palette;
+use nespera::ui::UiConfig;
 
 fn main() {
     setup(LevelFilter::Off, 0);
     let cartridge = Cartridge::from_file("tests/resources/roms/Balloon Fight (JU).nes").unwrap();
-    let palette = Palette::from_file("tests/resources/palettes/RP2C03.pal").unwrap();
+    let palette = Palette::with_emphasis(&Palette::from_file("tests/resources/palettes/RP2C03.pal").unwrap());
     let mut console = Console::new(cartridge);
-    ui::run(&mut console, &palette);
+    ui::run(&mut console, &palette, &UiConfig::default());
 }
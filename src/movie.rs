@@ -0,0 +1,30 @@
+// Controller states for each frame. Both ports are stored as the bits of their buttons.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Movie {
+    pub frames: Vec<(u8, u8)>,
+}
+
+impl Movie {
+    pub fn new() -> Self { Self { frames: Vec::new() } }
+
+    pub fn len(&self) -> usize { self.frames.len() }
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+}
+
+// Movie being played back.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Playback {
+    pub fn new(movie: Movie) -> Self { Self { movie, frame: 0 } }
+
+    // Input for the next frame. None when the movie is over.
+    pub fn next_frame(&mut self) -> Option<(u8, u8)> {
+        let res = self.movie.frames.get(self.frame).cloned();
+        self.frame += 1;
+        res
+    }
+}
@@ -0,0 +1,335 @@
+use crate::cartridge::CartridgeLike;
+use crate::cartridge::console_read_location;
+use crate::cartridge::console_write_location;
+use crate::cartridge::LoadError;
+use crate::cartridge::location::Location;
+use crate::ppu::Region;
+use crate::utils::bits;
+
+const HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+const PRG_RAM_CAPACITY: usize = 0x2000;
+
+// Where the driver lives. Unused by the console and below the bank registers.
+const DRIVER_START: u16 = 0x5000;
+const DRIVER_NMI: u16 = DRIVER_START + 0x32;
+const DRIVER_IRQ: u16 = DRIVER_START + 0x36;
+
+// Bank selects. One for each 4kb of $8000-$FFFF.
+const BANK_REGS_START: u16 = 0x5ff8;
+
+// Header of a NES Sound Format file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Nsf {
+    pub songs: u8,
+
+    // One based, like in the file.
+    pub starting_song: u8,
+
+    // Addresses in the CPU
+    pub load: u16,
+    pub init: u16,
+    pub play: u16,
+
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+
+    // Microseconds between calls to play
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+
+    // Initial banks. All zeros means the tune isn't banked.
+    pub banks: [u8; 8],
+    pub region: Region,
+
+    data: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn new(data: &[u8]) -> Result<Self, LoadError> {
+        if data.len() < HEADER_SIZE || data.get(0..5) != Some(&b"NESM\x1a"[..]) {
+            return Err(LoadError::InvalidHeader);
+        }
+
+        let word = |addr: usize| bits::word(data[addr + 1], data[addr]);
+
+        // Strings are padded with zeros.
+        let text = |addr: usize| {
+            let text = &data[addr..addr + 32];
+            let len = text.iter().position(|&c| c == 0).unwrap_or(text.len());
+            String::from_utf8_lossy(&text[..len]).into_owned()
+        };
+
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+
+        // Dual region tunes play as NTSC.
+        let region = if data[0x7a] & 0b11 == 0b01 { Region::Pal } else { Region::Ntsc };
+
+        Ok(Self {
+            songs: data[0x06],
+            starting_song: data[0x07],
+            load: word(0x08),
+            init: word(0x0a),
+            play: word(0x0c),
+            name: text(0x0e),
+            artist: text(0x2e),
+            copyright: text(0x4e),
+            ntsc_speed: word(0x6e),
+            pal_speed: word(0x78),
+            banks,
+            region,
+            data: data[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    pub fn is_banked(&self) -> bool { self.banks.iter().any(|&bank| bank != 0) }
+}
+
+// Fake cartridge that maps the tune and a small driver.
+// The driver calls init once and then play on every vblank.
+#[derive(Debug)]
+pub struct NsfCartridge {
+    nsf: Nsf,
+    driver: Vec<u8>,
+    banks: [u8; 8],
+    prg_ram: Vec<u8>,
+}
+
+impl NsfCartridge {
+    // Song is zero based, like the value init receives.
+    pub fn new(nsf: Nsf, song: u8) -> Result<Self, LoadError> {
+        if song >= nsf.songs { return Err(LoadError::SongNotFound(song)); }
+
+        let region = if nsf.region == Region::Pal { 1 } else { 0 };
+        let (init_high, init_low) = (bits::high(nsf.init), bits::low(nsf.init));
+        let (play_high, play_low) = (bits::high(nsf.play), bits::low(nsf.play));
+
+        let driver = vec![
+            0x78,                   // 5000 SEI
+            0xd8,                   // 5001 CLD
+            0xa2, 0xff,             // 5002 LDX #$FF
+            0x9a,                   // 5004 TXS
+
+            // The PPU ignores PPUCTRL until it warms up, so wait for two vblanks
+            0x2c, 0x02, 0x20,       // 5005 BIT $2002
+            0x10, 0xfb,             // 5008 BPL $5005
+            0x2c, 0x02, 0x20,       // 500A BIT $2002
+            0x10, 0xfb,             // 500D BPL $500A
+
+            // Silence the APU
+            0xa9, 0x00,             // 500F LDA #$00
+            0xa2, 0x13,             // 5011 LDX #$13
+            0x9d, 0x00, 0x40,       // 5013 STA $4000,X
+            0xca,                   // 5016 DEX
+            0x10, 0xfa,             // 5017 BPL $5013
+            0xa9, 0x0f,             // 5019 LDA #$0F
+            0x8d, 0x15, 0x40,       // 501B STA $4015
+            0xa9, 0x40,             // 501E LDA #$40
+            0x8d, 0x17, 0x40,       // 5020 STA $4017
+
+            // Init receives the song in A and the region in X
+            0xa9, song,             // 5023 LDA #song
+            0xa2, region,           // 5025 LDX #region
+            0x20, init_low, init_high, // 5027 JSR init
+
+            // Play is called by the vblank NMI
+            0xa9, 0x80,             // 502A LDA #$80
+            0x8d, 0x00, 0x20,       // 502C STA $2000
+            0x4c, 0x2f, 0x50,       // 502F JMP $502F
+
+            0x20, play_low, play_high, // 5032 JSR play
+            0x40,                   // 5035 RTI
+
+            0x40,                   // 5036 RTI
+        ];
+
+        let banks = nsf.banks;
+        Ok(Self { nsf, driver, banks, prg_ram: vec![0; PRG_RAM_CAPACITY] })
+    }
+
+    pub fn nsf(&self) -> &Nsf { &self.nsf }
+
+    // Offset of the tune data for a CPU address between $8000 and $FFFF.
+    fn data_index(&self, addr: u16) -> Option<usize> {
+        let addr = usize::from(addr);
+
+        let index = if self.nsf.is_banked() {
+            // Data is padded so the load address falls on its offset in the first bank.
+            let bank = usize::from(self.banks[(addr - 0x8000) / BANK_SIZE]);
+            let padding = usize::from(self.nsf.load) % BANK_SIZE;
+            (bank * BANK_SIZE + addr % BANK_SIZE).checked_sub(padding)
+        } else {
+            // Loaded as is at its address.
+            addr.checked_sub(usize::from(self.nsf.load))
+        };
+
+        index.filter(|&index| index < self.nsf.data.len())
+    }
+}
+
+impl CartridgeLike for NsfCartridge {
+    fn cpu_read_location(&self, addr: u16) -> Location {
+        match addr {
+            0x4020..=0x5fff => Location::PrgRom(addr),
+            0x6000..=0x7fff => Location::PrgRam(addr - 0x6000),
+            0x8000..=0xffff => Location::PrgRom(addr),
+            _ => console_read_location(addr),
+        }
+    }
+
+    fn cpu_write_location(&self, addr: u16) -> Location {
+        match addr {
+            BANK_REGS_START..=0x5fff => Location::CartridgeRegister(addr),
+            0x6000..=0x7fff => Location::PrgRam(addr - 0x6000),
+            0x8000..=0xffff => Location::PrgRom(addr),
+            _ => console_write_location(addr),
+        }
+    }
+
+    // Nothing is drawn. Keep the PPU quiet with the usual map.
+    fn ppu_read_location(&self, addr: u16) -> Location {
+        match addr & 0x3fff {
+            addr @ 0x0000..=0x1fff => Location::ChrRom(addr),
+            addr @ 0x2000..=0x3eff => Location::Vram(addr & 0x07ff),
+            addr => Location::Palette(addr & 0x001f),
+        }
+    }
+
+    fn ppu_write_location(&self, addr: u16) -> Location { self.ppu_read_location(addr) }
+
+    // Addresses are the ones in the CPU.
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0xfffa => bits::low(DRIVER_NMI),
+            0xfffb => bits::high(DRIVER_NMI),
+            0xfffc => bits::low(DRIVER_START),
+            0xfffd => bits::high(DRIVER_START),
+            0xfffe => bits::low(DRIVER_IRQ),
+            0xffff => bits::high(DRIVER_IRQ),
+            0x8000..=0xffff => self.data_index(addr).map_or(0, |index| self.nsf.data[index]),
+            _ => addr.checked_sub(DRIVER_START)
+                .and_then(|index| self.driver.get(usize::from(index)))
+                .cloned()
+                .unwrap_or(0),
+        }
+    }
+
+    fn read_chr_rom(&self, _addr: u16) -> u8 { 0 }
+
+    fn read_prg_ram(&self, addr: u16) -> u8 { self.prg_ram[usize::from(addr) % PRG_RAM_CAPACITY] }
+
+    fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram[usize::from(addr) % PRG_RAM_CAPACITY] = data
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if addr >= BANK_REGS_START { self.banks[usize::from(addr - BANK_REGS_START)] = data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::console::Console;
+
+    use super::*;
+
+    // Tune loaded at $8000. Init starts a square wave and play counts the frames at $10.
+    fn tune(banks: [u8; 8]) -> Vec<u8> {
+        let mut nsf = b"NESM\x1a\x01\x02\x01".to_vec();
+        nsf.extend(&[0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+        nsf.resize(HEADER_SIZE, 0);
+        nsf[0x0e..0x12].copy_from_slice(b"Beep");
+        nsf[0x6e] = 0x1a;
+        nsf[0x6f] = 0x41;
+        nsf[0x70..0x78].copy_from_slice(&banks);
+
+        nsf.extend(&[
+            0xa9, 0xbf,       // LDA #$BF
+            0x8d, 0x00, 0x40, // STA $4000
+            0xa9, 0xff,       // LDA #$FF
+            0x8d, 0x02, 0x40, // STA $4002
+            0xa9, 0x08,       // LDA #$08
+            0x8d, 0x03, 0x40, // STA $4003
+            0x60,             // RTS
+
+            0xe6, 0x10,       // INC $10
+            0x60,             // RTS
+        ]);
+
+        nsf
+    }
+
+    #[test]
+    fn header() {
+        let nsf = Nsf::new(&tune([0; 8])).unwrap();
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.load, 0x8000);
+        assert_eq!(nsf.init, 0x8000);
+        assert_eq!(nsf.play, 0x8010);
+        assert_eq!(nsf.name, "Beep");
+        assert_eq!(nsf.artist, "");
+        assert_eq!(nsf.ntsc_speed, 16666);
+        assert_eq!(nsf.region, Region::Ntsc);
+        assert!(!nsf.is_banked());
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(match Nsf::new(b"NES\x1a") {
+            Err(LoadError::InvalidHeader) => true,
+            _ => false
+        });
+    }
+
+    #[test]
+    fn song_not_found() {
+        let nsf = Nsf::new(&tune([0; 8])).unwrap();
+        assert!(match NsfCartridge::new(nsf, 2) {
+            Err(LoadError::SongNotFound(2)) => true,
+            _ => false
+        });
+    }
+
+    #[test]
+    fn vectors() {
+        let cartridge = NsfCartridge::new(Nsf::new(&tune([0; 8])).unwrap(), 0).unwrap();
+        assert_eq!(cartridge.read_prg_rom(0xfffc), 0x00);
+        assert_eq!(cartridge.read_prg_rom(0xfffd), 0x50);
+        assert_eq!(cartridge.read_prg_rom(DRIVER_NMI), 0x20);
+        assert_eq!(cartridge.read_prg_rom(DRIVER_IRQ), 0x40);
+    }
+
+    #[test]
+    fn play() {
+        let mut console = Console::from_nsf(&tune([0; 8]), 0).unwrap();
+        assert_eq!(console.bus.borrow().apu.sample(), 0.0);
+
+        console.run_frames(6);
+
+        let bus = console.bus.borrow();
+        assert!(bus.apu.sample() > 0.0);
+
+        // Called once per frame after the driver waits for the PPU to warm up.
+        let plays = bus.peek_cpu(0x0010);
+        assert!((3..=4).contains(&plays), "{}", plays);
+    }
+
+    #[test]
+    fn bank_switching() {
+        // Second bank of the data is mapped at $9000 and then at $8000.
+        let mut data = tune([0, 1, 2, 3, 4, 5, 6, 7]);
+        data.resize(HEADER_SIZE + 2 * BANK_SIZE, 0);
+        data[HEADER_SIZE + BANK_SIZE] = 0x42;
+
+        let mut cartridge = NsfCartridge::new(Nsf::new(&data).unwrap(), 0).unwrap();
+        assert_eq!(cartridge.read_prg_rom(0x8000), 0xa9);
+        assert_eq!(cartridge.read_prg_rom(0x9000), 0x42);
+
+        assert_eq!(cartridge.cpu_write_location(0x5ff8), Location::CartridgeRegister(0x5ff8));
+        cartridge.write_register(0x5ff8, 1);
+        assert_eq!(cartridge.read_prg_rom(0x8000), 0x42);
+    }
+}
@@ -1,29 +1,141 @@
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::time::Instant;
 
 use crate::bus::Bus;
 use crate::utils::bits;
-use crate::bus::ppu_data::SpriteSize;
-use std::intrinsics::assume;
 use crate::bus::ppu_data::VRamAddr;
 
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 pub const SCREEN_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
 
+// Both pattern tables side by side. Each has 16x16 tiles.
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+pub const PATTERN_TABLES_SIZE: usize = PATTERN_TABLES_WIDTH * PATTERN_TABLES_HEIGHT;
+
+// All 64 sprites in a 8x8 grid.
+pub const SPRITE_PREVIEW_WIDTH: usize = 64;
+pub const SPRITE_PREVIEW_HEIGHT: usize = 64;
+pub const SPRITE_PREVIEW_SIZE: usize = SPRITE_PREVIEW_WIDTH * SPRITE_PREVIEW_HEIGHT;
+
+// Sprites in OAM take four bytes. Only eight fit in a scanline.
+const OAM_SPRITE_SIZE: usize = 4;
+const OAM_SPRITES: usize = 64;
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// Room for every sprite, for when the limit is off. The hardware has only eight.
+const SECONDARY_OAM_CAPACITY: usize = OAM_SPRITES * OAM_SPRITE_SIZE;
+
+// All four nametables in a 2x2 grid.
+pub const NAMETABLES_WIDTH: usize = 2 * SCREEN_WIDTH;
+pub const NAMETABLES_HEIGHT: usize = 2 * SCREEN_HEIGHT;
+pub const NAMETABLES_SIZE: usize = NAMETABLES_WIDTH * NAMETABLES_HEIGHT;
+
+// Television standard the console was built for.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    // Scanlines in a frame, counting the pre-render one.
+    pub fn scanlines(self) -> i32 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal => 312,
+        }
+    }
+
+    // Last scanline of vblank.
+    pub fn last_scanline(self) -> i32 { self.scanlines() - 2 }
+
+    // Dots after power or reset until the PPU accepts writes to its registers. About 29658 CPU cycles on NTSC.
+    pub fn warmup_dots(self) -> u32 {
+        match self {
+            Region::Ntsc => 88_974,
+            Region::Pal => 106_022,
+        }
+    }
+
+    // NTSC runs one CPU cycle every three dots. PAL runs five every sixteen.
+    pub fn is_cpu_dot(self, clock: u32) -> bool {
+        match self {
+            Region::Ntsc => clock.is_multiple_of(3),
+            Region::Pal => (clock % 16) * 5 % 16 < 5,
+        }
+    }
+}
+
+// Rendering options that trade accuracy for looks.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct PpuConfig {
+    // Draw at most eight sprites per scanline like the hardware.
+    // Turning it off removes flicker. Sprite overflow is still reported as usual.
+    pub sprite_limit: bool,
+}
+
+impl Default for PpuConfig {
+    fn default() -> Self { Self { sprite_limit: true } }
+}
+
+// Loopy registers in a readable form.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct ScrollInfo {
+    pub coarse_x: u8,
+    pub coarse_y: u8,
+    pub fine_x: u8,
+    pub fine_y: u8,
+
+    // 0 to 3. Top left, top right, bottom left and bottom right.
+    pub nametable: u8,
+}
+
+impl ScrollInfo {
+    pub fn new(addr: u16, fine_x: u8) -> Self {
+        let addr = VRamAddr::new(addr);
+        Self {
+            coarse_x: addr.coarse_x as u8,
+            coarse_y: addr.coarse_y as u8,
+            fine_x: fine_x & 0b111,
+            fine_y: addr.fine_y as u8,
+            nametable: u8::from(addr.horizontal_nametable) | (u8::from(addr.vertical_nametable) << 1),
+        }
+    }
+
+    // Position in pixels over the four nametables.
+    pub fn x(&self) -> u16 {
+        u16::from(self.nametable & 1) * SCREEN_WIDTH as u16 + u16::from(self.coarse_x) * 8 + u16::from(self.fine_x)
+    }
+
+    pub fn y(&self) -> u16 {
+        u16::from(self.nametable >> 1) * SCREEN_HEIGHT as u16 + u16::from(self.coarse_y) * 8 + u16::from(self.fine_y)
+    }
+}
+
 #[derive(Copy, Clone)]
 struct RenderInfo {
     background: [u8; 8],
     attribute: u16,
 }
 
+// Callback run after each visible scanline.
+pub type ScanlineHook = Box<dyn FnMut(i32, &Ppu)>;
+
 pub struct Ppu {
     pub clock: u32,
 
     // Address Bus
     pub bus: Rc<RefCell<Bus>>,
 
+    pub region: Region,
+    pub config: PpuConfig,
+
+    // Clock when the last power or reset happened, while the PPU is still warming up.
+    warmup_start: Option<u32>,
+
     // Rendering information
     pub frame: u32,
     pub scanline: i32,
@@ -36,12 +148,15 @@ pub struct Ppu {
     low_background: u8,
     high_background: u8,
 
-    // Fps calculation
-    frame_start: Instant,
-    pub fps: f64,
+    // Sprites of the next scanline.
+    secondary_oam: [u8; SECONDARY_OAM_CAPACITY],
+    sprite_count: usize,
 
     // Screen result
     pub screen: [u8; SCREEN_SIZE],
+
+    // Called with the visible scanline just finished. Used to debug raster effects.
+    pub on_scanline: Option<ScanlineHook>,
 }
 
 impl Ppu {
@@ -55,6 +170,11 @@ impl Ppu {
             clock: 0,
             bus,
 
+            region: Region::Ntsc,
+            config: PpuConfig::default(),
+
+            warmup_start: None,
+
             frame: 1,
             scanline: 0,
             dot: 30,
@@ -66,12 +186,25 @@ impl Ppu {
             low_background: 0,
             high_background: 0,
 
-            frame_start: Instant::now(),
-            fps: 0_f64,
+            secondary_oam: [0xff; SECONDARY_OAM_CAPACITY],
+            sprite_count: 0,
+
             screen: [0; SCREEN_SIZE],
+
+            on_scanline: None,
         }
     }
 
+    // Ignore writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR for a while, like after power or reset.
+    pub fn start_warmup(&mut self) {
+        self.warmup_start = Some(self.clock);
+        self.bus.borrow_mut().ppu.warming_up = true;
+    }
+
+    pub fn set_on_scanline(&mut self, callback: impl FnMut(i32, &Ppu) + 'static) {
+        self.on_scanline = Some(Box::new(callback))
+    }
+
     // Matrix indexes
     fn index(base: usize, x: usize, y: usize, width: usize) -> usize { base + x + y * width }
     fn screen_index(x: usize, y: usize) -> usize { Self::index(0, x, y, SCREEN_WIDTH) }
@@ -85,135 +218,247 @@ impl Ppu {
         Self::put_dot_on_screen(&mut self.screen, x, y, dot)
     }
 
-    // Render all sprites from OAM.
-    fn render_sprites(&mut self) {
-        const CHUNKS_SIZE: usize = 4;
+    // Hash of the screen using FNV-1a. Stable between runs, so it can be used for golden tests.
+    pub fn frame_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        self.screen.iter().fold(FNV_OFFSET_BASIS, |hash, &pixel| {
+            (hash ^ u64::from(pixel)).wrapping_mul(FNV_PRIME)
+        })
+    }
 
+    // Decode every tile on both pattern tables using one of the eight palettes.
+    pub fn render_pattern_tables(&self, palette_index: u8) -> [u8; PATTERN_TABLES_SIZE] {
         let bus = self.bus.borrow();
+        let palette = 0x04 * u16::from(palette_index & 0b0111);
 
-        let sprite_table = bus.ppu.sprite_pattern_table;
-        let sprite_size = bus.ppu.sprite_size;
+        let mut res = [0; PATTERN_TABLES_SIZE];
+        for tile in 0..0x200_u16 {
+            let addr = 0x10 * tile;
 
-        for sprite in bus.ppu.oam_chunks(CHUNKS_SIZE) {
-            let y_sprite;
-            let tile;
-            let attr;
-            let x_sprite;
+            // Second table starts after the 16 columns of the first one.
+            let tile = tile as usize;
+            let column = (tile / 0x100) * 16 + tile % 16;
+            let row = (tile % 0x100) / 16;
 
-            unsafe {
-                assume(sprite.len() == CHUNKS_SIZE);
+            for y in 0..8 {
+                let low = bus.peek_ppu(addr + y);
+                let high = bus.peek_ppu(addr + y + 8);
 
-                y_sprite = sprite[0];
-                tile = u16::from(sprite[1]);
-                attr = sprite[2];
-                x_sprite = sprite[3];
+                for (x, &pixel) in bits::interlace(low, high).iter().enumerate() {
+                    let pixel = bus.ppu.peek_palette(palette + u16::from(pixel));
+                    res[Self::index(0, column * 8 + x, row * 8 + y as usize, PATTERN_TABLES_WIDTH)] = pixel;
+                }
             }
+        }
 
-            // Not visible
-            if (0xef_u8..=0xff_u8).contains(&y_sprite) { continue; }
+        res
+    }
 
-            // Pattern.
-            let addr = match sprite_size {
-                SpriteSize::S8 => sprite_table + 0x10 * tile,
-                SpriteSize::S16 => { 0x1000 * (tile & 1) + (0x10 * (tile >> 1)) }
-            };
+    // Render every sprite from OAM in a grid, ignoring their position and flipping.
+    // Only the top tile of 8x16 sprites is shown. Transparent pixels use the backdrop color.
+    pub fn render_sprite_preview(&self) -> [u8; SPRITE_PREVIEW_SIZE] {
+        const CHUNKS_SIZE: usize = 4;
 
-            // Palette
-            let palette = bits::mask(attr, 0b_0011) as usize;
+        let bus = self.bus.borrow();
 
-            // Flip the sprite
-            let flip_x = bits::is_set(attr, 6);
-            let flip_y = bits::is_set(attr, 7);
+        let mut res = [0; SPRITE_PREVIEW_SIZE];
+        for (i, sprite) in bus.ppu.oam_snapshot().chunks(CHUNKS_SIZE).enumerate() {
+            let tile = sprite[1];
+            let palette = 0x10 + 0x04 * u16::from(bits::mask(sprite[2], 0b_0011));
 
+            let column = i % 8;
+            let row = i / 8;
+
+            // Only the top half of 8x16 sprites fits.
             for y in 0..8 {
-                let y = if flip_y { 7 - y } else { y };
+                let addr = bus.ppu.sprite_pattern_addr(tile, y);
+                let low = bus.peek_ppu(addr);
+                let high = bus.peek_ppu(addr + 8);
+
+                for (x, &pixel) in bits::interlace(low, high).iter().enumerate() {
+                    let pixel = if pixel == 0 { 0 } else { palette + u16::from(pixel) };
+                    let index = Self::index(0, column * 8 + x, row * 8 + y as usize, SPRITE_PREVIEW_WIDTH);
+                    res[index] = bus.ppu.peek_palette(pixel);
+                }
+            }
+        }
 
-                for x in 0..8 {
-                    let low = bus.cartridge.read_chr_rom(addr + y);
-                    let high = bus.cartridge.read_chr_rom(addr + y + 8);
-                    let pixel = bits::interlace(low, high)[x as usize];
+        res
+    }
 
-                    // Transparent
-                    if pixel == 0 { continue; }
+    // Find the sprites of the next scanline. Sprites are drawn one scanline below their Y coordinate.
+    // Evaluation on the pre-render scanline finds nothing, so no sprite is ever drawn on scanline 0.
+    fn evaluate_sprites(&mut self) {
+        self.secondary_oam = [0xff; SECONDARY_OAM_CAPACITY];
+        self.sprite_count = 0;
 
-                    let pixel = 0x3f10 + 0x04 * palette + pixel as usize;
-                    let pixel = unsafe { bus.ppu.peek_ram(pixel) };
+        let mut bus = self.bus.borrow_mut();
+        let rendering_enabled = bus.ppu.show_background || bus.ppu.show_sprites;
+        if !rendering_enabled || self.scanline < 0 { return; }
 
-                    let x = if flip_x { 7 - x } else { x };
+        let height = bus.ppu.sprite_height() as i32;
+        let mut overflow = false;
 
-                    let x = (x_sprite).wrapping_add(x) as usize;
-                    let y = (y_sprite).wrapping_add(y as u8) as usize;
+        for sprite in bus.ppu.oam_chunks(OAM_SPRITE_SIZE) {
+            let row = self.scanline - i32::from(sprite[0]);
+            if !(0..height).contains(&row) { continue; }
 
-                    // Out of bound
-                    if x > SCREEN_WIDTH || y > SCREEN_HEIGHT { continue; }
-                    unsafe { *self.screen.get_unchecked_mut(Self::screen_index(x, y)) = pixel }
-                }
+            if self.sprite_count == SPRITES_PER_SCANLINE {
+                overflow = true;
+                if self.config.sprite_limit { break; }
             }
+
+            let index = self.sprite_count * OAM_SPRITE_SIZE;
+            self.secondary_oam[index..index + OAM_SPRITE_SIZE].copy_from_slice(sprite);
+            self.sprite_count += 1;
         }
+
+        if overflow { bus.ppu.sprite_overflow_set() }
     }
 
-    // Render the current dot
-    pub fn render(&mut self) {
+    // Draw the sprites found for this scanline over the background.
+    fn render_sprites(&mut self) {
+        let bus = self.bus.borrow();
+        if !bus.ppu.show_sprites { return; }
+
         let scanline = self.scanline as usize;
-        let dot = self.dot as usize;
+        let height = bus.ppu.sprite_height();
 
-        // Not visible in these cases.
-        if !(0..SCREEN_HEIGHT).contains(&scanline) { return; }
-        if !(0..SCREEN_WIDTH).contains(&dot) { return; }
+        // The first sprites have priority, so they are drawn last.
+        for sprite in self.secondary_oam[..self.sprite_count * OAM_SPRITE_SIZE].chunks(OAM_SPRITE_SIZE).rev() {
+            let y_sprite = sprite[0];
+            let tile = sprite[1];
+            let attr = sprite[2];
+            let x_sprite = sprite[3];
+
+            // Palette
+            let palette = bits::mask(attr, 0b_0011) as u16;
+
+            // Flip the sprite
+            let flip_x = bits::is_set(attr, 6);
+            let flip_y = bits::is_set(attr, 7);
+
+            // Pattern. Flipping 8x16 sprites swaps the tiles too.
+            let y = (scanline - 1 - usize::from(y_sprite)) as u16;
+            let row = if flip_y { height - 1 - y } else { y };
+            let addr = bus.ppu.sprite_pattern_addr(tile, row);
+            let low = bus.peek_ppu(addr);
+            let high = bus.peek_ppu(addr + 8);
+
+            let row = bits::interlace(low, high);
+            for (x, &pixel) in row.iter().enumerate() {
+                // Transparent
+                if pixel == 0 { continue; }
+
+                let pixel = 0x10 + 0x04 * palette + u16::from(pixel);
+                let pixel = bus.ppu.peek_palette(pixel);
+
+                let x = if flip_x { 7 - x } else { x };
+                let x = usize::from(x_sprite) + x;
 
-        let bus = self.bus.borrow_mut();
+                // Out of bound
+                if x >= SCREEN_WIDTH { continue; }
+                unsafe { *self.screen.get_unchecked_mut(Self::screen_index(x, scanline)) = pixel }
+            }
+        }
+    }
 
+    // Background pixel at a position of a nametable.
+    fn background_pixel(bus: &Bus, nametable: usize, x: usize, y: usize) -> u8 {
         // Tables
-        let background_table = bus.ppu.base_nametable_addr;
-        let attribute_table = background_table + 0x03c0;
+        let attribute_table = nametable + 0x03c0;
         let pattern_table = bus.ppu.background_pattern_table;
 
         // Position on the name table.
-        let row = scanline / 8;
-        let tile = dot / 8;
+        let row = y / 8;
+        let tile = x / 8;
 
         // Background
-        let background = Self::index(background_table, tile, row, 32);
-        let pattern = pattern_table + u16::from(unsafe { bus.ppu.peek_ram(background) }) * 0x10;
+        let background = Self::index(nametable, tile, row, 32);
+        let pattern = pattern_table + u16::from(bus.peek_ppu(background as u16)) * 0x10;
 
         // Palette
         let palette = Self::index(attribute_table, tile / 4, row / 4, 8);
-        let palette = unsafe { bus.ppu.peek_ram(palette) };
-
-        // Position inside the pattern.
-        let x = dot % 8;
-        let y = scanline as u16 % 8;
+        let palette = bus.peek_ppu(palette as u16);
 
         // Pixel
-        let low = bus.cartridge.read_chr_rom(pattern + y);
-        let high = bus.cartridge.read_chr_rom(pattern + y + 8);
-        let pixel = bits::interlace(low, high)[x];
+        let fine_y = y as u16 % 8;
+        let low = bus.peek_ppu(pattern + fine_y);
+        let high = bus.peek_ppu(pattern + fine_y + 8);
+        let pixel = bits::interlace(low, high)[x % 8];
 
         let pixel = if pixel == 0 {
             0_u8
         } else {
-            let mask = 2 * (((row as u8 & 1) << 1) | (tile as u8 & 1));
+            // Each attribute byte covers four quadrants of 2x2 tiles.
+            let mask = 2 * ((((row as u8 >> 1) & 1) << 1) | ((tile as u8 >> 1) & 1));
             let color = (palette & (0b0000_0011 << mask)) >> mask;
 
             (color << 2) | pixel
         };
 
-        let pixel = 0x3f00 + pixel as usize;
-        let pixel = unsafe { bus.ppu.peek_ram(pixel) };
+        bus.ppu.peek_palette(u16::from(pixel))
+    }
+
+    // Render the current dot
+    pub fn render(&mut self) {
+        let scanline = self.scanline as usize;
+        let dot = self.dot as usize;
+
+        // Not visible in these cases.
+        if !(0..SCREEN_HEIGHT).contains(&scanline) { return; }
+        if !(0..SCREEN_WIDTH).contains(&dot) { return; }
+
+        let bus = self.bus.borrow();
+        let pixel = Self::background_pixel(&bus, bus.ppu.base_nametable_addr, dot, scanline);
 
         drop(bus);
         unsafe { self.put_dot(dot, scanline, pixel) }
     }
 
+    // Render all four nametables in a 2x2 grid. Mirrored ones show the same content.
+    pub fn render_nametables(&self) -> [u8; NAMETABLES_SIZE] {
+        let bus = self.bus.borrow();
+
+        let mut res = [0; NAMETABLES_SIZE];
+        for table in 0..4 {
+            let nametable = 0x2000 + 0x0400 * table;
+            let left = (table % 2) * SCREEN_WIDTH;
+            let top = (table / 2) * SCREEN_HEIGHT;
+
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    let index = Self::index(0, left + x, top + y, NAMETABLES_WIDTH);
+                    res[index] = Self::background_pixel(&bus, nametable, x, y);
+                }
+            }
+        }
+
+        res
+    }
+
+    // Scroll set by the game, from t. Copied to v when rendering starts.
+    pub fn scroll_info(&self) -> ScrollInfo {
+        let bus = self.bus.borrow();
+        ScrollInfo::new(bus.ppu.t, bus.ppu.x)
+    }
+
+    // Where the PPU is fetching from right now, from v.
+    pub fn position_info(&self) -> ScrollInfo {
+        let bus = self.bus.borrow();
+        ScrollInfo::new(bus.ppu.v, bus.ppu.x)
+    }
+
     // Run one step on the PPU.
     pub fn step(&mut self) {
         let mut bus = self.bus.borrow_mut();
-        let (data, cartridge) = bus.get_ppu_and_cartridge();
-        let v = VRamAddr::new(data.v);
+        let v = VRamAddr::new(bus.ppu.v);
 
         // Various PPU states.
-        let rendering_enabled = data.show_background || data.show_sprites;
-        let show_background = data.show_background;
+        let rendering_enabled = bus.ppu.show_background || bus.ppu.show_sprites;
+        let show_background = bus.ppu.show_background;
 
         let fetch_scanline = self.scanline < 240;
         let fetch_dot = (1..257).contains(&self.dot) || (321..341).contains(&self.dot);
@@ -229,14 +474,22 @@ impl Ppu {
             // Each fetch takes two cycles starting at dot 1.
             match self.dot % 8 {
                 1 => {
-                    let name_table = u16::from(data.fetch_nametable(cartridge.ppu_mirror));
-                    self.name_table = data.background_pattern_table + (name_table << 4);
+                    let name_table = u16::from(bus.peek_ppu(bus.ppu.nametable_addr()));
+                    self.name_table = bus.ppu.background_pattern_table + (name_table << 4);
+                }
+                3 => self.attribute = u16::from(bus.peek_ppu(bus.ppu.attribute_addr())),
+                5 => {
+                    let addr = self.name_table + v.fine_y;
+                    bus.cartridge.clock_a12(addr);
+                    self.low_background = bus.peek_ppu(addr);
+                }
+                7 => {
+                    let addr = self.name_table + v.fine_y + 8;
+                    bus.cartridge.clock_a12(addr);
+                    self.high_background = bus.peek_ppu(addr);
                 }
-                3 => self.attribute = data.fetch_attribute(),
-                5 => self.low_background = cartridge.read_chr_rom(self.name_table + v.fine_y),
-                7 => self.high_background = cartridge.read_chr_rom(self.name_table + v.fine_y + 8),
                 0 => {
-                    data.inc_coarse_x();
+                    bus.ppu.inc_coarse_x();
                     self.render[0] = self.render[1];
                     self.render[1].background = bits::interlace(self.low_background, self.high_background);
                     self.render[1].attribute = self.attribute;
@@ -252,7 +505,7 @@ impl Ppu {
             let render = self.render[0];
 
             // Pixel
-            let pixel = render.background[dot % 8 + data.x as usize];
+            let pixel = render.background[dot % 8 + bus.ppu.x as usize];
             let pixel = if pixel == 0 {
                 0_u8
             } else {
@@ -262,63 +515,811 @@ impl Ppu {
                 (color << 2) | pixel
             };
 
-            //let pixel = 0x3f00 + pixel as usize;
-            let pixel = (pixel as usize % 0x0020) + 0x3f00;
-            let pixel = unsafe { data.peek_ram(pixel) };
+            let pixel = bus.ppu.peek_palette(u16::from(pixel));
 
             unsafe { Self::put_dot_on_screen(&mut self.screen, dot, scanline, pixel) }
         }
 
+        // Light gun sees what's on the screen under the beam.
+        if let Some(zapper) = bus.zapper.as_mut() {
+            let (x, y) = (self.dot as usize, self.scanline as usize);
+            let color = if visible_scanline && visible_dot { self.screen[Self::screen_index(x, y)] } else { 0 };
+            zapper.sense(x, y, color);
+        }
+
+        // OAMADDR is cleared while sprites are fetched.
+        bus.ppu.rendering = rendering_enabled && fetch_scanline;
+        if bus.ppu.rendering && (257..321).contains(&self.dot) { bus.ppu.oam_addr = 0 }
+
         // Vertical position.
+        // Rendering is checked on each dot, so toggling it mid-scanline only affects the updates after it.
         if rendering_enabled {
             if copy_vertical_scanline && copy_vertical_dot {
-                data.copy_vertical_v();
+                bus.ppu.copy_vertical_v();
             }
 
             if fetch_scanline {
                 if self.dot == 256 {
-                    data.inc_fine_y();
+                    bus.ppu.inc_fine_y();
                 } else if self.dot == 257 {
-                    data.copy_horizontal_v()
+                    bus.ppu.copy_horizontal_v()
                 }
             }
         }
 
-        // Increment the clock, dot and scanline.
+        // Status flags are set and cleared on dot 1 of the vblank and pre-render scanlines.
+        if self.dot == 1 {
+            match self.scanline {
+                -1 => bus.ppu.status_clear(),
+
+                // Reading the status on the same clock vblank would be set suppresses both the flag and the NMI.
+                241 if !bus.ppu.status_read => bus.start_vblank(),
+
+                _ => {}
+            }
+        }
+
+        // Reading it a clock or two after it's set returns the flag but the NMI is still suppressed.
+        if self.scanline == 241 && (self.dot == 2 || self.dot == 3) && bus.ppu.status_read {
+            bus.nmi = false;
+        }
+
+        bus.ppu.status_read = false;
         drop(bus);
+
+        // Increment the clock, dot and scanline.
         self.clock += 1;
         self.dot += 1;
 
+        if let Some(start) = self.warmup_start {
+            if self.clock.wrapping_sub(start) >= self.region.warmup_dots() {
+                self.bus.borrow_mut().ppu.warming_up = false;
+                self.warmup_start = None;
+            }
+        }
+
         if self.dot > 340 {
+            if (0..240).contains(&self.scanline) {
+                self.render_sprites();
+
+                if let Some(mut callback) = self.on_scanline.take() {
+                    callback(self.scanline, self);
+                    self.on_scanline = Some(callback);
+                }
+            }
+
+            if self.scanline < 240 { self.evaluate_sprites() }
+
             self.dot = 0;
             self.scanline += 1;
 
             if self.scanline == 0 {
-                // On odd frames this dot is skipped if rendering is enabled.
-                if rendering_enabled && self.frame % 2 == 1 { self.dot += 1 }
+                // On odd frames this dot is skipped if background rendering is enabled. NTSC only.
+                let skip_dot = self.region == Region::Ntsc && show_background && self.frame % 2 == 1;
+                if skip_dot { self.dot += 1 }
             } else if self.scanline == 240 {
                 self.frame += 1;
-                self.render_sprites();
-            } else if self.scanline > 260 {
+                self.bus.borrow_mut().ppu.age_latch();
+            } else if self.scanline > self.region.last_scanline() {
                 trace!("Finished running frame {}.", self.frame);
 
                 self.scanline = -1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bus::controller::Zapper;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::PpuMirror;
+    use crate::console::Console;
+    use crate::cpu::Cpu;
+
+    use super::*;
+
+    fn ppu_at(scanline: i32, dot: u32) -> Ppu {
+        let bus = Rc::new(RefCell::new(Bus::new()));
+        let mut ppu = Ppu::new(bus);
+        ppu.scanline = scanline;
+        ppu.dot = dot;
+        ppu
+    }
+
+    fn set_status(ppu: &Ppu) {
+        let mut bus = ppu.bus.borrow_mut();
+        bus.ppu.vblank_set();
+        bus.ppu.sprite_zero_hit_set();
+        bus.ppu.sprite_overflow_set();
+    }
+
+    fn status(ppu: &Ppu) -> (bool, bool, bool) {
+        let bus = ppu.bus.borrow();
+        (bus.ppu.is_vblank(), bus.ppu.is_sprite_zero_hit(), bus.ppu.is_sprite_overflow())
+    }
+
+    fn run_frames(frames: u32) -> Ppu {
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+        console.run_frames(frames);
+        console.ppu
+    }
+
+    #[test]
+    fn on_scanline() {
+        let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut console = Console::new(cartridge);
+        console.run_frames(1);
+
+        let scanlines = Rc::new(RefCell::new(Vec::new()));
+        let log = scanlines.clone();
+        console.ppu.set_on_scanline(move |scanline, ppu| {
+            assert_eq!(ppu.scanline, scanline);
+            log.borrow_mut().push(scanline);
+        });
+
+        console.run_frames(2);
+        let expected = (0..240).chain(0..240).collect::<Vec<_>>();
+        assert_eq!(*scanlines.borrow(), expected);
+    }
+
+    #[test]
+    fn frame_hash_deterministic() {
+        assert_eq!(run_frames(5).frame_hash(), run_frames(5).frame_hash());
+    }
 
-                // Update fps
-                let now = Instant::now();
-                self.fps = 1_f64 / (now - self.frame_start).as_secs_f64();
-                self.frame_start = now;
+    #[test]
+    fn frame_hash_pixel_change() {
+        let mut ppu = run_frames(5);
+        let hash = ppu.frame_hash();
+
+        ppu.screen[SCREEN_SIZE / 2] ^= 0x01;
+        assert_ne!(hash, ppu.frame_hash());
+    }
+
+    // Cartridge with a single tile, number 0x21, on the second pattern table.
+    fn pattern_ppu() -> Ppu {
+        let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        rom.resize(0x10 + 0x4000, 0);
+
+        let mut chr_rom = vec![0; 0x2000];
+        chr_rom[0x1210] = 0b1000_0001;
+        chr_rom[0x1218] = 0b1100_0000;
+        rom.extend(chr_rom);
+
+        let bus = Bus::with_cartridge(Cartridge::new(&rom).unwrap());
+        Ppu::new(Rc::new(RefCell::new(bus)))
+    }
+
+    #[test]
+    fn pattern_tables() {
+        let ppu = pattern_ppu();
+        {
+            let mut bus = ppu.bus.borrow_mut();
+            for (i, &color) in [0x0f, 0x16, 0x27, 0x30, 0x0f, 0x01, 0x02, 0x03].iter().enumerate() {
+                bus.write_ppu(0x3f00 + i as u16, color);
             }
         }
 
-        // Vblank
-        if self.dot == 4 {
-            let mut bus = self.bus.borrow_mut();
-            match self.scanline {
-                -1 => bus.ppu.vblank_clear(),
-                241 => bus.start_vblank(),
-                _ => {}
+        // Tile 0x21 of the second table is on column 16 + 1 and row 2.
+        let x = (16 + 1) * 8;
+        let y = 2 * 8;
+        let tables = ppu.render_pattern_tables(0);
+        let row = &tables[y * PATTERN_TABLES_WIDTH + x..][..8];
+        assert_eq!(row, [0x30, 0x27, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x16]);
+
+        // Only the first line of the tile has data.
+        assert!(tables[(y + 1) * PATTERN_TABLES_WIDTH + x..][..8].iter().all(|&pixel| pixel == 0x0f));
+
+        // Other palettes.
+        let tables = ppu.render_pattern_tables(1);
+        let row = &tables[y * PATTERN_TABLES_WIDTH + x..][..8];
+        assert_eq!(row, [0x03, 0x02, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x01]);
+    }
+
+    #[test]
+    fn oam_snapshot_after_dma() {
+        let mut mem = vec![0; 0x0300];
+        mem[0x0200..0x0204].copy_from_slice(&[0x10, 0x21, 0b0000_0001, 0x30]);
+        mem[0x02fc..0x0300].copy_from_slice(&[0x40, 0x22, 0b0100_0010, 0x50]);
+
+        let bus = Rc::new(RefCell::new(Bus::with_mem(&mem)));
+        let mut cpu = Cpu::new(bus.clone());
+
+        // STA $4014
+        cpu.reg.s_a(0x02);
+        cpu.run(&[0x8D, 0x14, 0x40]);
+        cpu.run_oam_dma();
+
+        let oam = bus.borrow().ppu.oam_snapshot();
+        assert_eq!(oam[0x00..0x04], [0x10, 0x21, 0b0000_0001, 0x30]);
+        assert_eq!(oam[0xfc..0x100], [0x40, 0x22, 0b0100_0010, 0x50]);
+    }
+
+    #[test]
+    fn sprite_preview() {
+        let ppu = pattern_ppu();
+        {
+            let mut bus = ppu.bus.borrow_mut();
+            bus.write_ppu(0x3f00, 0x0f);
+            bus.write_ppu(0x3f15, 0x16);
+            bus.write_ppu(0x3f16, 0x27);
+            bus.write_ppu(0x3f17, 0x30);
+
+            // Sprites use the second pattern table.
+            bus.write_cpu(0x2000, 0b0000_1000);
+
+            // Tenth sprite with tile 0x21 and the second palette.
+            bus.write_cpu(0x2003, 9 * 4);
+            for &data in &[0x80, 0x21, 0b0000_0001, 0x80] { bus.write_cpu(0x2004, data) }
+        }
+
+        let preview = ppu.render_sprite_preview();
+
+        // Tenth sprite is on column 1, row 1.
+        let (x, y) = (8, 8);
+        let row = &preview[y * SPRITE_PREVIEW_WIDTH + x..][..8];
+        assert_eq!(row, [0x30, 0x27, 0x0f, 0x0f, 0x0f, 0x0f, 0x0f, 0x16]);
+    }
+
+    #[test]
+    fn oam_addr_cleared_while_rendering() {
+        let mut ppu = ppu_at(10, 256);
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0001_1000);
+        ppu.bus.borrow_mut().write_cpu(0x2003, 0x40);
+
+        ppu.step();
+        assert_eq!(ppu.bus.borrow().ppu.oam_addr, 0x40);
+
+        ppu.step();
+        assert_eq!(ppu.bus.borrow().ppu.oam_addr, 0x00);
+    }
+
+    #[test]
+    fn oam_addr_kept_in_vblank() {
+        let mut ppu = ppu_at(245, 256);
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0001_1000);
+        ppu.bus.borrow_mut().write_cpu(0x2003, 0x40);
+
+        for _ in 0..80 { ppu.step() }
+        assert_eq!(ppu.bus.borrow().ppu.oam_addr, 0x40);
+
+        // Writes go to OAM as usual.
+        ppu.bus.borrow_mut().write_cpu(0x2004, 0x12);
+        assert_eq!(ppu.bus.borrow().ppu.oam_snapshot()[0x40], 0x12);
+    }
+
+    #[test]
+    fn zapper_sees_bright_pixel() {
+        let mut ppu = ppu_at(20, 9);
+        ppu.screen[20 * SCREEN_WIDTH + 10] = 0x30;
+        ppu.bus.borrow_mut().zapper = Some(Zapper::new(10, 20));
+
+        ppu.step();
+        assert_eq!(ppu.bus.borrow_mut().read_cpu(0x4017) & 0b0000_1000, 0b0000_1000);
+
+        ppu.step();
+        assert_eq!(ppu.bus.borrow_mut().read_cpu(0x4017) & 0b0000_1000, 0);
+    }
+
+    #[test]
+    fn zapper_ignores_dark_pixel() {
+        let mut ppu = ppu_at(20, 9);
+        ppu.screen[20 * SCREEN_WIDTH + 10] = 0x0d;
+        ppu.bus.borrow_mut().zapper = Some(Zapper::new(10, 20));
+
+        ppu.step();
+        ppu.step();
+        assert_eq!(ppu.bus.borrow_mut().read_cpu(0x4017) & 0b0000_1000, 0b0000_1000);
+    }
+
+    #[test]
+    fn nametables() {
+        let ppu = pattern_ppu();
+        {
+            let mut bus = ppu.bus.borrow_mut();
+            bus.cartridge.set_ppu_mirror(PpuMirror::Vertical);
+            bus.write_ppu(0x3f00, 0x0f);
+            bus.write_ppu(0x3f05, 0x16);
+            bus.write_ppu(0x3f07, 0x27);
+
+            // Background uses the second pattern table.
+            bus.write_cpu(0x2000, 0b0001_0000);
+
+            // Tile 0x21 on column 3, row 2 of the second nametable with the second palette.
+            bus.write_ppu(0x2400 + 2 * 32 + 3, 0x21);
+            bus.write_ppu(0x27c0, 0b0100_0000);
+        }
+
+        let tables = ppu.render_nametables();
+        let pixel = |x: usize, y: usize| tables[y * NAMETABLES_WIDTH + x];
+
+        // Second nametable is on the top right.
+        let (x, y) = (SCREEN_WIDTH + 3 * 8, 2 * 8);
+        assert_eq!(pixel(x, y), 0x27);
+        assert_eq!(pixel(x + 2, y), 0x0f);
+        assert_eq!(pixel(x + 7, y), 0x16);
+        assert_eq!(pixel(x, y + 1), 0x0f);
+
+        // Mirrored on the bottom right. Top left is empty.
+        assert_eq!(pixel(x, SCREEN_HEIGHT + y), 0x27);
+        assert_eq!(pixel(3 * 8, y), 0x0f);
+    }
+
+    #[test]
+    fn render_uses_palette_ram() {
+        let mut ppu = ppu_at(10, 20);
+        let mut palette = [0x0f; 0x20];
+        palette[0x00] = 0x2a;
+        ppu.bus.borrow_mut().ppu.set_palette_ram(palette);
+
+        // The empty cartridge has only transparent tiles, which show the backdrop color.
+        ppu.render();
+        assert_eq!(ppu.screen[10 * SCREEN_WIDTH + 20], 0x2a);
+    }
+
+    #[test]
+    fn vblank_set_at_dot_1() {
+        let mut ppu = ppu_at(240, 340);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (241, 0));
+        assert_eq!(status(&ppu), (false, false, false));
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (241, 1));
+        assert_eq!(status(&ppu), (false, false, false));
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (241, 2));
+        assert_eq!(status(&ppu), (true, false, false));
+    }
+
+    #[test]
+    fn nmi_at_dot_1() {
+        let mut ppu = ppu_at(241, 0);
+        ppu.bus.borrow_mut().write_cpu(0x2000, 0b1000_0000);
+
+        ppu.step();
+        assert!(!ppu.bus.borrow().nmi);
+
+        ppu.step();
+        assert!(ppu.bus.borrow().nmi);
+    }
+
+    #[test]
+    fn status_read_suppresses_vblank() {
+        let mut ppu = ppu_at(241, 1);
+        ppu.bus.borrow_mut().write_cpu(0x2000, 0b1000_0000);
+
+        let data = ppu.bus.borrow_mut().read_cpu(0x2002);
+        assert!(bits::is_clear(data, 7));
+
+        ppu.step();
+        assert_eq!(status(&ppu), (false, false, false));
+        assert!(!ppu.bus.borrow().nmi);
+    }
+
+    #[test]
+    fn status_read_before_vblank() {
+        let mut ppu = ppu_at(241, 0);
+        ppu.bus.borrow_mut().write_cpu(0x2000, 0b1000_0000);
+        ppu.bus.borrow_mut().read_cpu(0x2002);
+
+        ppu.step();
+        ppu.step();
+        assert_eq!(status(&ppu), (true, false, false));
+        assert!(ppu.bus.borrow().nmi);
+    }
+
+    #[test]
+    fn status_read_after_vblank_suppresses_nmi() {
+        for &dot in &[2, 3] {
+            let mut ppu = ppu_at(241, 1);
+            ppu.bus.borrow_mut().write_cpu(0x2000, 0b1000_0000);
+            while ppu.dot < dot { ppu.step() }
+            assert!(ppu.bus.borrow().nmi);
+
+            let data = ppu.bus.borrow_mut().read_cpu(0x2002);
+            assert!(bits::is_set(data, 7), "{}", dot);
+
+            ppu.step();
+            assert_eq!(status(&ppu), (false, false, false));
+            assert!(!ppu.bus.borrow().nmi, "{}", dot);
+        }
+    }
+
+    #[test]
+    fn status_read_late_keeps_nmi() {
+        let mut ppu = ppu_at(241, 1);
+        ppu.bus.borrow_mut().write_cpu(0x2000, 0b1000_0000);
+        while ppu.dot < 4 { ppu.step() }
+
+        let data = ppu.bus.borrow_mut().read_cpu(0x2002);
+        assert!(bits::is_set(data, 7));
+
+        ppu.step();
+        assert!(ppu.bus.borrow().nmi);
+    }
+
+    #[test]
+    fn status_cleared_at_pre_render_dot_1() {
+        let mut ppu = ppu_at(260, 340);
+        set_status(&ppu);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (-1, 0));
+        assert_eq!(status(&ppu), (true, true, true));
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (-1, 1));
+        assert_eq!(status(&ppu), (true, true, true));
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (-1, 2));
+        assert_eq!(status(&ppu), (false, false, false));
+    }
+
+    mod scroll_info {
+        use super::*;
+
+        fn ppu() -> Ppu {
+            let ppu = ppu_at(241, 0);
+            {
+                let mut bus = ppu.bus.borrow_mut();
+
+                // Fine Y 2, nametable 3, coarse Y 26, coarse X 5.
+                bus.write_cpu(0x2006, 0x2f);
+                bus.write_cpu(0x2006, 0x45);
+
+                // Nametable 2, X 125 and Y 94.
+                bus.write_cpu(0x2000, 0b0000_0010);
+                bus.write_cpu(0x2005, 0x7d);
+                bus.write_cpu(0x2005, 0x5e);
             }
+            ppu
+        }
+
+        #[test]
+        fn scroll() {
+            let info = ppu().scroll_info();
+            assert_eq!(info, ScrollInfo { coarse_x: 15, coarse_y: 11, fine_x: 5, fine_y: 6, nametable: 2 });
+            assert_eq!((info.x(), info.y()), (0x7d, 240 + 0x5e));
+        }
+
+        #[test]
+        fn position() {
+            let info = ppu().position_info();
+            assert_eq!(info, ScrollInfo { coarse_x: 5, coarse_y: 26, fine_x: 5, fine_y: 2, nametable: 3 });
+            assert_eq!((info.x(), info.y()), (256 + 45, 240 + 210));
         }
     }
+
+    mod warmup {
+        use super::*;
+
+        fn write(ppu: &Ppu, addr: u16, data: u8) { ppu.bus.borrow_mut().write_cpu(addr, data) }
+
+        fn run(ppu: &mut Ppu, dots: u32) { (0..dots).for_each(|_| ppu.step()) }
+
+        #[test]
+        fn control_ignored() {
+            let mut ppu = ppu_at(0, 0);
+            ppu.start_warmup();
+
+            write(&ppu, 0x2000, 0x80);
+            assert!(!ppu.bus.borrow().ppu.generate_nmi_at_vblank);
+
+            run(&mut ppu, Region::Ntsc.warmup_dots() - 1);
+            write(&ppu, 0x2000, 0x80);
+            assert!(!ppu.bus.borrow().ppu.generate_nmi_at_vblank);
+
+            run(&mut ppu, 1);
+            write(&ppu, 0x2000, 0x80);
+            assert!(ppu.bus.borrow().ppu.generate_nmi_at_vblank);
+        }
+
+        #[test]
+        fn other_registers() {
+            let mut ppu = ppu_at(0, 0);
+            ppu.start_warmup();
+
+            write(&ppu, 0x2001, 0x18);
+            write(&ppu, 0x2005, 0xff);
+            write(&ppu, 0x2006, 0x21);
+            write(&ppu, 0x2006, 0x08);
+
+            {
+                let bus = ppu.bus.borrow();
+                assert!(!bus.ppu.show_background);
+                assert_eq!((bus.ppu.t, bus.ppu.v, bus.ppu.w), (0, 0, false));
+
+                // The bus is still driven.
+                assert_eq!(bus.ppu.latch, 0x08);
+            }
+
+            // Memory is not affected.
+            write(&ppu, 0x2003, 0x10);
+            write(&ppu, 0x2004, 0x42);
+            assert_eq!(ppu.bus.borrow().ppu.oam_snapshot()[0x10], 0x42);
+        }
+
+        #[test]
+        fn pal_is_longer() {
+            let mut ppu = ppu_at(0, 0);
+            ppu.region = Region::Pal;
+            ppu.start_warmup();
+
+            run(&mut ppu, Region::Ntsc.warmup_dots());
+            write(&ppu, 0x2001, 0x08);
+            assert!(!ppu.bus.borrow().ppu.show_background);
+
+            run(&mut ppu, Region::Pal.warmup_dots() - Region::Ntsc.warmup_dots());
+            write(&ppu, 0x2001, 0x08);
+            assert!(ppu.bus.borrow().ppu.show_background);
+        }
+
+        #[test]
+        fn reset_restarts() {
+            let cartridge = Cartridge::from_file("tests/resources/cpu/nestest.nes").unwrap();
+            let mut console = Console::new(cartridge);
+            console.run_frames(2);
+
+            console.bus.borrow_mut().write_cpu(0x2000, 0x80);
+            assert!(console.bus.borrow().ppu.generate_nmi_at_vblank);
+
+            console.reset();
+            console.bus.borrow_mut().write_cpu(0x2000, 0x80);
+            assert!(!console.bus.borrow().ppu.generate_nmi_at_vblank);
+        }
+    }
+
+    mod sprites {
+        use super::*;
+
+        const COLOR: u8 = 0x21;
+
+        // Tile 1 is solid. Every sprite uses it.
+        fn ppu(sprites: &[(u8, u8)], mask: u8) -> Ppu { ppu_with_config(sprites, mask, PpuConfig::default()) }
+
+        fn ppu_with_config(sprites: &[(u8, u8)], mask: u8, config: PpuConfig) -> Ppu {
+            let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+            rom.resize(0x10 + 0x4000, 0);
+            let mut chr = vec![0; 0x2000];
+            chr[0x10..0x18].iter_mut().for_each(|byte| *byte = 0xff);
+            rom.extend_from_slice(&chr);
+
+            let bus = Rc::new(RefCell::new(Bus::with_cartridge(Cartridge::new(&rom).unwrap())));
+            {
+                let mut bus = bus.borrow_mut();
+                bus.ppu.poke_palette(0x11, COLOR);
+                bus.write_cpu(0x2003, 0);
+                for &(x, y) in sprites {
+                    for &data in &[y, 1, 0, x] { bus.write_cpu(0x2004, data) }
+                }
+                for _ in sprites.len() * 4..0x100 { bus.write_cpu(0x2004, 0xff) }
+                bus.write_cpu(0x2001, mask);
+            }
+
+            let mut ppu = Ppu::new(bus);
+            ppu.config = config;
+            ppu.scanline = -1;
+            ppu.dot = 0;
+            while ppu.scanline != 240 { ppu.step() }
+            ppu
+        }
+
+        fn rows(ppu: &Ppu, x: usize) -> Vec<usize> {
+            (0..SCREEN_HEIGHT).filter(|&y| ppu.screen[Ppu::screen_index(x, y)] == COLOR).collect()
+        }
+
+        #[test]
+        fn one_scanline_below() {
+            let ppu = ppu(&[(10, 0), (20, 100)], 0b0001_0000);
+            assert_eq!(rows(&ppu, 10), (1..9).collect::<Vec<_>>());
+            assert_eq!(rows(&ppu, 20), (101..109).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn never_on_scanline_zero() {
+            // Y of $FF puts the first row on scanline 256, off screen. It must not wrap around to the top.
+            let ppu = ppu(&[(10, 0xff)], 0b0001_0000);
+            assert!(rows(&ppu, 10).is_empty());
+        }
+
+        #[test]
+        fn hidden() {
+            let ppu = ppu(&[(10, 0)], 0b0000_1000);
+            assert!(rows(&ppu, 10).is_empty());
+        }
+
+        #[test]
+        fn eight_per_scanline() {
+            let sprites = (0..9).map(|i| (i * 10, 50)).collect::<Vec<_>>();
+            let ppu = ppu(&sprites, 0b0001_0000);
+
+            for i in 0..8 { assert_eq!(rows(&ppu, i * 10).len(), 8, "{}", i) }
+            assert!(rows(&ppu, 80).is_empty());
+            assert_eq!(ppu.bus.borrow_mut().read_cpu(0x2002) & 0b0010_0000, 0b0010_0000);
+        }
+
+        #[test]
+        fn no_overflow() {
+            let sprites = (0..8).map(|i| (i * 10, 50)).collect::<Vec<_>>();
+            let ppu = ppu(&sprites, 0b0001_0000);
+            assert!(!ppu.bus.borrow().ppu.is_sprite_overflow());
+        }
+
+        #[test]
+        fn no_limit() {
+            let sprites = (0..9).map(|i| (i * 10, 50)).collect::<Vec<_>>();
+            let ppu = ppu_with_config(&sprites, 0b0001_0000, PpuConfig { sprite_limit: false });
+
+            for i in 0..9 { assert_eq!(rows(&ppu, i * 10).len(), 8, "{}", i) }
+            assert!(ppu.bus.borrow().ppu.is_sprite_overflow());
+        }
+
+        #[test]
+        fn no_limit_every_sprite() {
+            let sprites = (0..64).map(|i| (i * 4, 50)).collect::<Vec<_>>();
+            let ppu = ppu_with_config(&sprites, 0b0001_0000, PpuConfig { sprite_limit: false });
+            assert_eq!(rows(&ppu, 252), (51..59).collect::<Vec<_>>());
+        }
+    }
+
+    mod mask_toggle {
+        use super::*;
+
+        // Scanline 10 about to increment Y. Horizontal scroll differs between v and t.
+        fn ppu(mask: u8) -> Ppu {
+            let ppu = ppu_at(10, 256);
+            {
+                let mut bus = ppu.bus.borrow_mut();
+                bus.write_cpu(0x2001, mask);
+
+                let mut v = VRamAddr::new(0);
+                v.coarse_x = 3;
+                v.fine_y = 2;
+                bus.ppu.v = v.as_u16();
+
+                let mut t = VRamAddr::new(0);
+                t.coarse_x = 12;
+                t.horizontal_nametable = true;
+                bus.ppu.t = t.as_u16();
+            }
+            ppu
+        }
+
+        fn v(ppu: &Ppu) -> VRamAddr { VRamAddr::new(ppu.bus.borrow().ppu.v) }
+
+        #[test]
+        fn enabled() {
+            let mut ppu = ppu(0b0000_1000);
+            ppu.step();
+            ppu.step();
+
+            assert_eq!(v(&ppu).fine_y, 3);
+            assert_eq!(v(&ppu).coarse_x, 12);
+            assert!(v(&ppu).horizontal_nametable);
+        }
+
+        #[test]
+        fn enabled_after_dot_256() {
+            let mut ppu = ppu(0);
+            ppu.step();
+            ppu.bus.borrow_mut().write_cpu(0x2001, 0b0000_1000);
+            ppu.step();
+
+            // Y wasn't incremented, but X was copied from t.
+            assert_eq!(v(&ppu).fine_y, 2);
+            assert_eq!(v(&ppu).coarse_x, 12);
+            assert!(v(&ppu).horizontal_nametable);
+        }
+
+        #[test]
+        fn disabled_after_dot_256() {
+            let mut ppu = ppu(0b0000_1000);
+            ppu.step();
+            ppu.bus.borrow_mut().write_cpu(0x2001, 0);
+            ppu.step();
+
+            // Y and coarse X were incremented, but X wasn't copied from t.
+            assert_eq!(v(&ppu).fine_y, 3);
+            assert_eq!(v(&ppu).coarse_x, 4);
+            assert!(!v(&ppu).horizontal_nametable);
+        }
+    }
+
+    #[test]
+    fn odd_frame_skip_with_background() {
+        let mut ppu = ppu_at(-1, 340);
+        ppu.frame = 1;
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0000_1000);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (0, 1));
+    }
+
+    #[test]
+    fn odd_frame_no_skip_with_sprites_only() {
+        let mut ppu = ppu_at(-1, 340);
+        ppu.frame = 1;
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0001_0000);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+    }
+
+    #[test]
+    fn even_frame_no_skip() {
+        let mut ppu = ppu_at(-1, 340);
+        ppu.frame = 2;
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0001_1000);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+    }
+
+    // Count the scanlines until the pre-render one comes back.
+    fn scanlines_in_frame(region: Region) -> u32 {
+        let mut ppu = ppu_at(-1, 0);
+        ppu.region = region;
+
+        let mut dots = 0;
+        loop {
+            ppu.step();
+            dots += 1;
+            if (ppu.scanline, ppu.dot) == (-1, 0) { break; }
+        }
+
+        assert_eq!(dots % 341, 0);
+        dots / 341
+    }
+
+    #[test]
+    fn full_frame() {
+        let mut ppu = ppu_at(-1, 0);
+        let frame = ppu.frame;
+
+        for _ in 0..262 * 341 { ppu.step(); }
+        assert_eq!(ppu.frame, frame + 1);
+        assert_eq!((ppu.scanline, ppu.dot), (-1, 0));
+    }
+
+    #[test]
+    fn ntsc_scanlines() {
+        assert_eq!(scanlines_in_frame(Region::Ntsc), 262);
+    }
+
+    #[test]
+    fn pal_scanlines() {
+        assert_eq!(scanlines_in_frame(Region::Pal), 312);
+    }
+
+    #[test]
+    fn pal_odd_frame_no_skip() {
+        let mut ppu = ppu_at(-1, 340);
+        ppu.region = Region::Pal;
+        ppu.frame = 1;
+        ppu.bus.borrow_mut().write_cpu(0x2001, 0b0000_1000);
+
+        ppu.step();
+        assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+    }
+
+    #[test]
+    fn cpu_dot_ratio() {
+        assert_eq!((0..48).filter(|&clock| Region::Ntsc.is_cpu_dot(clock)).count(), 16);
+        assert_eq!((0..48).filter(|&clock| Region::Pal.is_cpu_dot(clock)).count(), 15);
+    }
+
+    #[test]
+    fn status_kept_outside_boundaries() {
+        let mut ppu = ppu_at(100, 0);
+        set_status(&ppu);
+
+        for _ in 0..341 { ppu.step(); }
+        assert_eq!(status(&ppu), (true, true, true));
+    }
 }
@@ -0,0 +1,54 @@
+// Every frame is emulated, but only some are shown. Saves the drawing work on slow hosts.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FrameSkip {
+    // Frames skipped after each one shown.
+    skip: u32,
+    frame: u32,
+}
+
+impl FrameSkip {
+    pub fn new(skip: u32) -> Self { Self { skip, frame: 0 } }
+
+    // Called once per emulated frame. Whether this one should be shown.
+    pub fn present(&mut self) -> bool {
+        let res = self.frame == 0;
+        self.frame = if self.frame >= self.skip { 0 } else { self.frame + 1 };
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::console::Console;
+
+    use super::*;
+
+    #[test]
+    fn no_skip() {
+        let mut frame_skip = FrameSkip::new(0);
+        assert!((0..10).all(|_| frame_skip.present()));
+    }
+
+    #[test]
+    fn pattern() {
+        let mut frame_skip = FrameSkip::new(2);
+        let shown = (0..7).map(|_| frame_skip.present()).collect::<Vec<_>>();
+        assert_eq!(shown, vec![true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn emulates_every_frame() {
+        let mut console = Console::from_file("tests/resources/cpu/nestest.nes").unwrap();
+        let mut frame_skip = FrameSkip::new(2);
+        let start = console.ppu.frame;
+
+        let mut presented = 0;
+        for _ in 0..9 {
+            console.run_frames(1);
+            if frame_skip.present() { presented += 1 }
+        }
+
+        assert_eq!(console.ppu.frame - start, 9);
+        assert_eq!(presented, 3);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::bus::controller::Buttons;
+
+// Sizes in screen pixels, before the window scale.
+const BUTTON_SIZE: f64 = 4.0;
+const SPACING: f64 = 1.0;
+const MARGIN: f64 = 4.0;
+
+pub const PRESSED_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 0.9];
+pub const RELEASED_COLOR: [f32; 4] = [0.2, 0.2, 0.2, 0.6];
+
+// Column and row of each button, laid out like the pad: D-pad, select, start, B and A.
+const LAYOUT: [(Buttons, f64, f64); 8] = [
+    (Buttons::UP, 1.0, 0.0),
+    (Buttons::LEFT, 0.0, 1.0),
+    (Buttons::RIGHT, 2.0, 1.0),
+    (Buttons::DOWN, 1.0, 2.0),
+    (Buttons::SELECT, 4.0, 1.0),
+    (Buttons::START, 5.0, 1.0),
+    (Buttons::B, 7.0, 1.0),
+    (Buttons::A, 8.0, 1.0),
+];
+
+// A button of the overlay. The rectangle is x, y, width and height.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct OverlayButton {
+    pub button: Buttons,
+    pub rect: [f64; 4],
+    pub pressed: bool,
+}
+
+impl OverlayButton {
+    pub fn color(&self) -> [f32; 4] { if self.pressed { PRESSED_COLOR } else { RELEASED_COLOR } }
+}
+
+// Buttons to draw on the top left of the screen for the buttons pressed on a controller.
+pub fn buttons(pressed: Buttons) -> Vec<OverlayButton> {
+    LAYOUT.iter()
+        .map(|&(button, column, row)| {
+            let x = MARGIN + column * (BUTTON_SIZE + SPACING);
+            let y = MARGIN + row * (BUTTON_SIZE + SPACING);
+            OverlayButton { button, rect: [x, y, BUTTON_SIZE, BUTTON_SIZE], pressed: pressed.contains(button) }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ppu;
+
+    use super::*;
+
+    fn pressed(buttons: &[OverlayButton]) -> Vec<Buttons> {
+        buttons.iter().filter(|button| button.pressed).map(|button| button.button).collect()
+    }
+
+    #[test]
+    fn released() {
+        let buttons = buttons(Buttons::empty());
+        assert_eq!(buttons.len(), 8);
+        assert!(pressed(&buttons).is_empty());
+        assert!(buttons.iter().all(|button| button.color() == RELEASED_COLOR));
+    }
+
+    #[test]
+    fn highlights_pressed() {
+        let buttons = buttons(Buttons::A | Buttons::LEFT);
+        assert_eq!(pressed(&buttons), vec![Buttons::LEFT, Buttons::A]);
+
+        let a = buttons.iter().find(|button| button.button == Buttons::A).unwrap();
+        assert_eq!(a.color(), PRESSED_COLOR);
+    }
+
+    #[test]
+    fn fits_on_screen_without_overlapping() {
+        let buttons = buttons(Buttons::empty());
+        for (i, first) in buttons.iter().enumerate() {
+            let [x, y, width, height] = first.rect;
+            assert!(x + width <= ppu::SCREEN_WIDTH as f64 && y + height <= ppu::SCREEN_HEIGHT as f64);
+
+            for second in &buttons[i + 1..] {
+                let [other_x, other_y, _, _] = second.rect;
+                assert!((x - other_x).abs() >= width || (y - other_y).abs() >= height);
+            }
+        }
+    }
+}
@@ -1,27 +1,91 @@
+use std::time::Instant;
+
 use piston_window::*;
 
 use ::image::RgbaImage;
 
 use crate::console::Console;
 use crate::ppu;
+use crate::ui::frame_skip::FrameSkip;
 use crate::ui::palette::Palette;
+use crate::utils::frame_stats::FrameStats;
 
+pub mod frame_skip;
+pub mod input_overlay;
 pub mod palette;
 
-// Run the console on a window
-pub fn run(console: &mut Console, palette: &Palette) {
-    const SCALE: u32 = 3;
-    const SCALE_AS_DOUBLE: f64 = SCALE as f64;
+// Pixel aspect ratio of the NTSC NES.
+const NTSC_PIXEL_ASPECT: f64 = 8_f64 / 7_f64;
+
+// Configuration for the window
+#[derive(Debug, Copy, Clone)]
+pub struct UiConfig {
+    // Integer scale of the screen.
+    pub scale: u32,
+
+    // Stretch the screen horizontally to match the geometry of a NTSC TV.
+    pub aspect_correction: bool,
+
+    // Hide the top and bottom overscan rows, showing 256x224 like most TVs did.
+    pub crop_overscan: bool,
+
+    // Frames emulated but not drawn after each one drawn. Zero draws everything.
+    pub frame_skip: u32,
+
+    // Draw the buttons held on the first controller over the game. Helps checking input and movies.
+    pub show_input_overlay: bool,
+}
+
+impl UiConfig {
+    pub const MIN_SCALE: u32 = 1;
+    pub const MAX_SCALE: u32 = 6;
+
+    pub fn new(scale: u32, aspect_correction: bool) -> Self {
+        Self {
+            scale: scale.clamp(Self::MIN_SCALE, Self::MAX_SCALE),
+            aspect_correction,
+            crop_overscan: false,
+            frame_skip: 0,
+            show_input_overlay: false,
+        }
+    }
+
+    fn horizontal_scale(&self) -> f64 {
+        let scale = f64::from(self.scale);
+        if self.aspect_correction { scale * NTSC_PIXEL_ASPECT } else { scale }
+    }
 
-    let mut window: PistonWindow = WindowSettings::new("Nespera", [SCALE * ppu::SCREEN_WIDTH as u32, SCALE * ppu::SCREEN_HEIGHT as u32])
+    fn vertical_scale(&self) -> f64 { f64::from(self.scale) }
+
+    fn overscan_rows(&self) -> usize { if self.crop_overscan { palette::OVERSCAN_ROWS } else { 0 } }
+
+    // Rows of the screen that are shown
+    fn screen_height(&self) -> usize { ppu::SCREEN_HEIGHT - 2 * self.overscan_rows() }
+
+    // Size of the window in pixels
+    fn window_size(&self) -> [u32; 2] {
+        let width = (self.horizontal_scale() * ppu::SCREEN_WIDTH as f64).round() as u32;
+        let height = (self.vertical_scale() * self.screen_height() as f64).round() as u32;
+        [width, height]
+    }
+}
+
+impl Default for UiConfig {
+    fn default() -> Self { Self::new(3, false) }
+}
+
+// Run the console on a window
+pub fn run(console: &mut Console, palette: &Palette, config: &UiConfig) {
+    let mut window: PistonWindow = WindowSettings::new("Nespera", config.window_size())
         .exit_on_esc(true)
         .build()
         .unwrap();
 
+    // Integer scales are kept sharp. Aspect correction needs filtering to avoid uneven columns.
     let mut settings = TextureSettings::new();
-    settings.set_mag(Filter::Nearest);
+    settings.set_mag(if config.aspect_correction { Filter::Linear } else { Filter::Nearest });
 
-    let mut screen = RgbaImage::new(ppu::SCREEN_WIDTH as u32, ppu::SCREEN_HEIGHT as u32);
+    let mut screen = RgbaImage::new(ppu::SCREEN_WIDTH as u32, config.screen_height() as u32);
 
     let mut canvas: G2dTexture = Texture::from_image(
         &mut window.factory,
@@ -29,19 +93,42 @@ pub fn run(console: &mut Console, palette: &Palette) {
         &settings,
     ).unwrap();
 
+    let horizontal_scale = config.horizontal_scale();
+    let vertical_scale = config.vertical_scale();
+
+    let mut frame_start = Instant::now();
+    let mut stats = FrameStats::default();
+    let mut frame_skip = FrameSkip::new(config.frame_skip);
+
     while let Some(event) = window.next() {
         if event.render_args().is_some() {
             console.run_frames(1);
 
-            window.set_title(format!("Nespera | fps: {:.2}", console.ppu.fps));
+            // The console has no notion of time, so fps is measured here.
+            let now = Instant::now();
+            stats.push(now - frame_start);
+            frame_start = now;
 
-            palette.map(&console.ppu.screen, &mut screen);
+            window.set_title(format!("Nespera | fps: {:.2}", stats.avg_fps().unwrap_or_default()));
+
+            if !frame_skip.present() { continue; }
+
+            let (mask, pressed) = {
+                let bus = console.bus.borrow();
+                (bus.ppu.mask(), bus.controllers[0].pressed())
+            };
+            palette.map_cropped(&console.ppu.screen, mask, &mut screen, config.overscan_rows());
             canvas.update(&mut window.encoder, &screen).unwrap();
 
             window.draw_2d(&event, |context, graphics| {
-                image(&canvas,
-                      context.transform.scale(SCALE_AS_DOUBLE, SCALE_AS_DOUBLE),
-                      graphics);
+                let transform = context.transform.scale(horizontal_scale, vertical_scale);
+                image(&canvas, transform, graphics);
+
+                if config.show_input_overlay {
+                    for button in input_overlay::buttons(pressed) {
+                        rectangle(button.color(), button.rect, transform, graphics);
+                    }
+                }
             });
         }
     }
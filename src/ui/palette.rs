@@ -2,11 +2,25 @@ use std::fs::File;
 use std::io;
 use std::io::Read;
 
+use crate::ppu;
+use crate::utils::bits;
+
 const COLOR_AMOUNT: usize = 0x40;
 const COLOR_DEPTH: usize = 3;
+pub const RGBA_DEPTH: usize = 4;
+
+// Every combination of the three emphasis bits of PPUMASK.
+const EMPHASIS_AMOUNT: usize = 8;
+
+// How much an emphasis bit dims the other two channels.
+const EMPHASIS_ATTENUATION: f32 = 0.816_328;
 
+// Scanlines hidden by the TV on the top and on the bottom of the screen.
+pub const OVERSCAN_ROWS: usize = 8;
+
+// Either the 64 colors, or 512 with the emphasized variants after them.
 pub struct Palette {
-    colors: [image::Rgba<u8>; COLOR_AMOUNT],
+    colors: Vec<image::Rgba<u8>>,
 }
 
 impl Palette {
@@ -16,19 +30,143 @@ impl Palette {
         let mut rgb_colors = [0_u8; COLOR_AMOUNT * COLOR_DEPTH];
         file.read_exact(&mut rgb_colors)?;
 
-        let mut colors = [image::Rgba::<u8>([0, 0, 0, 0]); COLOR_AMOUNT];
-        for (rgb, color) in rgb_colors.chunks(COLOR_DEPTH).zip(colors.iter_mut()) {
-            *color = image::Rgba::<u8>([rgb[0], rgb[1], rgb[2], 0xff])
-        }
+        let colors = rgb_colors.chunks(COLOR_DEPTH)
+            .map(|rgb| image::Rgba::<u8>([rgb[0], rgb[1], rgb[2], 0xff]))
+            .collect();
 
         Result::Ok(Self { colors })
     }
 
+    // Derive the 512 colors from the base 64. Each emphasis bit dims the channels other than its own.
+    pub fn with_emphasis(base: &Palette) -> Self {
+        let mut colors = Vec::with_capacity(COLOR_AMOUNT * EMPHASIS_AMOUNT);
+        for emphasis in 0..EMPHASIS_AMOUNT {
+            for color in &base.colors[..COLOR_AMOUNT] {
+                let mut color = *color;
+                for channel in 0..COLOR_DEPTH {
+                    let dimmed = (0..COLOR_DEPTH).any(|bit| bit != channel && emphasis & (1 << bit) != 0);
+                    if dimmed { color.0[channel] = (f32::from(color.0[channel]) * EMPHASIS_ATTENUATION) as u8 }
+                }
+                colors.push(color);
+            }
+        }
+        Self { colors }
+    }
+
+    pub fn has_emphasis(&self) -> bool { self.colors.len() > COLOR_AMOUNT }
+
+    // Color of a palette index as RGBA
+    pub fn rgba(&self, index: u8) -> [u8; RGBA_DEPTH] {
+        self.colors[index as usize % COLOR_AMOUNT].0
+    }
+
+    // Color of a palette index as seen with PPUMASK.
+    // Greyscale keeps only the column of grey. Emphasis needs the 512 colors, otherwise it's ignored.
+    pub fn rgba_masked(&self, index: u8, mask: u8) -> [u8; RGBA_DEPTH] {
+        self.colors[self.masked_index(index, mask)].0
+    }
+
+    fn masked_index(&self, index: u8, mask: u8) -> usize {
+        let index = if bits::is_set(mask, 0) { index & 0x30 } else { index } as usize % COLOR_AMOUNT;
+        if self.has_emphasis() { (mask as usize >> 5) * COLOR_AMOUNT + index } else { index }
+    }
+
+    // Map a list of pixels into a RGBA buffer
+    pub fn map_rgba(&self, pixels: &[u8], buffer: &mut [u8]) {
+        for (dest, &source) in buffer.chunks_mut(RGBA_DEPTH).zip(pixels) {
+            dest.copy_from_slice(&self.rgba(source));
+        }
+    }
+
     // Map a list of pixels into an image
-    pub fn map(&self, pixels: &[u8], image: &mut image::RgbaImage) {
+    pub fn map(&self, pixels: &[u8], image: &mut image::RgbaImage) { self.map_masked(pixels, 0, image) }
+
+    // Map a list of pixels into an image with the greyscale and emphasis of PPUMASK
+    pub fn map_masked(&self, pixels: &[u8], mask: u8, image: &mut image::RgbaImage) {
         for (dest, &source) in image.pixels_mut().zip(pixels) {
-            debug_assert!((source as usize) < self.colors.len(), "Palette index out of range: {}", source);
-            *dest = unsafe { *self.colors.get_unchecked((source as usize) % self.colors.len()) };
+            debug_assert!((source as usize) < COLOR_AMOUNT, "Palette index out of range: {}", source);
+            *dest = unsafe { *self.colors.get_unchecked(self.masked_index(source, mask)) };
         }
     }
+
+    // Map a list of pixels into an image skipping the top and bottom rows
+    pub fn map_cropped(&self, pixels: &[u8], mask: u8, image: &mut image::RgbaImage, rows: usize) {
+        let skip = (rows * ppu::SCREEN_WIDTH).min(pixels.len() / 2);
+        self.map_masked(&pixels[skip..pixels.len() - skip], mask, image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette { Palette::from_file("tests/resources/palettes/RP2C03.pal").unwrap() }
+
+    #[test]
+    fn map_full() {
+        let palette = palette();
+        let mut pixels = [0_u8; ppu::SCREEN_SIZE];
+        pixels[8 * ppu::SCREEN_WIDTH] = 0x21;
+
+        let mut image = image::RgbaImage::new(ppu::SCREEN_WIDTH as u32, ppu::SCREEN_HEIGHT as u32);
+        palette.map(&pixels, &mut image);
+
+        assert_eq!(image.get_pixel(0, 8).0, palette.rgba(0x21));
+        assert_eq!(image.get_pixel(0, 0).0, palette.rgba(0x00));
+    }
+
+    #[test]
+    fn map_cropped() {
+        let palette = palette();
+        let mut pixels = [0_u8; ppu::SCREEN_SIZE];
+        for (y, row) in pixels.chunks_mut(ppu::SCREEN_WIDTH).enumerate() {
+            row.iter_mut().for_each(|pixel| *pixel = (y % 0x40) as u8);
+        }
+
+        let height = ppu::SCREEN_HEIGHT - 2 * OVERSCAN_ROWS;
+        let mut image = image::RgbaImage::new(ppu::SCREEN_WIDTH as u32, height as u32);
+        palette.map_cropped(&pixels, 0, &mut image, OVERSCAN_ROWS);
+
+        assert_eq!(image.dimensions(), (256, 224));
+        assert_eq!(image.get_pixel(0, 0).0, palette.rgba(8));
+        assert_eq!(image.get_pixel(255, 0).0, palette.rgba(8));
+        assert_eq!(image.get_pixel(0, 223).0, palette.rgba((231 % 0x40) as u8));
+    }
+
+    #[test]
+    fn greyscale() {
+        let palette = palette();
+        assert_eq!(palette.rgba_masked(0x21, 0b0000_0001), palette.rgba(0x20));
+        assert_eq!(palette.rgba_masked(0x21, 0b0000_0000), palette.rgba(0x21));
+    }
+
+    #[test]
+    fn emphasis_ignored_without_table() {
+        let palette = palette();
+        assert!(!palette.has_emphasis());
+        assert_eq!(palette.rgba_masked(0x30, 0b1110_0000), palette.rgba(0x30));
+    }
+
+    #[test]
+    fn red_emphasis() {
+        let base = palette();
+        let palette = Palette::with_emphasis(&base);
+        assert!(palette.has_emphasis());
+
+        // White is dimmed on green and blue only.
+        let [r, g, b, a] = base.rgba(0x30);
+        let [er, eg, eb, ea] = palette.rgba_masked(0x30, 0b0010_0000);
+        assert_eq!((er, ea), (r, a));
+        assert!(eg < g && eb < b);
+
+        assert_eq!(palette.rgba_masked(0x30, 0), base.rgba(0x30));
+    }
+
+    #[test]
+    fn every_emphasis_dims_white() {
+        let palette = Palette::with_emphasis(&palette());
+        let [r, g, b, _] = palette.rgba(0x30);
+        let [er, eg, eb, _] = palette.rgba_masked(0x30, 0b1110_0000);
+        assert!(er < r && eg < g && eb < b);
+    }
 }
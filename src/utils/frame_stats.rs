@@ -0,0 +1,110 @@
+// Frame times over a window of recent frames.
+// The console has no notion of time, so the frontend measures frames and feeds them here.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    frames: VecDeque<Duration>,
+    window: usize,
+    total: Duration,
+}
+
+impl FrameStats {
+    // Keeps the last `window` frames. At least one is kept.
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self { frames: VecDeque::with_capacity(window), window, total: Duration::default() }
+    }
+
+    pub fn push(&mut self, frame: Duration) {
+        if self.frames.len() == self.window {
+            if let Some(oldest) = self.frames.pop_front() { self.total -= oldest; }
+        }
+        self.frames.push_back(frame);
+        self.total += frame;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.total = Duration::default();
+    }
+
+    pub fn len(&self) -> usize { self.frames.len() }
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+
+    pub fn avg_frame_time(&self) -> Option<Duration> {
+        if self.is_empty() { None } else { Some(self.total / self.frames.len() as u32) }
+    }
+
+    pub fn min_frame_time(&self) -> Option<Duration> { self.frames.iter().min().copied() }
+    pub fn max_frame_time(&self) -> Option<Duration> { self.frames.iter().max().copied() }
+
+    // Frames shown per second over the whole window.
+    pub fn avg_fps(&self) -> Option<f64> {
+        if self.is_empty() { None } else { Some(self.frames.len() as f64 / self.total.as_secs_f64()) }
+    }
+
+    // The slowest frame gives the lowest fps and the fastest gives the highest.
+    pub fn min_fps(&self) -> Option<f64> { self.max_frame_time().map(|frame| 1.0 / frame.as_secs_f64()) }
+    pub fn max_fps(&self) -> Option<f64> { self.min_frame_time().map(|frame| 1.0 / frame.as_secs_f64()) }
+}
+
+impl Default for FrameStats {
+    // About a second of NTSC frames.
+    fn default() -> Self { Self::new(60) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration { Duration::from_millis(millis) }
+
+    fn close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn empty() {
+        let stats = FrameStats::new(4);
+        assert_eq!(stats.avg_fps(), None);
+        assert_eq!(stats.min_fps(), None);
+        assert_eq!(stats.max_fps(), None);
+        assert_eq!(stats.avg_frame_time(), None);
+    }
+
+    #[test]
+    fn average() {
+        let mut stats = FrameStats::new(4);
+        for &frame in &[10, 20, 30, 40] { stats.push(ms(frame)); }
+
+        // 4 frames in 100ms.
+        close(stats.avg_fps(), 40.0);
+        close(stats.min_fps(), 25.0);
+        close(stats.max_fps(), 100.0);
+        assert_eq!(stats.avg_frame_time(), Some(ms(25)));
+    }
+
+    #[test]
+    fn window_drops_oldest() {
+        let mut stats = FrameStats::new(2);
+        for &frame in &[100, 10, 40] { stats.push(ms(frame)); }
+
+        assert_eq!(stats.len(), 2);
+        close(stats.avg_fps(), 40.0);
+        assert_eq!(stats.max_frame_time(), Some(ms(40)));
+        assert_eq!(stats.min_frame_time(), Some(ms(10)));
+    }
+
+    #[test]
+    fn clear() {
+        let mut stats = FrameStats::default();
+        stats.push(ms(16));
+        stats.clear();
+        assert!(stats.is_empty());
+        assert_eq!(stats.avg_fps(), None);
+    }
+}
@@ -0,0 +1,48 @@
+// Memory access with the address wrapping around the buffer.
+// The safe_mem feature checks every access so malformed ROMs can't cause undefined behaviour.
+
+#[cfg(not(feature = "safe_mem"))]
+pub fn read(buf: &[u8], addr: usize) -> u8 {
+    unsafe { *buf.get_unchecked(addr % buf.len()) }
+}
+
+#[cfg(not(feature = "safe_mem"))]
+pub fn write(buf: &mut [u8], addr: usize, data: u8) {
+    let len = buf.len();
+    unsafe { *buf.get_unchecked_mut(addr % len) = data }
+}
+
+// Empty buffers read as zero and ignore writes.
+#[cfg(feature = "safe_mem")]
+pub fn read(buf: &[u8], addr: usize) -> u8 {
+    if buf.is_empty() { return 0; }
+    buf.get(addr % buf.len()).copied().unwrap_or(0)
+}
+
+#[cfg(feature = "safe_mem")]
+pub fn write(buf: &mut [u8], addr: usize, data: u8) {
+    if buf.is_empty() { return; }
+    let len = buf.len();
+    if let Some(dest) = buf.get_mut(addr % len) { *dest = data }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let mut buf = [0u8; 4];
+        write(&mut buf, 5, 0x12);
+        assert_eq!(buf, [0, 0x12, 0, 0]);
+        assert_eq!(read(&buf, 9), 0x12);
+    }
+
+    #[cfg(feature = "safe_mem")]
+    #[test]
+    fn empty_reads_zero() {
+        let mut buf: [u8; 0] = [];
+        write(&mut buf, 5, 0x12);
+        assert_eq!(read(&buf, 5), 0);
+    }
+}
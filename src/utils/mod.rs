@@ -1 +1,6 @@
 pub mod bits;
+pub mod frame_stats;
+pub mod hash;
+pub mod mem;
+#[cfg(feature = "serde")]
+pub mod state;
@@ -0,0 +1,127 @@
+// Save state wire format.
+// States derive serde and are encoded with bincode: fields one after the other in declaration order,
+// little endian, with no padding. A version byte goes in front.
+// Changing the order or the fields of any state means bumping the version.
+
+use bincode::{DefaultOptions, ErrorKind, Options};
+use serde::de::{DeserializeOwned, Deserializer, Error};
+use serde::{Serialize, Serializer};
+
+pub const STATE_VERSION: u8 = 2;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum StateError {
+    // Saved by another version of the format.
+    Version(u8),
+
+    // Data ended before every field was read.
+    Truncated,
+
+    // A field has a value the state can't have.
+    Invalid,
+}
+
+fn options() -> impl Options {
+    DefaultOptions::new().with_fixint_encoding().with_little_endian().reject_trailing_bytes()
+}
+
+// Save with the version in front.
+pub fn save<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut res = vec![STATE_VERSION];
+    options().serialize_into(&mut res, value).expect("States are always serializable");
+    res
+}
+
+// Restore a saved state. The value is left untouched if the state can't be read.
+pub fn load<T: DeserializeOwned>(value: &mut T, data: &[u8]) -> Result<(), StateError> {
+    let (&version, data) = data.split_first().ok_or(StateError::Truncated)?;
+    if version != STATE_VERSION { return Err(StateError::Version(version)); }
+
+    *value = options().deserialize(data).map_err(|err| match *err {
+        ErrorKind::Io(_) => StateError::Truncated,
+        _ => StateError::Invalid,
+    })?;
+    Ok(())
+}
+
+// Serde only handles arrays of up to 32 elements. Bigger RAM goes through this as bytes of a fixed size.
+pub mod bytes {
+    use std::convert::TryInto;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(data: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+        let data: Vec<u8> = serde::Deserialize::deserialize(deserializer)?;
+        let len = data.len();
+        data.try_into().map_err(|_| D::Error::invalid_length(len, &"the size of the RAM"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        flag: bool,
+        byte: u8,
+        word: u16,
+        long: u32,
+    }
+
+    fn sample() -> Sample { Sample { flag: true, byte: 0x12, word: 0x3456, long: 0x789a_bcde } }
+
+    #[test]
+    fn layout() {
+        assert_eq!(save(&sample()), vec![STATE_VERSION, 1, 0x12, 0x56, 0x34, 0xde, 0xbc, 0x9a, 0x78]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut res = Sample::default();
+        assert_eq!(load(&mut res, &save(&sample())), Ok(()));
+        assert_eq!(res, sample());
+    }
+
+    #[test]
+    fn errors_keep_value() {
+        let mut data = save(&sample());
+        let mut res = Sample::default();
+
+        assert_eq!(load(&mut res, &[]), Err(StateError::Truncated));
+
+        data[0] = STATE_VERSION + 1;
+        assert_eq!(load(&mut res, &data), Err(StateError::Version(STATE_VERSION + 1)));
+
+        data[0] = STATE_VERSION;
+        assert_eq!(load(&mut res, &data[..data.len() - 1]), Err(StateError::Truncated));
+
+        data[1] = 2;
+        assert_eq!(load(&mut res, &data), Err(StateError::Invalid));
+
+        data[1] = 1;
+        data.push(0);
+        assert_eq!(load(&mut res, &data), Err(StateError::Invalid));
+
+        assert_eq!(res, Sample::default());
+    }
+
+    #[test]
+    fn big_arrays() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Ram(#[serde(with = "bytes")] [u8; 0x100]);
+
+        let mut ram = Ram([0; 0x100]);
+        ram.0[0xff] = 0x42;
+
+        let mut res = Ram([0; 0x100]);
+        load(&mut res, &save(&ram)).unwrap();
+        assert_eq!(res, ram);
+    }
+}
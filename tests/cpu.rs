@@ -1,5 +1,11 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nespera::bus::Bus;
 use nespera::cartridge::Cartridge;
+use nespera::cartridge::flat::FlatMemory;
 use nespera::console::Console;
+use nespera::cpu::Cpu;
 
 fn passed_message(test: &'static str) -> String { format!("\n{}\n\nPassed\n", test) }
 
@@ -70,8 +76,7 @@ mod instr_test {
 
     #[test]
     fn abs_xy() {
-        // This rom does not work properly.
-        run_blargg("instr_test/07-abs_xy.nes", "9C SYA abs,X\n9E SXA abs,Y\n\n07-abs_xy\n\nFailed\n".to_owned());
+        run_blargg("instr_test/07-abs_xy.nes", passed_message("07-abs_xy"));
     }
 
     #[test]
@@ -119,3 +124,36 @@ mod instr_test {
         run_blargg("instr_test/16-special.nes", passed_message("16-special"));
     }
 }
+
+// Klaus Dormann's 6502 functional test. The binary is the prebuilt one from the project, which traps at $3469 on success.
+// It's assembled with the decimal mode tests, but the 2A03 has no decimal mode. They are skipped the same way
+// assembling with `disable_decimal = 1` does: after the last binary test, jump straight to the end of the tests.
+mod functional {
+    use super::*;
+
+    const SUCCESS_TRAP: u16 = 0x3469;
+
+    // `lda #$2a; sta test_case` starting the decimal tests, and `lda #$f0; sta test_case` ending all tests.
+    const DECIMAL_TESTS: usize = 0x3368;
+    const TESTS_DONE: u16 = 0x3464;
+
+    // Takes about 84 million cycles.
+    const MAX_CYCLES: u32 = 100_000_000;
+
+    #[test]
+    fn klaus_dormann() {
+        let mut image = std::fs::read("tests/resources/cpu/6502_functional_test.bin").unwrap();
+        assert_eq!(image[DECIMAL_TESTS..DECIMAL_TESTS + 5], [0xa9, 0x2a, 0x8d, 0x00, 0x02]);
+        assert_eq!(image[TESTS_DONE as usize..TESTS_DONE as usize + 5], [0xa9, 0xf0, 0x8d, 0x00, 0x02]);
+
+        // JMP and two NOPs.
+        let [low, high] = TESTS_DONE.to_le_bytes();
+        image[DECIMAL_TESTS..DECIMAL_TESTS + 5].copy_from_slice(&[0x4c, low, high, 0xea, 0xea]);
+
+        let mut cpu = Cpu::new(Rc::new(RefCell::new(Bus::with_cartridge(FlatMemory::new(&image)))));
+        cpu.reg.set_pc(0x0400);
+
+        let trap = cpu.run_until_trap(MAX_CYCLES);
+        assert_eq!(trap, Some(SUCCESS_TRAP), "Trapped at {:x?}\n{}", trap, cpu);
+    }
+}